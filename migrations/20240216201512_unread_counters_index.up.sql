@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS users_items_unread ON users_items (user_id, channel_id) WHERE read = false;
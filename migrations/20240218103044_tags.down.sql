@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS users_items_tags;
+DROP TABLE IF EXISTS tags;
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS tags
+(
+    id      SERIAL PRIMARY KEY,
+    user_id integer      not null,
+    name    varchar(128) not null,
+    UNIQUE (user_id, name),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS users_items_tags
+(
+    tag_id     integer not null,
+    user_id    integer not null,
+    item_id    integer not null,
+    channel_id integer not null,
+    PRIMARY KEY (tag_id, item_id),
+    FOREIGN KEY (tag_id) REFERENCES tags (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (user_id, item_id, channel_id) REFERENCES users_items (user_id, item_id, channel_id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS users_items_tags_item ON users_items_tags (user_id, item_id);
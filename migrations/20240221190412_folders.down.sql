@@ -0,0 +1,4 @@
+ALTER TABLE channel_users
+    DROP COLUMN IF EXISTS folder_id;
+
+DROP TABLE IF EXISTS folders;
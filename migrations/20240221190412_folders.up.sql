@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS folders
+(
+    id        SERIAL PRIMARY KEY,
+    user_id   integer      not null,
+    name      varchar(512) not null,
+    parent_id integer      null,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (parent_id) REFERENCES folders (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS folders_user ON folders (user_id);
+
+ALTER TABLE channel_users
+    ADD COLUMN IF NOT EXISTS
+        folder_id integer null REFERENCES folders (id) ON DELETE SET NULL ON UPDATE CASCADE;
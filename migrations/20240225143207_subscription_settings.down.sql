@@ -0,0 +1,5 @@
+ALTER TABLE channel_users
+    DROP COLUMN IF EXISTS hide_from_river,
+    DROP COLUMN IF EXISTS default_sort;
+
+DROP TYPE IF EXISTS items_sort;
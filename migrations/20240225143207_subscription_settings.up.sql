@@ -0,0 +1,7 @@
+CREATE TYPE items_sort AS ENUM ('newest', 'oldest');
+
+ALTER TABLE channel_users
+    ADD COLUMN IF NOT EXISTS
+        hide_from_river BOOLEAN    NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS
+        default_sort    items_sort NOT NULL DEFAULT 'newest';
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS imports_failures;
+DROP TABLE IF EXISTS imports;
+DROP TYPE IF EXISTS import_status;
@@ -0,0 +1,24 @@
+CREATE TYPE import_status AS ENUM ('running', 'done', 'failed');
+
+CREATE TABLE IF NOT EXISTS imports
+(
+    id              SERIAL PRIMARY KEY,
+    user_id         integer       not null,
+    status          import_status not null default 'running',
+    total           integer       not null,
+    processed       integer       not null default 0,
+    start_timestamp timestamptz   not null default now(),
+    end_timestamp   timestamptz   null,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS imports_failures
+(
+    id        SERIAL PRIMARY KEY,
+    import_id integer not null,
+    url       text    not null,
+    reason    text    not null,
+    FOREIGN KEY (import_id) REFERENCES imports (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS imports_failures_import ON imports_failures (import_id);
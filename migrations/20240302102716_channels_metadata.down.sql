@@ -0,0 +1,3 @@
+ALTER TABLE channels
+    DROP COLUMN IF EXISTS site_url,
+    DROP COLUMN IF EXISTS description;
@@ -0,0 +1,3 @@
+ALTER TABLE channels
+    ADD COLUMN IF NOT EXISTS site_url    text null,
+    ADD COLUMN IF NOT EXISTS description text null;
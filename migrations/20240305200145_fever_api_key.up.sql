@@ -0,0 +1,3 @@
+-- The Fever API keys are bearer credentials: only their SHA-256 hash is kept
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS fever_api_key_hash varchar(64) null unique;
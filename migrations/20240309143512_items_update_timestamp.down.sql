@@ -0,0 +1,5 @@
+DROP TRIGGER IF EXISTS users_items_updated_timestamp ON users_items;
+DROP FUNCTION IF EXISTS set_users_items_updated_timestamp;
+DROP INDEX IF EXISTS users_items_updated;
+ALTER TABLE users_items
+    DROP COLUMN IF EXISTS updated_timestamp;
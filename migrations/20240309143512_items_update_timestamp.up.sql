@@ -0,0 +1,20 @@
+ALTER TABLE users_items
+    ADD COLUMN IF NOT EXISTS updated_timestamp timestamptz not null default now();
+
+CREATE INDEX IF NOT EXISTS users_items_updated ON users_items (user_id, updated_timestamp);
+
+-- Keep track of the last change of the read and starred status, for the synchronisation of third party clients
+CREATE OR REPLACE FUNCTION set_users_items_updated_timestamp() RETURNS trigger AS
+$$
+BEGIN
+    NEW.updated_timestamp = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER users_items_updated_timestamp
+    BEFORE UPDATE OF read, starred
+    ON users_items
+    FOR EACH ROW
+    WHEN (OLD.read IS DISTINCT FROM NEW.read OR OLD.starred IS DISTINCT FROM NEW.starred)
+EXECUTE FUNCTION set_users_items_updated_timestamp();
@@ -0,0 +1,28 @@
+CREATE TABLE IF NOT EXISTS webhooks
+(
+    id                 SERIAL PRIMARY KEY,
+    user_id            integer     not null,
+    url                text        not null,
+    secret             text        not null,
+    channel_id         integer     null,
+    keyword            text        null,
+    creation_timestamp timestamptz not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (channel_id) REFERENCES channels (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_user ON webhooks (user_id);
+
+CREATE TABLE IF NOT EXISTS webhooks_deliveries
+(
+    id                 SERIAL PRIMARY KEY,
+    webhook_id         integer     not null,
+    attempt            integer     not null,
+    delivery_timestamp timestamptz not null default now(),
+    items_count        integer     not null,
+    status_code        integer     null,
+    error              text        null,
+    FOREIGN KEY (webhook_id) REFERENCES webhooks (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_deliveries_webhook ON webhooks_deliveries (webhook_id, delivery_timestamp);
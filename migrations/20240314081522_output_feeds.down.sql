@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS output_feeds;
+DROP TYPE IF EXISTS output_feed_source;
@@ -0,0 +1,15 @@
+CREATE TYPE output_feed_source AS ENUM ('starred', 'tag', 'folder');
+
+CREATE TABLE IF NOT EXISTS output_feeds
+(
+    id                 SERIAL PRIMARY KEY,
+    user_id            integer            not null,
+    token              text               not null UNIQUE,
+    name               text               not null,
+    source             output_feed_source not null,
+    source_id          integer            null,
+    creation_timestamp timestamptz        not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS output_feeds_user ON output_feeds (user_id);
@@ -0,0 +1,10 @@
+DROP TABLE IF EXISTS filter_rules;
+DROP TYPE IF EXISTS rule_action;
+DROP TYPE IF EXISTS rule_matching;
+DROP TYPE IF EXISTS rule_field;
+
+ALTER TABLE users_items
+    DROP COLUMN IF EXISTS hidden;
+
+ALTER TABLE items
+    DROP COLUMN IF EXISTS author;
@@ -0,0 +1,27 @@
+ALTER TABLE items
+    ADD COLUMN author text null;
+
+ALTER TABLE users_items
+    ADD COLUMN hidden boolean not null default false;
+
+CREATE TYPE rule_field AS ENUM ('title', 'content', 'author', 'url');
+CREATE TYPE rule_matching AS ENUM ('substring', 'regex');
+CREATE TYPE rule_action AS ENUM ('mark_read', 'star', 'tag', 'hide');
+
+CREATE TABLE IF NOT EXISTS filter_rules
+(
+    id                 SERIAL PRIMARY KEY,
+    user_id            integer       not null,
+    channel_id         integer       null,
+    field              rule_field    not null,
+    matching           rule_matching not null,
+    pattern            text          not null,
+    action             rule_action   not null,
+    tag_id             integer       null,
+    creation_timestamp timestamptz   not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (channel_id) REFERENCES channels (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (tag_id) REFERENCES tags (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS filter_rules_user ON filter_rules (user_id);
@@ -0,0 +1,5 @@
+DELETE FROM output_feeds WHERE source = 'saved_search';
+-- Values cannot be removed from an enum type, so 'saved_search' stays in output_feed_source
+
+DROP INDEX IF EXISTS items_search;
+DROP TABLE IF EXISTS saved_searches;
@@ -0,0 +1,22 @@
+CREATE TABLE IF NOT EXISTS saved_searches
+(
+    id                 SERIAL PRIMARY KEY,
+    user_id            integer     not null,
+    name               text        not null,
+    terms              text        null,
+    channel_ids        integer[]   not null default '{}',
+    folder_ids         integer[]   not null default '{}',
+    tag_ids            integer[]   not null default '{}',
+    read               boolean     null,
+    starred            boolean     null,
+    window_days        integer     null,
+    creation_timestamp timestamptz not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS saved_searches_user ON saved_searches (user_id);
+
+CREATE INDEX IF NOT EXISTS items_search ON items
+    USING GIN (to_tsvector('simple', COALESCE(title, '') || ' ' || COALESCE(content, '')));
+
+ALTER TYPE output_feed_source ADD VALUE IF NOT EXISTS 'saved_search';
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS digests;
+DROP TYPE IF EXISTS digest_content;
+DROP TYPE IF EXISTS digest_frequency;
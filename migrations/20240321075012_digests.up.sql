@@ -0,0 +1,18 @@
+CREATE TYPE digest_frequency AS ENUM ('daily', 'weekly');
+CREATE TYPE digest_content AS ENUM ('unread', 'starred', 'folder');
+
+-- At most one digest per user. The address is an encrypted copy of the verified email of the user,
+-- as the users table only keeps its hash.
+CREATE TABLE IF NOT EXISTS digests
+(
+    user_id             integer primary key,
+    frequency           digest_frequency not null,
+    content             digest_content   not null,
+    folder_id           integer          null,
+    encrypted_email     text             not null,
+    unsubscribe_token   text             not null unique,
+    last_sent_timestamp timestamptz      null,
+    creation_timestamp  timestamptz      not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE,
+    FOREIGN KEY (folder_id) REFERENCES folders (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS personal_tokens;
+DROP TYPE IF EXISTS token_scope;
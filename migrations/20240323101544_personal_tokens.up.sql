@@ -0,0 +1,18 @@
+CREATE TYPE token_scope AS ENUM ('read', 'items', 'subscriptions', 'admin');
+
+-- Only a SHA-256 hash of the tokens is stored, the prefix helps the users to recognize them
+CREATE TABLE IF NOT EXISTS personal_tokens
+(
+    id                   SERIAL PRIMARY KEY,
+    user_id              integer       not null,
+    name                 text          not null,
+    token_hash           text          not null unique,
+    prefix               text          not null,
+    scopes               token_scope[] not null,
+    expiration_timestamp timestamptz   null,
+    last_used_timestamp  timestamptz   null,
+    creation_timestamp   timestamptz   not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS personal_tokens_user ON personal_tokens (user_id);
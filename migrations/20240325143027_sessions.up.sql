@@ -0,0 +1,26 @@
+-- A session is opened at each login, and lives as long as its refresh tokens are rotated before expiring.
+CREATE TABLE IF NOT EXISTS sessions
+(
+    id                   SERIAL PRIMARY KEY,
+    user_id              integer     not null,
+    user_agent           text        null,
+    ip_address           text        null,
+    creation_timestamp   timestamptz not null default now(),
+    last_use_timestamp   timestamptz not null default now(),
+    expiration_timestamp timestamptz not null,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user ON sessions (user_id);
+
+-- Every refresh token ever issued for a session, the used ones being kept to detect their reuse
+CREATE TABLE IF NOT EXISTS refresh_tokens
+(
+    token_hash         text primary key,
+    session_id         integer     not null,
+    used               boolean     not null default false,
+    creation_timestamp timestamptz not null default now(),
+    FOREIGN KEY (session_id) REFERENCES sessions (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_session ON refresh_tokens (session_id);
@@ -0,0 +1,3 @@
+-- Bumped to revoke all the access tokens issued to the user
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS token_version integer not null default 0;
@@ -0,0 +1,20 @@
+-- TOTP second factor of a user, only checked once enabled with a first valid code
+CREATE TABLE IF NOT EXISTS two_factor
+(
+    user_id            integer primary key,
+    encrypted_secret   text        not null,
+    enabled            boolean     not null default false,
+    allow_basic_auth   boolean     not null default false,
+    last_used_step     bigint      not null default 0,
+    creation_timestamp timestamptz not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- One-time recovery codes, replacing the TOTP code when the device is lost
+CREATE TABLE IF NOT EXISTS recovery_codes
+(
+    user_id   integer not null,
+    code_hash text    not null,
+    PRIMARY KEY (user_id, code_hash),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
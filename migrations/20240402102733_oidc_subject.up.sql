@@ -0,0 +1,3 @@
+-- Subject of the identity provider account linked to the user, for single sign-on
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS oidc_subject text null unique;
@@ -0,0 +1,4 @@
+ALTER TABLE users
+    DROP COLUMN IF EXISTS last_login_timestamp,
+    DROP COLUMN IF EXISTS creation_timestamp,
+    DROP COLUMN IF EXISTS disabled;
@@ -0,0 +1,5 @@
+-- Accounts can be disabled by an admin without being deleted
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS disabled boolean not null default false,
+    ADD COLUMN IF NOT EXISTS creation_timestamp timestamptz not null default now(),
+    ADD COLUMN IF NOT EXISTS last_login_timestamp timestamptz null;
@@ -0,0 +1,18 @@
+-- Invitations to register when the account creation is not open to everyone.
+-- Only a SHA-256 hash of the codes is stored, the prefix helps to recognize them
+CREATE TABLE IF NOT EXISTS invitations
+(
+    id                   SERIAL PRIMARY KEY,
+    user_id              integer     not null,
+    code_hash            text        not null unique,
+    prefix               text        not null,
+    role                 user_role   not null,
+    channels             text[]      not null default '{}',
+    max_uses             integer     not null,
+    uses                 integer     not null default 0,
+    expiration_timestamp timestamptz not null,
+    creation_timestamp   timestamptz not null default now(),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS invitations_user ON invitations (user_id);
@@ -1,8 +1,9 @@
 use std::future::Future;
 use std::pin::Pin;
 
-use crate::common::Pool as DbPool;
+use crate::common::{hash_secret, Pool as DbPool};
 use actix_web::http::header::HeaderMap;
+use actix_web::http::Method;
 use actix_web::web::Data;
 use actix_web::{dev, FromRequest, HttpRequest};
 use anyhow::Context;
@@ -17,14 +18,22 @@ use redis::AsyncCommands;
 use secrecy::Secret;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
-use tracing::{debug_span, instrument, Instrument};
-
-use crate::common::model::{User, UserRole};
+use tracing::{debug_span, instrument, warn, Instrument};
+use uuid::Uuid;
+
+use crate::common::lockouts::{self, LockoutPolicy};
+use crate::common::model::{TokenScope, User, UserRole};
+use crate::common::password::{verify_dummy_password, verify_password, PasswordCheck};
+use crate::common::personal_tokens::use_token;
+use crate::common::two_factor::{self, refuses_password_auth};
 use crate::common::users::*;
 use crate::errors::AuthenticationError;
 
 use crate::startup::AppState;
 
+/// Number of seconds the token version of a user is cached in redis
+const TOKEN_VERSION_CACHE_TTL: u64 = 30;
+
 static JWT_KEY: Lazy<Hmac<Sha256>> = Lazy::new(|| {
     Hmac::new_from_slice(
         std::env::var("JWT_SECRET")
@@ -34,7 +43,7 @@ static JWT_KEY: Lazy<Hmac<Sha256>> = Lazy::new(|| {
     .unwrap()
 });
 
-/// # Represent an authenticated user, from JWT or HTTP Basic Auth
+/// # Represent an authenticated user, from JWT, HTTP Basic Auth or a personal token
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AuthenticatedUser {
     pub id: i32,
@@ -61,9 +70,17 @@ impl AuthenticatedUser {
 /// # JWT claims
 struct Claims {
     user: AuthenticatedUser,
+    token_version: i32,
     exp: i64,
 }
 
+/// # A user kept in redis once authenticated, along with their token version at that time
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedUser {
+    user: AuthenticatedUser,
+    token_version: i32,
+}
+
 impl FromRequest for AuthenticatedUser {
     type Error = AuthenticationError;
     type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
@@ -76,7 +93,7 @@ impl FromRequest for AuthenticatedUser {
 }
 
 /// # Extract the authenticated user from the request
-async fn extract_authenticated_user(
+pub(crate) async fn extract_authenticated_user(
     req: &HttpRequest,
 ) -> Result<AuthenticatedUser, AuthenticationError> {
     let req = req.clone();
@@ -98,7 +115,10 @@ async fn extract_authenticated_user(
     let req = req.clone();
 
     return match (scheme, value) {
-        (bearer, token) if bearer.to_ascii_lowercase() == "bearer" => verify_jwt(token).await,
+        (bearer, token) if bearer.to_ascii_lowercase() == "bearer" => {
+            let app_state = req.app_data::<Data<AppState>>().unwrap();
+            verify_jwt(token, &app_state.db, &app_state.redis).await
+        }
         (basic, _) if basic.to_ascii_lowercase() == "basic" => {
             let (user, password) = match extract_credentials_from_http_basic(header_value) {
                 Ok(credentials) => credentials,
@@ -112,10 +132,22 @@ async fn extract_authenticated_user(
                 &app_state.db,
                 &app_state.redis,
                 header_value,
+                client_ip(&req).as_deref(),
             )
             .await
         }
 
+        (personal, token) if personal.to_ascii_lowercase() == "token" => {
+            let app_state = req.app_data::<Data<AppState>>().unwrap();
+            verify_personal_token(token, &req, &app_state.db).await
+        }
+
+        (google, token) if google.to_ascii_lowercase() == "googlelogin" => {
+            let token = token.strip_prefix("auth=").unwrap_or(token);
+            let app_state = req.app_data::<Data<AppState>>().unwrap();
+            verify_greader_token(token, &app_state.db, &app_state.redis).await
+        }
+
         (_error, _) => Err(AuthenticationError::UnknownAuthScheme),
     };
 }
@@ -137,78 +169,143 @@ fn extract_value_authentication_header(headers: &HeaderMap) -> Result<&str, Auth
 }
 
 /// # Retrieve a user and check its credentials
-#[instrument(skip(connection, password))]
-async fn check_and_get_user(
+/// The failed attempts are counted per username and per IP address, to slow down and then lock out guessing.
+#[instrument(skip(connection, redis, password))]
+pub async fn check_and_get_user(
     connection: &DbPool,
+    redis: &Pool,
     username: &str,
     password: &Secret<String>,
+    ip: Option<&str>,
 ) -> Result<User, AuthenticationError> {
-    let user = match get_user_by_username(connection, username)
+    if !password_login_allowed() {
+        return Err(AuthenticationError::Forbidden(
+            "Password login is disabled, use single sign-on or a personal token".into(),
+        ));
+    }
+
+    if let Some(retry_after) = lockouts::locked_for(redis, username, ip).await? {
+        return Err(AuthenticationError::Locked(retry_after));
+    }
+
+    let user = get_user_by_username(connection, username)
         .await
-        .context("Database error")?
-    {
-        None => {
-            return Err(AuthenticationError::Unauthorized(
+        .context("Database error")?;
+
+    // An unknown user takes as long as a wrong password, not to tell which usernames exist
+    let check = match &user {
+        Some(user) => verify_password(&user.password, password).await,
+        None => verify_dummy_password(password).await,
+    };
+
+    // A disabled account answers like a wrong password, not to tell when the password was guessed
+    match user {
+        Some(user) if check.is_valid() && !user.disabled => {
+            // With a second factor, the failures are only forgotten once the code is right too
+            if !two_factor::is_enabled(connection, user.id)
+                .await
+                .context("Database error")?
+            {
+                lockouts::record_success(redis, username).await?;
+            }
+            if check == PasswordCheck::Outdated {
+                // The login goes on with the old hash if it cannot be replaced
+                if let Err(e) = rehash_password(connection, &user, password).await {
+                    warn!("Could not rehash the password of user {}: {:?}", user.id, e);
+                }
+            }
+            Ok(user)
+        }
+        _ => {
+            let delay =
+                lockouts::record_failure(redis, &LockoutPolicy::from_env(), username, ip).await?;
+            tokio::time::sleep(delay).await;
+            Err(AuthenticationError::Unauthorized(
                 "Invalid credentials".into(),
             ))
         }
-        Some(u) => u,
-    };
-
-    if !crate::common::password::verify_password(&user.password, password) {
-        return Err(AuthenticationError::Unauthorized(
-            "Invalid credentials".into(),
-        ));
     }
+}
+
+/// # Return the IP address of the client
+/// The forwarding headers are only trusted when the server is configured to be behind a reverse proxy.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let behind_proxy = std::env::var("RSS_AGGREGATOR_BEHIND_PROXY")
+        .map(|x| x.parse().unwrap_or(false))
+        .unwrap_or(false);
 
-    Ok(user)
+    if behind_proxy {
+        req.connection_info().realip_remote_addr().map(String::from)
+    } else {
+        req.peer_addr().map(|addr| addr.ip().to_string())
+    }
 }
 
 /// # Retrieve a user and check its credentials
-/// Check in redis if the user has already pass authentication by looking for its header. If not, try to authenticate
-/// it by matching password
-/// If it's ok, store the header in redis.
+/// Check in redis if the user has already pass authentication by looking for the hash of its header. If not, try to
+/// authenticate it by matching password
+/// If it's ok, store the hash of the header in redis, along with the token version of the user.
 /// This is to avoid decoding the password for each request, because it's a costing operation.
 #[instrument(skip_all)]
 async fn check_and_get_authenticated_user(
     user: &str,
     password: &Secret<String>,
     connection: &DbPool,
-    redis: &Pool,
-    key: &str,
+    redis_pool: &Pool,
+    header: &str,
+    ip: Option<&str>,
 ) -> Result<AuthenticatedUser, AuthenticationError> {
+    let key = format!("basic.{}", hash_secret(header));
+
     // Fist, check that the user is not already in the cache
-    let mut redis = redis.get().await.context("Couldn't get redis connection")?;
+    let mut redis = redis_pool
+        .get()
+        .await
+        .context("Couldn't get redis connection")?;
     let value: Option<String> = redis
-        .get(format!("user:{}:{}", user, key))
+        .get(&key)
         .instrument(debug_span!("getting_http_token_in_redis"))
         .await
         .context("Could not get value")?;
 
     if let Some(value) = value {
         // We have something, cool!
-        let user: AuthenticatedUser =
+        let cached: CachedUser =
             serde_json::from_str(&value).context("Could not deserialize user from redis")?;
-        return Ok(user);
+        match check_token_version(cached.user.id, cached.token_version, connection, redis_pool)
+            .await
+        {
+            Ok(()) => return Ok(cached.user),
+            // Once revoked, the password is checked again, as it may be unchanged
+            Err(AuthenticationError::Unauthorized(_)) => {
+                redis
+                    .del::<_, ()>(&key)
+                    .instrument(debug_span!("removing_http_token_in_redis"))
+                    .await
+                    .context("Could not remove value")?;
+            }
+            Err(e) => return Err(e),
+        }
     }
 
     // Nothing? Authenticate dance!
-    let user = check_and_get_user(connection, user, password).await?;
-    let user = AuthenticatedUser::from_user(&user);
+    let user = check_and_get_user(connection, redis_pool, user, password, ip).await?;
+    check_password_auth_allowed(&user, connection).await?;
+    let cached = CachedUser {
+        user: AuthenticatedUser::from_user(&user),
+        token_version: user.token_version,
+    };
 
     // Store it in redis
-    let serialized_user = serde_json::to_string(&user).context("Could serialize user for redis")?;
+    let serialized_user =
+        serde_json::to_string(&cached).context("Could serialize user for redis")?;
     redis
-        .set_ex::<_, _, ()>(
-            &format!("user:{}:Basic:{}", user.login, key),
-            serialized_user,
-            60 * 15,
-        )
+        .set_ex::<_, _, ()>(&key, serialized_user, 60 * 15)
         .instrument(debug_span!("store_http_token_in_redis"))
         .await
         .context("Could not store user in redis")?;
 
-    Ok(user)
+    Ok(cached.user)
 }
 
 /// # Return user and password from basic auth value
@@ -219,16 +316,28 @@ fn extract_credentials_from_http_basic(
     Ok((credentials.user_id, Secret::new(credentials.password)))
 }
 
-/// # Generate a JWT for the given user password
-#[instrument(skip(connection, password))]
-pub async fn get_jwt_from_login_request(
-    user: &str,
-    password: &Secret<String>,
+/// # Whether the users can log in with their password, or only with single sign-on and personal tokens
+fn password_login_allowed() -> bool {
+    std::env::var("RSS_AGGREGATOR_ALLOW_PASSWORD_LOGIN")
+        .map(|x| x.parse().unwrap_or(true))
+        .unwrap_or(true)
+}
+
+/// # Refuse the password alone for the users protected by a second factor, unless they accept it
+async fn check_password_auth_allowed(
+    user: &User,
     connection: &DbPool,
-) -> Result<String, AuthenticationError> {
-    let user = check_and_get_user(connection, user, password).await?;
+) -> Result<(), AuthenticationError> {
+    if refuses_password_auth(connection, user.id)
+        .await
+        .context("Database error")?
+    {
+        return Err(AuthenticationError::Forbidden(
+            "Two-factor authentication is enabled, use a personal token".into(),
+        ));
+    }
 
-    get_jwt(&user).await
+    Ok(())
 }
 
 /// # Generate a JWT for the given user
@@ -238,18 +347,145 @@ pub async fn get_jwt(user: &User) -> Result<String, AuthenticationError> {
 
     let claim = Claims {
         user: authenticated_user,
+        token_version: user.token_version,
         exp: utc.timestamp(),
     };
 
     Ok(claim.sign_with_key(&(*JWT_KEY))?)
 }
 
-pub fn extract_login_from_refresh_token(token: &str) -> &str {
-    token.split('.').collect::<Vec<&str>>()[1]
+/// # Generate a Google Reader API token for the given user password
+/// The token is stored in redis for 30 days, as GReader clients keep it as long as it is accepted.
+#[instrument(skip(connection, redis, password))]
+pub async fn get_greader_token_from_login_request(
+    user: &str,
+    password: &Secret<String>,
+    ip: Option<&str>,
+    connection: &DbPool,
+    redis: &Pool,
+) -> Result<String, AuthenticationError> {
+    let user = check_and_get_user(connection, redis, user, password, ip).await?;
+    check_password_auth_allowed(&user, connection).await?;
+    let cached = CachedUser {
+        user: AuthenticatedUser::from_user(&user),
+        token_version: user.token_version,
+    };
+    let id = Uuid::new_v4().simple().to_string();
+
+    let serialized_user =
+        serde_json::to_string(&cached).context("Could serialize user for redis")?;
+    redis
+        .get()
+        .await
+        .context("Couldn't get redis connection")?
+        .set_ex::<_, _, ()>(
+            format!("user.{}.greader.{}", user.id, id),
+            serialized_user,
+            60 * 60 * 24 * 30,
+        )
+        .instrument(debug_span!("store_greader_token_in_redis"))
+        .await
+        .context("Could not store greader token in redis")?;
+
+    Ok(format!("{}/{}", user.id, id))
+}
+
+#[instrument(skip_all)]
+async fn verify_greader_token(
+    token: &str,
+    connection: &DbPool,
+    redis: &Pool,
+) -> Result<AuthenticatedUser, AuthenticationError> {
+    let Some((user_id, id)) = token.split_once('/') else {
+        return Err(AuthenticationError::Unauthorized("Invalid token".into()));
+    };
+
+    let value: Option<String> = redis
+        .get()
+        .await
+        .context("Couldn't get redis connection")?
+        .get(format!("user.{}.greader.{}", user_id, id))
+        .instrument(debug_span!("getting_greader_token_in_redis"))
+        .await
+        .context("Could not get value")?;
+
+    let Some(value) = value else {
+        return Err(AuthenticationError::Unauthorized("Invalid token".into()));
+    };
+
+    let cached: CachedUser =
+        serde_json::from_str(&value).context("Could not deserialize user from redis")?;
+    check_token_version(cached.user.id, cached.token_version, connection, redis).await?;
+
+    Ok(cached.user)
+}
+
+/// # Check a personal token, and that its scopes allow the request
+/// The user keeps the admin role only if the token has the admin scope.
+#[instrument(skip_all)]
+async fn verify_personal_token(
+    token: &str,
+    req: &HttpRequest,
+    connection: &DbPool,
+) -> Result<AuthenticatedUser, AuthenticationError> {
+    let required_scope = required_scope(req)?;
+
+    let Some(owner) = use_token(connection, token)
+        .await
+        .context("Database error")?
+    else {
+        return Err(AuthenticationError::Unauthorized("Invalid token".into()));
+    };
+
+    if required_scope != TokenScope::Read && !owner.scopes.contains(&required_scope) {
+        return Err(AuthenticationError::Forbidden(format!(
+            "The token lacks the {:?} scope",
+            required_scope
+        )));
+    }
+
+    Ok(AuthenticatedUser {
+        id: owner.user_id,
+        login: owner.username,
+        role: if owner.scopes.contains(&TokenScope::Admin) {
+            owner.role
+        } else {
+            UserRole::Basic
+        },
+    })
+}
+
+/// # Return the scope a personal token needs for the request
+/// Reading only needs a valid token, but the personal tokens cannot be managed with a personal token.
+fn required_scope(req: &HttpRequest) -> Result<TokenScope, AuthenticationError> {
+    let path = req.path().trim_start_matches("/api/v1/");
+    let resource = path.split('/').next().unwrap_or_default();
+
+    if matches!(resource, "tokens" | "token") {
+        return Err(AuthenticationError::Forbidden(
+            "Personal tokens cannot be managed with a personal token".into(),
+        ));
+    }
+
+    if matches!(*req.method(), Method::GET | Method::HEAD) {
+        return Ok(TokenScope::Read);
+    }
+
+    Ok(match resource {
+        "items" | "item" | "tags" | "tag" => TokenScope::Items,
+        "channel" if path.ends_with("/read") => TokenScope::Items,
+        "channels" | "channel" | "folders" | "folder" | "rules" | "rule" | "streams"
+        | "webhooks" | "webhook" | "output-feeds" | "output-feed" => TokenScope::Subscriptions,
+        _ => TokenScope::Admin,
+    })
 }
 
 #[instrument(skip_all)]
-async fn verify_jwt(token: &str) -> Result<AuthenticatedUser, AuthenticationError> {
+async fn verify_jwt(
+    token: &str,
+    connection: &DbPool,
+    redis: &Pool,
+) -> Result<AuthenticatedUser, AuthenticationError> {
     let claims: Claims = token.verify_with_key(&(*JWT_KEY))?;
 
     let date = if let Single(t) = Utc.timestamp_opt(claims.exp, 0) {
@@ -261,5 +497,119 @@ async fn verify_jwt(token: &str) -> Result<AuthenticatedUser, AuthenticationErro
     if date.lt(&Utc::now()) {
         return Err(AuthenticationError::ExpiredToken);
     }
+
+    check_token_version(claims.user.id, claims.token_version, connection, redis).await?;
     Ok(claims.user)
 }
+
+/// # Check that the tokens issued to the user with this version are not revoked
+/// The current version is cached in redis for a few seconds, to spare a database query for each request. Bumping the
+/// version clears this cache, so the revocation is immediate.
+#[instrument(skip(connection, redis))]
+async fn check_token_version(
+    user_id: i32,
+    token_version: i32,
+    connection: &DbPool,
+    redis: &Pool,
+) -> Result<(), AuthenticationError> {
+    let key = token_version_key(user_id);
+    let mut redis = redis.get().await.context("Couldn't get redis connection")?;
+    let cached: Option<i32> = redis
+        .get(&key)
+        .instrument(debug_span!("getting_token_version_in_redis"))
+        .await
+        .context("Could not get value")?;
+
+    let current = match cached {
+        Some(current) => Some(current),
+        None => {
+            let current = get_token_version(connection, user_id)
+                .await
+                .context("Database error")?;
+            if let Some(current) = current {
+                redis
+                    .set_ex::<_, _, ()>(&key, current, TOKEN_VERSION_CACHE_TTL)
+                    .instrument(debug_span!("store_token_version_in_redis"))
+                    .await
+                    .context("Could not store token version in redis")?;
+            }
+            current
+        }
+    };
+
+    if current != Some(token_version) {
+        return Err(AuthenticationError::Unauthorized("Revoked token".into()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use speculoos::prelude::*;
+
+    use super::*;
+    use crate::common::init_redis_connection;
+    use crate::common::model::LockoutKind;
+
+    #[sqlx::test(
+        fixtures(path = "common/fixtures", scripts("base_fixtures")),
+        migrations = "./migrations"
+    )]
+    async fn test_basic_auth_after_revocation(pool: DbPool) -> anyhow::Result<()> {
+        let redis = init_redis_connection();
+        let password = Secret::new(String::from("root"));
+        let header = format!("Basic {}", STANDARD.encode("root:root"));
+
+        let user =
+            check_and_get_authenticated_user("root", &password, &pool, &redis, &header, None)
+                .await?;
+        assert_that!(user.id).is_equal_to(1);
+
+        // The cached user is revoked, but the password is still right
+        bump_token_version(&pool, &redis, 1).await?;
+        let user =
+            check_and_get_authenticated_user("root", &password, &pool, &redis, &header, None)
+                .await?;
+        assert_that!(user.id).is_equal_to(1);
+
+        let wrong = Secret::new(String::from("wrong"));
+        let header = format!("Basic {}", STANDARD.encode("root:wrong"));
+        let result =
+            check_and_get_authenticated_user("root", &wrong, &pool, &redis, &header, None).await;
+        assert!(matches!(result, Err(AuthenticationError::Unauthorized(_))));
+
+        Ok(())
+    }
+    #[sqlx::test(
+        fixtures(path = "common/fixtures", scripts("base_fixtures")),
+        migrations = "./migrations"
+    )]
+    async fn test_disabled_user_cannot_log_in(pool: DbPool) -> anyhow::Result<()> {
+        let redis = init_redis_connection();
+        sqlx::query(
+            "INSERT INTO users (username, password, role, disabled) SELECT 'disabled_user', password, 'basic', true FROM users WHERE id = 1",
+        )
+        .execute(&pool)
+        .await?;
+        // The failures and the lockout of the previous runs are kept in redis
+        lockouts::clear_lockout(&redis, LockoutKind::Account, "disabled_user").await?;
+
+        // The right and the wrong passwords get the same answer
+        let right = Secret::new(String::from("root"));
+        let result = check_and_get_user(&pool, &redis, "disabled_user", &right, None).await;
+        assert!(
+            matches!(result, Err(AuthenticationError::Unauthorized(ref message)) if message == "Invalid credentials")
+        );
+
+        let wrong = Secret::new(String::from("wrong"));
+        let result = check_and_get_user(&pool, &redis, "disabled_user", &wrong, None).await;
+        assert!(
+            matches!(result, Err(AuthenticationError::Unauthorized(ref message)) if message == "Invalid credentials")
+        );
+
+        Ok(())
+    }
+}
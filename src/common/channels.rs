@@ -3,12 +3,23 @@ use sqlx::Result;
 use tokio::task;
 use tracing::{debug, error, info, instrument};
 
-use crate::common::model::{Channel, ChannelError, PagedResult, UsersChannel};
+use crate::common::errors::ServiceError;
+use crate::common::model::{Channel, ChannelError, ItemsSort, PagedResult, UsersChannel};
+use crate::common::rss;
 use crate::common::rss::check_feed;
 use crate::common::{DbError, Pool};
 use crate::services::fetching;
 use deadpool_redis::Pool as RedisPool;
 
+/// Changes of a subscription's metadata and settings
+#[derive(Debug, Default)]
+pub struct SubscriptionUpdate {
+    pub name: Option<String>,
+    pub notes: Option<String>,
+    pub hide_from_river: Option<bool>,
+    pub default_sort: Option<ItemsSort>,
+}
+
 /// Returns the whole list of errors associated to the given channel id.
 #[instrument(skip(db))]
 pub async fn select_errors_by_chan_id(
@@ -57,6 +68,9 @@ pub async fn select_by_id_and_user_id(
                     "channels"."last_update",
                     "channels"."disabled",
                     "channels"."failure_count",
+                    "channel_users"."folder_id",
+                    "channel_users"."hide_from_river",
+                    "channel_users"."default_sort" AS "default_sort: ItemsSort",
                     COUNT("users_items"."item_id") AS "items_count",
                     SUM(CAST("read" AS integer))   AS "items_read"
         FROM        "channels"
@@ -64,7 +78,7 @@ pub async fn select_by_id_and_user_id(
                     LEFT JOIN "users_items" ON "channels"."id" = "users_items"."channel_id"
         WHERE       "channel_users"."user_id" = $2
         AND         "channel_users"."channel_id" = $1
-        GROUP BY    "channels"."id", "channel_users"."name", "channel_users"."notes"
+        GROUP BY    "channels"."id", "channel_users"."channel_id", "channel_users"."user_id"
         "#,
         channel_id,
         user_id
@@ -87,11 +101,13 @@ pub async fn mark_channel_as_unread(db: &Pool, channel_id: i32, user_id: i32) ->
     mark_channel(db, channel_id, user_id, false).await
 }
 
-///  Select all the channels of a user, along side the total number of items
+///  Select all the channels of a user, along side the total number of items.
+///  If a folder is given, only its channels are returned.
 #[instrument(skip(db))]
 pub async fn select_page_by_user_id(
     db: &Pool,
     user_id: i32,
+    folder_id: Option<i32>,
     page_number: u64,
     page_size: u64,
 ) -> Result<PagedResult<UsersChannel>> {
@@ -106,19 +122,24 @@ pub async fn select_page_by_user_id(
                 "channels"."last_update",
                 "channels"."disabled",
                 "channels"."failure_count",
+                "channel_users"."folder_id",
+                "channel_users"."hide_from_river",
+                "channel_users"."default_sort" AS "default_sort: ItemsSort",
                 COUNT("users_items"."item_id") AS "items_count",
                 SUM(CAST("read" AS integer))   AS "items_read"
         FROM "channels"
         RIGHT JOIN "channel_users" ON "channels"."id" = "channel_users"."channel_id"
         LEFT JOIN "users_items" ON "channels"."id" = "users_items"."channel_id"
         WHERE "channel_users"."user_id" = $1
-        GROUP BY "channels"."id", "channel_users"."registration_timestamp", "channel_users"."name", "channel_users"."notes"
+        AND ($4::integer IS NULL OR "channel_users"."folder_id" = $4)
+        GROUP BY "channels"."id", "channel_users"."channel_id", "channel_users"."user_id"
         ORDER BY "channel_users"."registration_timestamp" DESC
         LIMIT $2 OFFSET $3
         "#,
         user_id,
         page_size as i64,
-        (page_number as i64 - 1) * page_size as i64
+        (page_number as i64 - 1) * page_size as i64,
+        folder_id
     )
     .fetch_all(db)
     .await?;
@@ -139,10 +160,12 @@ pub async fn select_page_by_user_id(
              RIGHT JOIN "channel_users" ON "channels"."id" = "channel_users"."channel_id"
              LEFT JOIN "users_items" ON "channels"."id" = "users_items"."channel_id"
         WHERE "channel_users"."user_id" = $1
+        AND ($2::integer IS NULL OR "channel_users"."folder_id" = $2)
         GROUP BY "channels"."id", "channel_users"."registration_timestamp"
         ORDER BY "channel_users"."registration_timestamp" DESC) AS "sub_query"
         "#,
-        user_id
+        user_id,
+        folder_id
     )
     .fetch_one(db)
     .await?
@@ -165,7 +188,7 @@ pub async fn create_or_link_channel(
     name: Option<String>,
     notes: Option<String>,
     user_id: i32,
-) -> Result<i32> {
+) -> Result<i32, ServiceError> {
     // Retrieve or create the channel
     let (channel_id, channel_name) = match sqlx::query!(
         r#"
@@ -213,6 +236,42 @@ pub async fn create_or_link_channel(
     Ok(channel_id)
 }
 
+/// Update the user's metadata and settings of a subscription.
+/// `None` values are left untouched, an empty `notes` removes them.
+#[instrument(skip(db))]
+pub async fn update_subscription(
+    db: &Pool,
+    channel_id: i32,
+    user_id: i32,
+    update: &SubscriptionUpdate,
+) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE  channel_users
+        SET     name = COALESCE($1, name),
+                notes = CASE WHEN $2::text IS NULL THEN notes ELSE NULLIF($2, '') END,
+                hide_from_river = COALESCE($3, hide_from_river),
+                default_sort = COALESCE($4, default_sort)
+        WHERE   channel_id = $5
+        AND     user_id = $6
+        "#,
+        update.name,
+        update.notes,
+        update.hide_from_river,
+        update.default_sort as Option<ItemsSort>,
+        channel_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
 /// Enable a channel and reset it's failure count
 #[instrument(skip(db))]
 pub async fn enable_channel(db: &Pool, channel_id: i32) -> Result<()> {
@@ -302,6 +361,28 @@ pub async fn update_last_fetched(db: &Pool, channel_id: i32, date: &DateTime<Utc
     Ok(())
 }
 
+/// Update the website URL and the description of a channel
+#[instrument(skip(db))]
+pub async fn update_metadata(
+    db: &Pool,
+    channel_id: i32,
+    site_url: Option<String>,
+    description: Option<String>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE channels SET site_url = $2, description = $3 WHERE id = $1
+        "#,
+        channel_id,
+        site_url,
+        description
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
 /// Retrieve the last update of channel
 #[instrument(skip(db))]
 pub async fn get_last_update(db: &Pool, channel_id: &i32) -> Result<Option<DateTime<Utc>>> {
@@ -352,18 +433,21 @@ async fn create_new_channel(
     db: &Pool,
     redis: &RedisPool,
     channel_url: &str,
-) -> Result<(i32, String)> {
-    let feed = check_feed(channel_url)
-        .await
-        .map_err(|_| DbError::RowNotFound)?; //TODO: Bad error type
+) -> Result<(i32, String), ServiceError> {
+    let feed = check_feed(channel_url).await?;
 
     let channel = sqlx::query_as!(
         Channel,
         r#"
-        INSERT INTO channels (name, url) VALUES ($1, $2) RETURNING *
+        INSERT INTO channels (name, url, site_url, description) VALUES ($1, $2, $3, $4) RETURNING *
         "#,
-        feed.title.map(|x| x.content).unwrap_or(channel_url.into()),
-        channel_url
+        feed.title
+            .as_ref()
+            .map(|x| x.content.clone())
+            .unwrap_or(channel_url.into()),
+        channel_url,
+        rss::site_url(&feed),
+        rss::description(&feed)
     )
     .fetch_one(db)
     .await?;
@@ -439,7 +523,8 @@ pub async fn unsubscribe_channel(db: &Pool, channel_id: i32, user_id: i32) -> Re
 
 #[cfg(test)]
 mod tests {
-    use crate::common::{init_redis_connection, items::get_items_of_user};
+    use crate::common::init_redis_connection;
+    use crate::common::items::{get_items_of_user, ItemFilters};
     use speculoos::prelude::*;
 
     use super::*;
@@ -479,9 +564,18 @@ mod tests {
         .unwrap();
 
         assert_that!(channel_id).is_equal_to(1);
-        let items = get_items_of_user(&pool, Some(1), None, None, 2, 1, 400)
-            .await
-            .unwrap();
+        let items = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                channel_id: Some(1),
+                ..Default::default()
+            },
+            2,
+            1,
+            400,
+        )
+        .await
+        .unwrap();
         asserting!("John doe now has the 60 items of channel 1")
             .that(items.content())
             .has_length(60);
@@ -504,9 +598,18 @@ mod tests {
         .unwrap();
 
         assert_that!(channel_id).is_equal_to(3);
-        let items = get_items_of_user(&pool, Some(3), None, None, 1, 1, 400) // Channel 3 is empty
-            .await
-            .unwrap();
+        let items = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                channel_id: Some(3),
+                ..Default::default()
+            },
+            1,
+            1,
+            400,
+        ) // Channel 3 is empty
+        .await
+        .unwrap();
         asserting!("List of items is empty")
             .that(items.content())
             .is_empty();
@@ -607,4 +710,40 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_update_subscription(pool: Pool) -> Result<()> {
+        let update = SubscriptionUpdate {
+            name: Some("Canard".to_owned()),
+            notes: Some("Some notes".to_owned()),
+            hide_from_river: Some(true),
+            default_sort: Some(ItemsSort::Oldest),
+        };
+        update_subscription(&pool, 1, 1, &update).await?;
+
+        let channel = select_by_id_and_user_id(&pool, 1, 1).await?.unwrap();
+        assert_eq!("Canard", channel.name);
+        assert_that!(channel.notes).is_equal_to(Some("Some notes".to_owned()));
+        assert_that!(channel.hide_from_river).is_true();
+        assert_that!(channel.default_sort).is_equal_to(ItemsSort::Oldest);
+
+        // Only the notes are removed
+        let update = SubscriptionUpdate {
+            notes: Some(String::new()),
+            ..Default::default()
+        };
+        update_subscription(&pool, 1, 1, &update).await?;
+
+        let channel = select_by_id_and_user_id(&pool, 1, 1).await?.unwrap();
+        assert_eq!("Canard", channel.name);
+        assert_that!(channel.notes).is_none();
+        assert_that!(channel.hide_from_river).is_true();
+
+        assert!(matches!(
+            update_subscription(&pool, 1, 2, &update).await,
+            Err(DbError::RowNotFound)
+        ));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,207 @@
+use anyhow::Context;
+use deadpool_redis::Pool as RedisPool;
+use redis::aio::ConnectionLike;
+use redis::{AsyncCommands, RedisResult};
+use sqlx::Result;
+use tracing::{debug_span, error, instrument, Instrument};
+
+use crate::common::model::{ChannelCounters, Counters, FolderCounters};
+use crate::common::saved_searches::count_unread;
+use crate::common::Pool;
+
+/// Return the counters of the given user, from the cache if possible
+#[instrument(skip(db, redis))]
+pub async fn get_counters(db: &Pool, redis: &RedisPool, user_id: i32) -> anyhow::Result<Counters> {
+    let ttl = cache_ttl();
+    if ttl == 0 {
+        return Ok(select_counters(db, user_id).await?);
+    }
+
+    let key = counters_key(user_id);
+    let mut redis = redis.get().await.context("Couldn't get redis connection")?;
+    let cached: Option<String> = redis
+        .get(&key)
+        .instrument(debug_span!("getting_counters_in_redis"))
+        .await?;
+
+    if let Some(cached) = cached {
+        return serde_json::from_str(&cached).context("Could not deserialize counters from redis");
+    }
+
+    let counters = select_counters(db, user_id).await?;
+    redis
+        .set_ex::<_, _, ()>(&key, serde_json::to_string(&counters)?, ttl)
+        .instrument(debug_span!("store_counters_in_redis"))
+        .await?;
+
+    Ok(counters)
+}
+
+/// Compute the unread and starred counters of the given user, and the unread counters of their saved searches
+#[instrument(skip(db))]
+pub async fn select_counters(db: &Pool, user_id: i32) -> Result<Counters> {
+    let channels = sqlx::query_as!(
+        ChannelCounters,
+        r#"
+        SELECT      channel_users.channel_id,
+                    channel_users.folder_id,
+                    COUNT(users_items.item_id) FILTER (WHERE NOT users_items.read) AS "unread!",
+                    COUNT(users_items.item_id) FILTER (WHERE users_items.starred)  AS "starred!"
+        FROM        channel_users
+                    LEFT JOIN users_items ON users_items.channel_id = channel_users.channel_id
+                                         AND users_items.user_id = channel_users.user_id
+        WHERE       channel_users.user_id = $1
+        GROUP BY    channel_users.channel_id, channel_users.folder_id
+        ORDER BY    channel_users.channel_id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    let folders = sqlx::query_as!(
+        FolderCounters,
+        r#"
+        SELECT      folders.id AS folder_id,
+                    COUNT(users_items.item_id) FILTER (WHERE NOT users_items.read) AS "unread!",
+                    COUNT(users_items.item_id) FILTER (WHERE users_items.starred)  AS "starred!"
+        FROM        folders
+                    LEFT JOIN channel_users ON channel_users.user_id = folders.user_id
+                                           AND channel_users.folder_id IN (SELECT sub_folders.id
+                                                                           FROM folders sub_folders
+                                                                           WHERE sub_folders.id = folders.id
+                                                                           OR sub_folders.parent_id = folders.id)
+                    LEFT JOIN users_items ON users_items.channel_id = channel_users.channel_id
+                                         AND users_items.user_id = channel_users.user_id
+        WHERE       folders.user_id = $1
+        GROUP BY    folders.id
+        ORDER BY    folders.id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(Counters {
+        unread: channels.iter().map(|c| c.unread).sum(),
+        starred: channels.iter().map(|c| c.starred).sum(),
+        channels,
+        folders,
+        streams: count_unread(db, user_id).await?,
+    })
+}
+
+/// Drop the cached counters of the given users once a change is saved. The change is done whatever happens here, so
+/// a failure is only logged: the cached counters expire after `COUNTERS_CACHE_TTL` anyway.
+#[instrument(skip(redis))]
+pub async fn forget_counters(redis: &RedisPool, user_ids: &[i32]) {
+    let result = match redis.get().await {
+        Ok(mut connection) => invalidate_counters(&mut connection, user_ids)
+            .await
+            .map_err(anyhow::Error::from),
+        Err(e) => Err(e.into()),
+    };
+
+    if let Err(e) = result {
+        error!(
+            "Could not invalidate the counters of users {:?}: {}",
+            user_ids, e
+        );
+    }
+}
+
+/// Drop the cached counters of the given users
+#[instrument(skip(redis))]
+pub async fn invalidate_counters<C>(redis: &mut C, user_ids: &[i32]) -> RedisResult<()>
+where
+    C: ConnectionLike + Send,
+{
+    if user_ids.is_empty() || cache_ttl() == 0 {
+        return Ok(());
+    }
+
+    let keys = user_ids
+        .iter()
+        .map(|user_id| counters_key(*user_id))
+        .collect::<Vec<String>>();
+
+    redis.del(keys).await
+}
+
+fn counters_key(user_id: i32) -> String {
+    format!("user.{}.counters", user_id)
+}
+
+/// TTL of the cached counters, in seconds. 0 disables the cache.
+fn cache_ttl() -> u64 {
+    std::env::var("COUNTERS_CACHE_TTL")
+        .map(|x| x.parse::<u64>().unwrap_or(300))
+        .unwrap_or(300)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::folders::{create_folder, set_channel_folder};
+
+    use super::*;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_counters_of_user(pool: Pool) -> Result<()> {
+        let counters = select_counters(&pool, 1).await?;
+
+        assert_that!(counters.unread).is_equal_to(16);
+        assert_that!(counters.starred).is_equal_to(3);
+        assert_that!(counters.channels).has_length(2);
+
+        let canard_pc = &counters.channels[0];
+        assert_that!(canard_pc.channel_id).is_equal_to(1);
+        assert_that!(canard_pc.unread).is_equal_to(0);
+        assert_that!(canard_pc.starred).is_equal_to(2);
+
+        let le_monde = &counters.channels[1];
+        assert_that!(le_monde.channel_id).is_equal_to(2);
+        assert_that!(le_monde.unread).is_equal_to(16);
+        assert_that!(le_monde.starred).is_equal_to(1);
+
+        assert_that!(counters.folders).is_empty();
+        assert_that!(counters.streams).is_empty();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_counters_of_folders(pool: Pool) -> anyhow::Result<()> {
+        let root = create_folder(&pool, 1, "Root", None).await?;
+        let games = create_folder(&pool, 1, "Games", Some(root)).await?;
+        let news = create_folder(&pool, 1, "News", Some(root)).await?;
+        let empty = create_folder(&pool, 1, "Empty", None).await?;
+        set_channel_folder(&pool, 1, 1, Some(games)).await?;
+        set_channel_folder(&pool, 1, 2, Some(news)).await?;
+
+        let counters = select_counters(&pool, 1).await?;
+        let folder = |id: i32| counters.folders.iter().find(|f| f.folder_id == id).unwrap();
+
+        assert_that!(counters.folders).has_length(4);
+        assert_that!(folder(root).unread).is_equal_to(16);
+        assert_that!(folder(root).starred).is_equal_to(3);
+        assert_that!(folder(games).unread).is_equal_to(0);
+        assert_that!(folder(games).starred).is_equal_to(2);
+        assert_that!(folder(news).unread).is_equal_to(16);
+        assert_that!(folder(empty).unread).is_equal_to(0);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_counters_of_user_without_channel(pool: Pool) -> Result<()> {
+        let counters = select_counters(&pool, 2).await?;
+
+        assert_that!(counters.unread).is_equal_to(0);
+        assert_that!(counters.starred).is_equal_to(0);
+        assert_that!(counters.channels).is_empty();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+//! Email digests, listing daily or weekly the new items of the users who opted in.
+//! They are sent to an encrypted copy of the verified email of the user, as the users table only keeps its hash.
+
+use chrono::{DateTime, Utc};
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use sqlx::Result;
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+use crate::common::email::send_email;
+use crate::common::encryption::{decrypt, encrypt};
+use crate::common::errors::DigestError;
+use crate::common::items::{get_items_of_user, ItemFilters};
+use crate::common::model::{Digest, DigestContent, DigestFrequency};
+use crate::common::users::has_verified_email;
+use crate::common::{public_url, DbError, Pool};
+
+/// Number of items listed in a digest
+const DIGEST_SIZE: u64 = 50;
+
+/// Definition of a digest, to enable or change it
+#[derive(Debug)]
+pub struct DigestDefinition {
+    pub frequency: DigestFrequency,
+    pub content: DigestContent,
+    pub folder_id: Option<i32>,
+}
+
+/// A digest to send, along with its recipient
+#[derive(Debug)]
+struct DueDigest {
+    user_id: i32,
+    username: String,
+    content: DigestContent,
+    folder_id: Option<i32>,
+    encrypted_email: String,
+    unsubscribe_token: String,
+    /// The items linked to the user after this date are listed
+    since: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct DigestData<'a> {
+    dest_name: &'a str,
+    dest_email: &'a str,
+    items: Vec<DigestItem>,
+    /// Number of new items not listed in the digest
+    more: u64,
+    total: u64,
+    unsubscribe_url: String,
+}
+
+#[derive(Serialize)]
+struct DigestItem {
+    title: String,
+    url: String,
+    channel_name: String,
+}
+
+/// Enable the digest of the user, or change it.
+/// The email must be the verified email of the user, an encrypted copy of it is kept.
+#[instrument(skip(db, email))]
+pub async fn enable_digest(
+    db: &Pool,
+    user_id: i32,
+    email: &Secret<String>,
+    digest: &DigestDefinition,
+) -> Result<(), DigestError> {
+    if !has_verified_email(db, user_id, email).await? {
+        return Err(DigestError::UnverifiedEmail);
+    }
+
+    let folder_id = match digest.content {
+        DigestContent::Folder => {
+            let folder_id = digest.folder_id.ok_or(DigestError::MissingFolder)?;
+            sqlx::query_scalar!(
+                r#"
+                SELECT id FROM folders WHERE id = $1 AND user_id = $2
+                "#,
+                folder_id,
+                user_id
+            )
+            .fetch_optional(db)
+            .await?
+            .ok_or(DigestError::NotFound("folder", folder_id))?;
+            Some(folder_id)
+        }
+        _ => None,
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO digests (user_id, frequency, content, folder_id, encrypted_email, unsubscribe_token)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (user_id) DO UPDATE SET frequency = EXCLUDED.frequency,
+                                            content = EXCLUDED.content,
+                                            folder_id = EXCLUDED.folder_id,
+                                            encrypted_email = EXCLUDED.encrypted_email
+        "#,
+        user_id,
+        digest.frequency as DigestFrequency,
+        digest.content as DigestContent,
+        folder_id,
+        encrypt(email)?,
+        Uuid::new_v4().simple().to_string()
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Return the digest preferences of the user, if they enabled it
+#[instrument(skip(db))]
+pub async fn get_digest(db: &Pool, user_id: i32) -> Result<Option<Digest>> {
+    sqlx::query_as!(
+        Digest,
+        r#"
+        SELECT  frequency AS "frequency: DigestFrequency",
+                content AS "content: DigestContent",
+                folder_id,
+                last_sent_timestamp,
+                creation_timestamp
+        FROM    digests
+        WHERE   user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Disable the digest of the user, forgetting the copy of their email
+#[instrument(skip(db))]
+pub async fn disable_digest(db: &Pool, user_id: i32) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM digests WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Disable the digest matching the unsubscribe token sent with it
+#[instrument(skip(db, token))]
+pub async fn unsubscribe(db: &Pool, token: &str) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM digests WHERE unsubscribe_token = $1
+        "#,
+        token
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Send the digests which are due. A digest without new items is skipped until the next period.
+#[instrument(skip(db))]
+pub async fn send_due_digests(db: &Pool) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let digests = select_due_digests(db, now).await?;
+    info!("{} digests to send", digests.len());
+
+    for digest in digests {
+        if let Err(e) = send_digest(db, &digest).await {
+            error!(
+                "Could not send the digest of user {}: {:?}",
+                digest.user_id, e
+            );
+            continue;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE digests SET last_sent_timestamp = $1 WHERE user_id = $2
+            "#,
+            now,
+            digest.user_id
+        )
+        .execute(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(db))]
+async fn select_due_digests(db: &Pool, now: DateTime<Utc>) -> Result<Vec<DueDigest>> {
+    // An hour of margin, so that the time taken by the previous run does not delay a digest by a whole period
+    sqlx::query_as!(
+        DueDigest,
+        r#"
+        SELECT  digests.user_id,
+                users.username,
+                digests.content AS "content: DigestContent",
+                digests.folder_id,
+                digests.encrypted_email,
+                digests.unsubscribe_token,
+                COALESCE(digests.last_sent_timestamp, digests.creation_timestamp) AS "since!"
+        FROM    digests
+                JOIN users ON users.id = digests.user_id
+        WHERE   users.email_verified = true
+        AND     (digests.last_sent_timestamp IS NULL
+                 OR digests.last_sent_timestamp <= $1::timestamptz - CASE digests.frequency
+                                                            WHEN 'daily' THEN interval '23 hours'
+                                                            ELSE interval '167 hours'
+                                                        END)
+        ORDER BY digests.user_id
+        "#,
+        now
+    )
+    .fetch_all(db)
+    .await
+}
+
+#[instrument(skip(db, digest), fields(user_id = digest.user_id))]
+async fn send_digest(db: &Pool, digest: &DueDigest) -> anyhow::Result<()> {
+    let items =
+        get_items_of_user(db, &item_filters(digest), digest.user_id, 1, DIGEST_SIZE).await?;
+    let total = *items.total_items();
+    if total == 0 {
+        return Ok(());
+    }
+
+    let email = decrypt(&digest.encrypted_email)?;
+    let items = items
+        .into_content()
+        .into_iter()
+        .map(|item| DigestItem {
+            title: item.title.unwrap_or_default(),
+            url: item.url.unwrap_or_default(),
+            channel_name: item.channel_name,
+        })
+        .collect::<Vec<DigestItem>>();
+
+    let data = DigestData {
+        dest_name: &digest.username,
+        dest_email: email.expose_secret(),
+        more: total - items.len() as u64,
+        items,
+        total,
+        unsubscribe_url: format!(
+            "{}/api/v1/digest/unsubscribe/{}",
+            public_url(),
+            digest.unsubscribe_token
+        ),
+    };
+
+    send_email("digest", &data).await
+}
+
+fn item_filters(digest: &DueDigest) -> ItemFilters {
+    let filters = ItemFilters {
+        added_after: Some(digest.since),
+        ..Default::default()
+    };
+
+    match digest.content {
+        DigestContent::Unread => ItemFilters {
+            read: Some(false),
+            exclude_hidden: true,
+            ..filters
+        },
+        DigestContent::Starred => ItemFilters {
+            starred: Some(true),
+            ..filters
+        },
+        DigestContent::Folder => ItemFilters {
+            read: Some(false),
+            folder_id: digest.folder_id,
+            ..filters
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use speculoos::prelude::*;
+
+    use crate::common::encryption::tests::set_test_key;
+    use crate::common::folders::create_folder;
+    use crate::common::users::hash_email;
+
+    use super::*;
+
+    const EMAIL: &str = "root@harss.net";
+
+    async fn verify_email(pool: &Pool, user_id: i32) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"UPDATE users SET email = $1, email_verified = true WHERE id = $2"#,
+            hash_email(&Some(Secret::new(EMAIL.to_owned()))),
+            user_id
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    fn definition(frequency: DigestFrequency, content: DigestContent) -> DigestDefinition {
+        DigestDefinition {
+            frequency,
+            content,
+            folder_id: None,
+        }
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_digest_preferences(pool: Pool) -> anyhow::Result<()> {
+        set_test_key();
+        let email = Secret::new(EMAIL.to_owned());
+        let daily = definition(DigestFrequency::Daily, DigestContent::Unread);
+
+        let result = enable_digest(&pool, 1, &email, &daily).await;
+        assert!(matches!(result, Err(DigestError::UnverifiedEmail)));
+
+        verify_email(&pool, 1).await?;
+        let other = Secret::new(String::from("other@harss.net"));
+        let result = enable_digest(&pool, 1, &other, &daily).await;
+        assert!(matches!(result, Err(DigestError::UnverifiedEmail)));
+
+        let folder = definition(DigestFrequency::Weekly, DigestContent::Folder);
+        let result = enable_digest(&pool, 1, &email, &folder).await;
+        assert!(matches!(result, Err(DigestError::MissingFolder)));
+
+        enable_digest(&pool, 1, &email, &daily).await?;
+        let digest = get_digest(&pool, 1).await?.unwrap();
+        assert_that!(digest.frequency).is_equal_to(DigestFrequency::Daily);
+        assert_that!(digest.last_sent_timestamp).is_none();
+
+        let folder_id = create_folder(&pool, 1, "News", None).await?;
+        let folder = DigestDefinition {
+            folder_id: Some(folder_id),
+            ..folder
+        };
+        enable_digest(&pool, 1, &email, &folder).await?;
+        let digest = get_digest(&pool, 1).await?.unwrap();
+        assert_that!(digest.content).is_equal_to(DigestContent::Folder);
+        assert_that!(digest.folder_id).is_equal_to(Some(folder_id));
+
+        // The stored address is encrypted
+        let stored = sqlx::query_scalar!("SELECT encrypted_email FROM digests WHERE user_id = 1")
+            .fetch_one(&pool)
+            .await?;
+        assert_that!(stored.contains("harss")).is_false();
+        assert_that!(decrypt(&stored)?.expose_secret().as_str()).is_equal_to(EMAIL);
+
+        disable_digest(&pool, 1).await?;
+        assert_that!(get_digest(&pool, 1).await?).is_none();
+        assert_that!(disable_digest(&pool, 1).await).is_err();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_due_digests(pool: Pool) -> anyhow::Result<()> {
+        set_test_key();
+        verify_email(&pool, 1).await?;
+        let email = Secret::new(EMAIL.to_owned());
+        enable_digest(
+            &pool,
+            1,
+            &email,
+            &definition(DigestFrequency::Weekly, DigestContent::Unread),
+        )
+        .await?;
+        let now = Utc::now();
+
+        let due = select_due_digests(&pool, now).await?;
+        assert_that!(due).has_length(1);
+
+        let last_week = now - Duration::days(7);
+        let yesterday = now - Duration::days(1);
+        sqlx::query!(
+            "UPDATE digests SET last_sent_timestamp = $1 WHERE user_id = 1",
+            yesterday
+        )
+        .execute(&pool)
+        .await?;
+        assert_that!(select_due_digests(&pool, now).await?).is_empty();
+
+        sqlx::query!(
+            "UPDATE digests SET last_sent_timestamp = $1 WHERE user_id = 1",
+            last_week
+        )
+        .execute(&pool)
+        .await?;
+        let due = select_due_digests(&pool, now).await?;
+        assert_that!(due).has_length(1);
+        assert_that!(due[0].since.timestamp_micros()).is_equal_to(last_week.timestamp_micros());
+
+        // Only the items linked to the user since the last digest are listed
+        sqlx::query!(
+            "UPDATE users_items SET added_timestamp = $1 WHERE user_id = 1",
+            last_week - Duration::days(1)
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query!(
+            "UPDATE users_items SET added_timestamp = $1 WHERE user_id = 1 AND item_id IN (SELECT item_id FROM users_items WHERE user_id = 1 AND read = false LIMIT 3)",
+            yesterday
+        )
+        .execute(&pool)
+        .await?;
+        let items = get_items_of_user(&pool, &item_filters(&due[0]), 1, 1, DIGEST_SIZE).await?;
+        assert_that!(*items.total_items()).is_equal_to(3);
+
+        let weekly_starred = DueDigest {
+            content: DigestContent::Starred,
+            since: now - Duration::days(365 * 100),
+            ..due.into_iter().next().unwrap()
+        };
+        let items =
+            get_items_of_user(&pool, &item_filters(&weekly_starred), 1, 1, DIGEST_SIZE).await?;
+        assert_that!(*items.total_items()).is_equal_to(3);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_unsubscribe(pool: Pool) -> anyhow::Result<()> {
+        set_test_key();
+        verify_email(&pool, 1).await?;
+        let email = Secret::new(EMAIL.to_owned());
+        enable_digest(
+            &pool,
+            1,
+            &email,
+            &definition(DigestFrequency::Daily, DigestContent::Starred),
+        )
+        .await?;
+        let token = select_due_digests(&pool, Utc::now()).await?[0]
+            .unsubscribe_token
+            .clone();
+
+        assert_that!(unsubscribe(&pool, "nope").await).is_err();
+        unsubscribe(&pool, &token).await?;
+        assert_that!(get_digest(&pool, 1).await?).is_none();
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::anyhow;
-use handlebars::{DirectorySourceOptions, Handlebars};
+use handlebars::{handlebars_helper, DirectorySourceOptions, Handlebars, JsonRender};
 use json_value_merge::Merge;
 use once_cell::sync::Lazy;
 use serde::Serialize;
@@ -16,9 +16,18 @@ static HANDLEBARS: Lazy<Handlebars> = Lazy::new(|| {
     handlebars
         .register_templates_directory("templates/", options)
         .unwrap();
+    // The templates are JSON documents: the values are escaped as JSON strings. The ones in the HTML parts go through
+    // the `html` helper first, the plain text parts being left as they are.
+    handlebars.register_escape_fn(|value| {
+        let escaped = serde_json::Value::String(value.to_owned()).to_string();
+        escaped[1..escaped.len() - 1].to_owned()
+    });
+    handlebars.register_helper("html", Box::new(escape_html));
     handlebars
 });
 
+handlebars_helper!(escape_html: |value: Json| handlebars::html_escape(&value.render()));
+
 static EMAIL_PROPERTIES: Lazy<anyhow::Result<EmailApiProperties>> =
     Lazy::new(|| match EmailApiProperties::load() {
         Ok(props) => Ok(props),
@@ -92,6 +101,42 @@ impl EmailApiProperties {
 
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_templates_render_json() {
+        let data = json!({
+            "dest_name": "Eric \"Fistons\"",
+            "total": 2,
+            "more": 0,
+            "items": [
+                {"title": "Back\\slash\nand new line", "url": "https://example.com/?a=1&b=2", "channel_name": "<Le Monde>"}
+            ],
+            "unsubscribe_url": "http://localhost:8080/api/v1/digest/unsubscribe/abc"
+        });
+
+        let body = HANDLEBARS.render("digest", &data).unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+
+        let text = body["text"].as_str().unwrap();
+        assert!(text.starts_with("Hello Eric \"Fistons\","));
+        assert!(text
+            .contains("- Back\\slash\nand new line (<Le Monde>)\n  https://example.com/?a=1&b=2"));
+        assert!(!text.contains("more"));
+
+        let html = body["html"].as_str().unwrap();
+        assert!(html.starts_with("Hello Eric &quot;Fistons&quot;,"));
+        assert!(html.contains(
+            "<a href=\"https://example.com/?a&#x3D;1&amp;b&#x3D;2\">Back\\slash\nand new line</a> (&lt;Le Monde&gt;)"
+        ));
+        assert_eq!(
+            body["additional_headers"][0]["value"],
+            "<http://localhost:8080/api/v1/digest/unsubscribe/abc>"
+        );
+        assert_eq!(body["subject"], "Your digest: 2 new items");
+    }
 
     //TODO: Test this one day, by mocking the API
     // #[tokio::test]
@@ -0,0 +1,97 @@
+//! Encryption at rest of the secrets which must be read back, with AES-256-GCM.
+//! The key is read from the `ENCRYPTION_KEY` environment variable, as 32 bytes encoded in base64.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::common::errors::EncryptionError;
+
+/// Size of the nonce prepended to each ciphertext, in bytes
+const NONCE_SIZE: usize = 12;
+
+/// Encrypt a secret, returning the nonce and the ciphertext encoded in base64
+pub fn encrypt(secret: &Secret<String>) -> Result<String, EncryptionError> {
+    let cipher = cipher()?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(&nonce, secret.expose_secret().as_bytes())
+        .map_err(|_| EncryptionError::InvalidData)?;
+
+    let mut data = nonce.to_vec();
+    data.extend(ciphertext);
+    Ok(STANDARD.encode(data))
+}
+
+/// Decrypt a secret encrypted by [encrypt]
+pub fn decrypt(encrypted: &str) -> Result<Secret<String>, EncryptionError> {
+    let cipher = cipher()?;
+
+    let data = STANDARD
+        .decode(encrypted)
+        .map_err(|_| EncryptionError::InvalidData)?;
+    if data.len() < NONCE_SIZE {
+        return Err(EncryptionError::InvalidData);
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| EncryptionError::InvalidData)?;
+
+    String::from_utf8(plaintext)
+        .map(Secret::new)
+        .map_err(|_| EncryptionError::InvalidData)
+}
+
+fn cipher() -> Result<Aes256Gcm, EncryptionError> {
+    let key = std::env::var("ENCRYPTION_KEY").map_err(|_| EncryptionError::MissingKey)?;
+    let key = STANDARD
+        .decode(key)
+        .map_err(|_| EncryptionError::InvalidKey)?;
+    if key.len() != 32 {
+        return Err(EncryptionError::InvalidKey);
+    }
+
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    /// Key shared by all the tests needing encryption, as they run in the same process
+    pub(crate) fn set_test_key() {
+        std::env::set_var(
+            "ENCRYPTION_KEY",
+            "3q2+7wABAgMEBQYHCAkKCwwNDg8QERITFBUWFxgZGho=",
+        );
+    }
+
+    #[test]
+    fn test_encryption() {
+        set_test_key();
+        let secret = Secret::new(String::from("eric@pedr0.net"));
+
+        let encrypted = encrypt(&secret).unwrap();
+        assert_that!(encrypted.contains("eric")).is_false();
+        // A new nonce is used each time
+        assert_that!(encrypt(&secret).unwrap()).is_not_equal_to(&encrypted);
+
+        let decrypted = decrypt(&encrypted).unwrap();
+        assert_that!(decrypted.expose_secret().as_str()).is_equal_to("eric@pedr0.net");
+
+        let mut tampered = STANDARD.decode(&encrypted).unwrap();
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(matches!(
+            decrypt(&STANDARD.encode(tampered)),
+            Err(EncryptionError::InvalidData)
+        ));
+        assert!(matches!(decrypt("abc"), Err(EncryptionError::InvalidData)));
+    }
+}
@@ -1,5 +1,7 @@
 use feed_rs::parser::ParseFeedError;
 
+use crate::common::model::PasswordViolation;
+
 #[derive(thiserror::Error, Debug)]
 pub enum RssParsingError {
     #[error("Non OK Http status returned: {0}")]
@@ -23,3 +25,153 @@ pub enum ServiceError {
     #[error(transparent)]
     FeedValidationError(#[from] anyhow::Error),
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum FolderError {
+    #[error("Object of type {0} with id {1} was not found")]
+    NotFound(&'static str, i32),
+    #[error("Folders can only be nested one level deep")]
+    TooDeep,
+    #[error("The name of a folder must have between 1 and 512 characters")]
+    InvalidName,
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum UserError {
+    #[error("Object of type {0} with id {1} was not found")]
+    NotFound(&'static str, i32),
+    #[error("The username cannot be empty")]
+    EmptyUsername,
+    #[error("This username is already taken")]
+    UsernameTaken,
+    #[error("Admins cannot demote or disable their own account")]
+    OwnAccount,
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PasswordPolicyError {
+    #[error("The password does not follow the policy: {0:?}")]
+    Violations(Vec<PasswordViolation>),
+    #[error("Could not read the breached passwords: {0}")]
+    BreachedListError(#[from] std::io::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[error("Invalid OPML document: {0}")]
+    InvalidOpml(#[from] opml::Error),
+    #[error("The OPML document does not contain any feed")]
+    NoFeed,
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RuleError {
+    #[error("Object of type {0} with id {1} was not found")]
+    NotFound(&'static str, i32),
+    #[error("The pattern cannot be empty")]
+    EmptyPattern,
+    #[error("Invalid regular expression: {0}")]
+    InvalidRegex(#[from] regex::Error),
+    #[error("The tag action needs a tag")]
+    MissingTag,
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SavedSearchError {
+    #[error("Object of type {0} with id {1} was not found")]
+    NotFound(&'static str, i32),
+    #[error("The name of the saved search cannot be empty")]
+    EmptyName,
+    #[error("The date window must be between 1 and 3650 days")]
+    InvalidWindow,
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EncryptionError {
+    #[error("The ENCRYPTION_KEY environment variable is not set")]
+    MissingKey,
+    #[error("The encryption key must be 32 bytes encoded in base64")]
+    InvalidKey,
+    #[error("The encrypted data is invalid")]
+    InvalidData,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebhookError {
+    #[error("The webhook url must be a valid http or https url")]
+    InvalidUrl,
+    #[error("The host {0} of the webhook cannot be resolved")]
+    UnknownHost(String),
+    #[error("The webhook cannot target the private address {0}")]
+    PrivateAddress(std::net::IpAddr),
+    #[error("Could not call the webhook: {0}")]
+    HttpError(#[from] reqwest::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DigestError {
+    #[error("Object of type {0} with id {1} was not found")]
+    NotFound(&'static str, i32),
+    #[error("The email must be the verified email of the user")]
+    UnverifiedEmail,
+    #[error("The folder digest needs a folder")]
+    MissingFolder,
+    #[error("Encryption error: {0}")]
+    EncryptionError(#[from] EncryptionError),
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TwoFactorError {
+    #[error("Two-factor authentication is not enrolled")]
+    NotEnrolled,
+    #[error("Two-factor authentication is already enabled")]
+    AlreadyEnabled,
+    #[error("The code is invalid")]
+    InvalidCode,
+    #[error("Encryption error: {0}")]
+    EncryptionError(#[from] EncryptionError),
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OidcError {
+    #[error("Single sign-on is not configured")]
+    NotConfigured,
+    #[error("Unknown or expired login state")]
+    InvalidState,
+    #[error("The identity provider refused the login: {0}")]
+    Denied(String),
+    #[error("Error with the identity provider: {0}")]
+    ProviderError(String),
+    #[error("Error with the identity provider: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("Invalid ID token: {0}")]
+    InvalidIdToken(&'static str),
+    #[error("The {0} claim is missing")]
+    MissingClaim(String),
+    #[error("No account matches this identity")]
+    UnknownAccount,
+    #[error("The account is linked to another identity")]
+    AlreadyLinked,
+    #[error("An account with this username exists, and cannot be linked to this identity")]
+    NotLinkable,
+    #[error("This account is disabled")]
+    DisabledAccount,
+    #[error("Database error: {0}")]
+    SqlError(#[from] sqlx::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
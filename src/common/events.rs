@@ -0,0 +1,183 @@
+//! Per-user events, published through Redis pub/sub so that every API instance can deliver them,
+//! whichever instance produced them.
+//! The last events of each user are also kept in Redis, for the clients to resume their stream.
+
+use deadpool_redis::Pool as RedisPool;
+use redis::aio::{ConnectionLike, PubSub};
+use redis::{AsyncCommands, RedisResult};
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+
+use crate::common::redis_url;
+
+/// Number of events kept for each user
+const HISTORY_SIZE: isize = 500;
+
+/// Time to live of the events history of a user, in seconds
+const HISTORY_TTL: i64 = 60 * 60 * 24;
+
+/// Something that happened to the subscriptions or the items of a user
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// New items were fetched for a channel.
+    NewItems {
+        channel_id: i32,
+        item_ids: Vec<i32>,
+    },
+    /// Items were marked as read or unread.
+    ItemsRead {
+        item_ids: Vec<i32>,
+        read: bool,
+    },
+    /// Items were starred or unstarred.
+    ItemsStarred {
+        item_ids: Vec<i32>,
+        starred: bool,
+    },
+    /// All the items of a channel were marked as read.
+    ChannelRead {
+        channel_id: i32,
+    },
+    SubscriptionAdded {
+        channel_id: i32,
+    },
+    SubscriptionRemoved {
+        channel_id: i32,
+    },
+    /// The fetching of a channel failed.
+    ChannelError {
+        channel_id: i32,
+        reason: String,
+    },
+}
+
+/// An event along with its id, which is increasing for each user
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct UserEvent {
+    pub id: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Publish an event to the given users
+#[instrument(skip(redis))]
+pub async fn publish<C>(redis: &mut C, user_ids: &[i32], event: &Event) -> RedisResult<()>
+where
+    C: ConnectionLike + Send,
+{
+    for user_id in user_ids {
+        let id: u64 = redis.incr(sequence_key(*user_id), 1).await?;
+        let payload = serde_json::to_string(&UserEvent {
+            id,
+            event: event.clone(),
+        })
+        .expect("Events are always serializable");
+
+        let history = history_key(*user_id);
+        redis::pipe()
+            .atomic()
+            .rpush(&history, &payload)
+            .ignore()
+            .ltrim(&history, -HISTORY_SIZE, -1)
+            .ignore()
+            .expire(&history, HISTORY_TTL)
+            .ignore()
+            .publish(channel_key(*user_id), &payload)
+            .ignore()
+            .query_async(redis)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Publish an event to the given users, once the change it tells about is saved.
+/// The change is done whatever happens here, so a failure is only logged: the clients catch up when they reload.
+#[instrument(skip(redis))]
+pub async fn notify(redis: &RedisPool, user_ids: &[i32], event: Event) {
+    if user_ids.is_empty() {
+        return;
+    }
+
+    match redis.get().await {
+        Ok(mut connection) => {
+            if let Err(e) = publish(&mut connection, user_ids, &event).await {
+                error!("Could not publish the event to users {:?}: {}", user_ids, e);
+            }
+        }
+        Err(e) => error!("Could not publish the event to users {:?}: {}", user_ids, e),
+    }
+}
+
+/// Return the events of the user following the given event id, oldest first
+#[instrument(skip(redis))]
+pub async fn events_since<C>(
+    redis: &mut C,
+    user_id: i32,
+    last_id: u64,
+) -> RedisResult<Vec<UserEvent>>
+where
+    C: ConnectionLike + Send,
+{
+    let payloads: Vec<String> = redis.lrange(history_key(user_id), 0, -1).await?;
+
+    Ok(payloads
+        .iter()
+        .filter_map(|payload| parse(payload))
+        .filter(|event| event.id > last_id)
+        .collect())
+}
+
+/// Subscribe to the events of the user.
+/// Pub/sub needs its own connection, so this does not use the connection pool.
+#[instrument]
+pub async fn subscribe(user_id: i32) -> RedisResult<PubSub> {
+    let client = redis::Client::open(redis_url())?;
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(channel_key(user_id)).await?;
+
+    Ok(pubsub)
+}
+
+/// Parse an event received from the pub/sub channel
+pub fn parse(payload: &str) -> Option<UserEvent> {
+    serde_json::from_str(payload).ok()
+}
+
+fn channel_key(user_id: i32) -> String {
+    format!("user.{}.events", user_id)
+}
+
+fn history_key(user_id: i32) -> String {
+    format!("user.{}.events.history", user_id)
+}
+
+fn sequence_key(user_id: i32) -> String {
+    format!("user.{}.events.sequence", user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let event = UserEvent {
+            id: 12,
+            event: Event::ItemsRead {
+                item_ids: vec![1, 2],
+                read: true,
+            },
+        };
+
+        let payload = serde_json::to_string(&event).unwrap();
+        assert_that!(payload).is_equal_to(
+            r#"{"id":12,"type":"items_read","item_ids":[1,2],"read":true}"#.to_owned(),
+        );
+        assert_that!(parse(&payload)).is_equal_to(Some(event));
+        assert_that!(parse("nope")).is_none();
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::Utc;
+use opml::{Body, Head, Outline, OPML};
+use tracing::instrument;
+
+use crate::common::folders::list_folders;
+use crate::common::Pool;
+
+/// A channel to be exported, with the name the user gave it
+struct ExportedChannel {
+    name: String,
+    url: String,
+    site_url: Option<String>,
+    description: Option<String>,
+    folder_id: Option<i32>,
+}
+
+impl From<&ExportedChannel> for Outline {
+    fn from(channel: &ExportedChannel) -> Self {
+        Outline {
+            text: channel.name.clone(),
+            title: Some(channel.name.clone()),
+            r#type: Some("rss".to_owned()),
+            xml_url: Some(channel.url.clone()),
+            html_url: channel.site_url.clone(),
+            description: channel.description.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Export the subscriptions of the user as an OPML 2.0 document, nested by folder
+#[instrument(skip(db))]
+pub async fn export_user_channels(db: &Pool, user_id: i32) -> anyhow::Result<String> {
+    let channels = sqlx::query_as!(
+        ExportedChannel,
+        r#"
+        SELECT      channel_users.name,
+                    channels.url,
+                    channels.site_url,
+                    channels.description,
+                    channel_users.folder_id
+        FROM        channel_users
+                    JOIN channels ON channels.id = channel_users.channel_id
+        WHERE       channel_users.user_id = $1
+        ORDER BY    channel_users.name
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?;
+    let folders = list_folders(db, user_id).await?;
+
+    // Build the outlines of a folder: its sub folders first, then its channels
+    let outlines_of = |folder_id: Option<i32>, sub_folders: Vec<Outline>| -> Vec<Outline> {
+        sub_folders
+            .into_iter()
+            .chain(
+                channels
+                    .iter()
+                    .filter(|channel| channel.folder_id == folder_id)
+                    .map(Outline::from),
+            )
+            .collect()
+    };
+
+    let top_folders = folders
+        .iter()
+        .filter(|folder| folder.parent_id.is_none())
+        .map(|folder| {
+            let sub_folders = folders
+                .iter()
+                .filter(|sub_folder| sub_folder.parent_id == Some(folder.id))
+                .map(|sub_folder| {
+                    folder_outline(&sub_folder.name, outlines_of(Some(sub_folder.id), vec![]))
+                })
+                .collect();
+            folder_outline(&folder.name, outlines_of(Some(folder.id), sub_folders))
+        })
+        .collect();
+
+    to_document("HaRSS subscriptions", outlines_of(None, top_folders))
+}
+
+/// Export all the channels of the instance as an OPML 2.0 document
+#[instrument(skip(db))]
+pub async fn export_all_channels(db: &Pool) -> anyhow::Result<String> {
+    let channels = sqlx::query_as!(
+        ExportedChannel,
+        r#"
+        SELECT  name,
+                url,
+                site_url,
+                description,
+                NULL::integer AS folder_id
+        FROM    channels
+        ORDER BY name
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    to_document(
+        "HaRSS channels",
+        channels.iter().map(Outline::from).collect(),
+    )
+}
+
+fn folder_outline(name: &str, outlines: Vec<Outline>) -> Outline {
+    Outline {
+        text: name.to_owned(),
+        title: Some(name.to_owned()),
+        outlines,
+        ..Default::default()
+    }
+}
+
+fn to_document(title: &str, outlines: Vec<Outline>) -> anyhow::Result<String> {
+    let document = OPML {
+        version: "2.0".to_owned(),
+        head: Some(Head {
+            title: Some(title.to_owned()),
+            date_created: Some(Utc::now().to_rfc2822()),
+            ..Default::default()
+        }),
+        body: Body { outlines },
+    };
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>{}"#,
+        document.to_string()?
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::folders::{create_folder, set_channel_folder};
+    use crate::common::imports::parse_opml;
+
+    use super::*;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_export_user_channels(pool: Pool) -> anyhow::Result<()> {
+        let news = create_folder(&pool, 1, "News", None).await?;
+        let daily = create_folder(&pool, 1, "Daily", Some(news)).await?;
+        set_channel_folder(&pool, 1, 2, Some(daily)).await?;
+
+        let document = export_user_channels(&pool, 1).await?;
+
+        let document = OPML::from_str(&document)?;
+        let outlines = &document.body.outlines;
+        assert_that!(outlines).has_length(2);
+        assert_that!(outlines[0].text).is_equal_to("News".to_owned());
+        assert_that!(outlines[0].outlines[0].text).is_equal_to("Daily".to_owned());
+        // The user's custom name is used
+        assert_that!(outlines[0].outlines[0].outlines[0].text).is_equal_to("Slashdot".to_owned());
+        assert_that!(outlines[1].xml_url)
+            .is_equal_to(Some("https://www.canardpc.com/feed".to_owned()));
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_export_all_channels(pool: Pool) -> anyhow::Result<()> {
+        let document = export_all_channels(&pool).await?;
+
+        let feeds = parse_opml(&document)?;
+        assert_that!(feeds).has_length(3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+//! Storage side of the Fever API compatibility layer.
+//! See <https://web.archive.org/web/20230616124016/https://feedafever.com/api> for the specification.
+
+use chrono::{DateTime, Utc};
+use md5::{Digest, Md5};
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use sqlx::Result;
+use tracing::instrument;
+
+use crate::common::{hash_secret, Pool};
+
+/// Maximum number of items returned by a single call
+const ITEMS_LIMIT: i64 = 50;
+
+/// A Fever group, which is a HaRSS folder
+#[derive(Debug, Serialize)]
+pub struct FeverGroup {
+    pub id: i32,
+    pub title: String,
+}
+
+/// The feeds of a Fever group, as a comma separated list of ids
+#[derive(Debug, Serialize)]
+pub struct FeverFeedsGroup {
+    pub group_id: i32,
+    pub feed_ids: String,
+}
+
+/// A Fever feed, which is a HaRSS channel with the user's metadata
+#[derive(Debug, Serialize)]
+pub struct FeverFeed {
+    pub id: i32,
+    pub favicon_id: i32,
+    pub title: String,
+    pub url: String,
+    pub site_url: String,
+    pub is_spark: i32,
+    pub last_updated_on_time: i64,
+}
+
+/// A Fever item, with the user's read and saved status
+#[derive(Debug, Serialize)]
+pub struct FeverItem {
+    pub id: i32,
+    pub feed_id: i32,
+    pub title: String,
+    pub author: String,
+    pub html: String,
+    pub url: String,
+    pub is_saved: i32,
+    pub is_read: i32,
+    pub created_on_time: i64,
+}
+
+/// Which items to return
+#[derive(Debug)]
+pub enum ItemsSelection {
+    /// The items following the given id, in ascending order.
+    SinceId(i32),
+    /// The items preceding the given id, in descending order.
+    MaxId(i32),
+    /// The given items.
+    WithIds(Vec<i32>),
+}
+
+/// Compute the Fever API key of a user, which is the MD5 hash of `username:password`
+pub fn api_key(username: &str, password: &Secret<String>) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(format!("{}:{}", username, password.expose_secret()));
+    format!("{:x}", hasher.finalize())
+}
+
+/// Hash of a Fever API key, the key itself being as good as the password
+fn api_key_hash(api_key: &str) -> String {
+    hash_secret(&api_key.to_ascii_lowercase())
+}
+
+/// Set the Fever password of the user, or disable the Fever API for them if `None`.
+/// Only the hash of the resulting API key is stored.
+#[instrument(skip(db, password))]
+pub async fn set_fever_password(
+    db: &Pool,
+    user_id: i32,
+    username: &str,
+    password: Option<&Secret<String>>,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE users SET fever_api_key_hash = $1 WHERE id = $2
+        "#,
+        password.map(|password| api_key_hash(&api_key(username, password))),
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Return the id of the user owning the given Fever API key
+#[instrument(skip(db, api_key))]
+pub async fn get_user_id_by_api_key(db: &Pool, api_key: &str) -> Result<Option<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM users WHERE fever_api_key_hash = $1 AND NOT disabled
+        "#,
+        api_key_hash(api_key)
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Return the last time one of the channels of the user was refreshed
+#[instrument(skip(db))]
+pub async fn last_refreshed(db: &Pool, user_id: i32) -> Result<Option<DateTime<Utc>>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT      MAX(channels.last_update)
+        FROM        channels
+                    JOIN channel_users ON channel_users.channel_id = channels.id
+        WHERE       channel_users.user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Return the groups of the user
+#[instrument(skip(db))]
+pub async fn get_groups(db: &Pool, user_id: i32) -> Result<Vec<FeverGroup>> {
+    sqlx::query_as!(
+        FeverGroup,
+        r#"
+        SELECT id, name AS title FROM folders WHERE user_id = $1 ORDER BY name
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Return the feeds of each group of the user. As Fever groups are flat, the feeds of a sub folder
+/// also belong to the group of its parent folder.
+#[instrument(skip(db))]
+pub async fn get_feeds_groups(db: &Pool, user_id: i32) -> Result<Vec<FeverFeedsGroup>> {
+    sqlx::query_as!(
+        FeverFeedsGroup,
+        r#"
+        SELECT      folders.id AS "group_id!",
+                    STRING_AGG(channel_users.channel_id::text, ',' ORDER BY channel_users.channel_id) AS "feed_ids!"
+        FROM        folders
+                    JOIN folders AS children ON children.id = folders.id OR children.parent_id = folders.id
+                    JOIN channel_users ON channel_users.folder_id = children.id
+        WHERE       folders.user_id = $1
+        GROUP BY    folders.id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Return the feeds of the user
+#[instrument(skip(db))]
+pub async fn get_feeds(db: &Pool, user_id: i32) -> Result<Vec<FeverFeed>> {
+    sqlx::query_as!(
+        FeverFeed,
+        r#"
+        SELECT      channels.id,
+                    1 AS "favicon_id!",
+                    channel_users.name AS title,
+                    channels.url,
+                    COALESCE(channels.site_url, '') AS "site_url!",
+                    0 AS "is_spark!",
+                    COALESCE(FLOOR(EXTRACT(EPOCH FROM channels.last_update))::bigint, 0) AS "last_updated_on_time!"
+        FROM        channels
+                    JOIN channel_users ON channel_users.channel_id = channels.id
+        WHERE       channel_users.user_id = $1
+        ORDER BY    channels.id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Return at most 50 items of the user
+#[instrument(skip(db))]
+pub async fn get_items(
+    db: &Pool,
+    user_id: i32,
+    selection: &ItemsSelection,
+) -> Result<Vec<FeverItem>> {
+    let (since_id, max_id, with_ids) = match selection {
+        ItemsSelection::SinceId(id) => (Some(*id), None, None),
+        ItemsSelection::MaxId(id) => (None, Some(*id), None),
+        ItemsSelection::WithIds(ids) => (None, None, Some(&ids[..])),
+    };
+
+    sqlx::query_as!(
+        FeverItem,
+        r#"
+        SELECT      items.id,
+                    items.channel_id AS feed_id,
+                    COALESCE(items.title, '') AS "title!",
+                    COALESCE(items.author, '') AS "author!",
+                    COALESCE(items.content, '') AS "html!",
+                    COALESCE(items.url, '') AS "url!",
+                    users_items.starred::integer AS "is_saved!",
+                    users_items.read::integer AS "is_read!",
+                    FLOOR(EXTRACT(EPOCH FROM COALESCE(items.publish_timestamp, items.fetch_timestamp)))::bigint AS "created_on_time!"
+        FROM        items
+                    JOIN users_items ON users_items.item_id = items.id
+        WHERE       users_items.user_id = $1
+        AND         users_items.hidden = false
+        AND         ($2::integer IS NULL OR items.id > $2)
+        AND         ($3::integer IS NULL OR items.id < $3)
+        AND         ($4::integer[] IS NULL OR items.id = ANY($4))
+        ORDER BY    CASE WHEN $3::integer IS NULL THEN items.id ELSE -items.id END
+        LIMIT       $5
+        "#,
+        user_id,
+        since_id,
+        max_id,
+        with_ids,
+        ITEMS_LIMIT
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Return the total number of items of the user
+#[instrument(skip(db))]
+pub async fn count_items(db: &Pool, user_id: i32) -> Result<i64> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM users_items WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Return the ids of the unread items of the user
+#[instrument(skip(db))]
+pub async fn get_unread_item_ids(db: &Pool, user_id: i32) -> Result<Vec<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT item_id FROM users_items WHERE user_id = $1 AND read = false AND hidden = false ORDER BY item_id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Return the ids of the starred items of the user
+#[instrument(skip(db))]
+pub async fn get_saved_item_ids(db: &Pool, user_id: i32) -> Result<Vec<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT item_id FROM users_items WHERE user_id = $1 AND starred = true AND hidden = false ORDER BY item_id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Mark as read the items of a channel fetched before the given date, returning the ids of the items marked
+#[instrument(skip(db))]
+pub async fn mark_feed_as_read(
+    db: &Pool,
+    user_id: i32,
+    channel_id: i32,
+    before: &DateTime<Utc>,
+) -> Result<Vec<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE      users_items SET read = true
+        FROM        items
+        WHERE       items.id = users_items.item_id
+        AND         users_items.user_id = $1
+        AND         users_items.channel_id = $2
+        AND         items.fetch_timestamp <= $3
+        AND         users_items.read = false
+        RETURNING   users_items.item_id
+        "#,
+        user_id,
+        channel_id,
+        before
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Mark as read the items of a group fetched before the given date, returning the ids of the items marked.
+/// The group `0` is the Fever "Kindling" super group, containing all the feeds.
+#[instrument(skip(db))]
+pub async fn mark_group_as_read(
+    db: &Pool,
+    user_id: i32,
+    group_id: i32,
+    before: &DateTime<Utc>,
+) -> Result<Vec<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        UPDATE  users_items SET read = true
+        FROM    items, channel_users
+        WHERE   items.id = users_items.item_id
+        AND     channel_users.channel_id = users_items.channel_id
+        AND     channel_users.user_id = users_items.user_id
+        AND     users_items.user_id = $1
+        AND     items.fetch_timestamp <= $3
+        AND     ($2 = 0 OR channel_users.folder_id IN (SELECT id FROM folders WHERE id = $2 OR parent_id = $2))
+        AND     users_items.read = false
+        RETURNING users_items.item_id
+        "#,
+        user_id,
+        group_id,
+        before
+    )
+    .fetch_all(db)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::folders::{create_folder, set_channel_folder};
+    use crate::common::items::{hide_items, set_item_read, set_item_starred};
+
+    use super::*;
+
+    #[test]
+    fn test_api_key() {
+        // md5("you@yourdomain.com:yourpassword")
+        let key = api_key(
+            "you@yourdomain.com",
+            &Secret::new("yourpassword".to_owned()),
+        );
+        assert_that!(key).is_equal_to("77a04d4c83ab44f689486dcab138d727".to_owned());
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_authenticate(pool: Pool) -> Result<()> {
+        let password = Secret::new("fever".to_owned());
+        set_fever_password(&pool, 1, "root", Some(&password)).await?;
+
+        let key = api_key("root", &password);
+        assert_that!(get_user_id_by_api_key(&pool, &key).await?).is_equal_to(Some(1));
+        assert_that!(get_user_id_by_api_key(&pool, &key.to_uppercase()).await?)
+            .is_equal_to(Some(1));
+
+        // The key itself is not stored
+        let stored = sqlx::query_scalar!("SELECT fever_api_key_hash FROM users WHERE id = 1")
+            .fetch_one(&pool)
+            .await?;
+        assert_that!(stored).is_equal_to(Some(api_key_hash(&key)));
+
+        set_fever_password(&pool, 1, "root", None).await?;
+        assert_that!(get_user_id_by_api_key(&pool, &key).await?).is_none();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_get_items(pool: Pool) -> Result<()> {
+        let items = get_items(&pool, 1, &ItemsSelection::SinceId(0)).await?;
+        assert_that!(items).has_length(50);
+        assert_that!(items[0].id).is_less_than(items[1].id);
+
+        let last_id = items[49].id;
+        let items = get_items(&pool, 1, &ItemsSelection::SinceId(last_id)).await?;
+        assert_that!(items).has_length(count_items(&pool, 1).await? as usize - 50);
+
+        let items = get_items(&pool, 1, &ItemsSelection::MaxId(last_id)).await?;
+        assert_that!(items).has_length(49);
+        assert_that!(items[0].id).is_greater_than(items[1].id);
+
+        let items = get_items(&pool, 1, &ItemsSelection::WithIds(vec![4, 5])).await?;
+        assert_that!(items).has_length(2);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_hidden_items(pool: Pool) -> Result<()> {
+        set_item_starred(&pool, 1, vec![4, 5], true).await?;
+        set_item_read(&pool, 1, vec![4, 5], false).await?;
+        hide_items(&pool, 1, vec![4]).await?;
+
+        let saved = get_saved_item_ids(&pool, 1).await?;
+        assert_that!(saved).contains(5);
+        assert_that!(saved).does_not_contain(4);
+        let unread = get_unread_item_ids(&pool, 1).await?;
+        assert_that!(unread).contains(5);
+        assert_that!(unread).does_not_contain(4);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_mark_group_as_read(pool: Pool) -> Result<()> {
+        let news = create_folder(&pool, 1, "News", None).await.unwrap();
+        let daily = create_folder(&pool, 1, "Daily", Some(news)).await.unwrap();
+        set_channel_folder(&pool, 1, 2, Some(daily)).await.unwrap();
+
+        let groups = get_feeds_groups(&pool, 1).await?;
+        assert_that!(groups).has_length(2);
+        for group in groups {
+            assert_that!(group.feed_ids).is_equal_to("2".to_owned());
+        }
+
+        let unread = get_unread_item_ids(&pool, 1).await?;
+        assert_that!(unread).is_not_empty();
+        let mut marked = mark_group_as_read(&pool, 1, news, &Utc::now()).await?;
+        marked.sort();
+        assert_that!(marked).is_equal_to(unread);
+        assert_that!(get_unread_item_ids(&pool, 1).await?).is_empty();
+
+        // Only the items which were unread are returned
+        assert_that!(mark_group_as_read(&pool, 1, news, &Utc::now()).await?).is_empty();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+use tracing::instrument;
+
+use crate::common::errors::FolderError;
+use crate::common::model::Folder;
+use crate::common::Pool;
+
+/// Maximum number of characters of a folder name
+const MAX_NAME_LENGTH: usize = 512;
+
+/// List the folders of a user
+#[instrument(skip(db))]
+pub async fn list_folders(db: &Pool, user_id: i32) -> Result<Vec<Folder>, FolderError> {
+    Ok(sqlx::query_as!(
+        Folder,
+        r#"
+        SELECT id, name, parent_id FROM folders WHERE user_id = $1 ORDER BY parent_id NULLS FIRST, name
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await?)
+}
+
+/// Create a new folder for the user, returning its id
+#[instrument(skip(db))]
+pub async fn create_folder(
+    db: &Pool,
+    user_id: i32,
+    name: &str,
+    parent_id: Option<i32>,
+) -> Result<i32, FolderError> {
+    let name = folder_name(name)?;
+    if let Some(parent_id) = parent_id {
+        check_parent(db, user_id, parent_id, None).await?;
+    }
+
+    Ok(sqlx::query_scalar!(
+        r#"
+        INSERT INTO folders (user_id, name, parent_id) VALUES ($1, $2, $3) RETURNING id
+        "#,
+        user_id,
+        name,
+        parent_id
+    )
+    .fetch_one(db)
+    .await?)
+}
+
+/// Return the id of the user's folder with the given name and parent, creating it if needed.
+/// The parent is expected to be a top level folder of the user.
+#[instrument(skip(db))]
+pub async fn find_or_create_folder(
+    db: &Pool,
+    user_id: i32,
+    name: &str,
+    parent_id: Option<i32>,
+) -> Result<i32, FolderError> {
+    let name = folder_name(name)?;
+    let existing = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM folders WHERE user_id = $1 AND name = $2 AND parent_id IS NOT DISTINCT FROM $3
+        "#,
+        user_id,
+        name,
+        parent_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    match existing {
+        Some(folder_id) => Ok(folder_id),
+        None => create_folder(db, user_id, name, parent_id).await,
+    }
+}
+
+/// Rename and/or move a folder of the user
+#[instrument(skip(db))]
+pub async fn update_folder(
+    db: &Pool,
+    user_id: i32,
+    folder_id: i32,
+    name: &str,
+    parent_id: Option<i32>,
+) -> Result<(), FolderError> {
+    let name = folder_name(name)?;
+    if let Some(parent_id) = parent_id {
+        check_parent(db, user_id, parent_id, Some(folder_id)).await?;
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE folders SET name = $1, parent_id = $2 WHERE id = $3 AND user_id = $4
+        "#,
+        name,
+        parent_id,
+        folder_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(FolderError::NotFound("folder", folder_id));
+    }
+
+    Ok(())
+}
+
+/// Delete a folder of the user, along with its sub folders.
+/// The channels of the deleted folders are not unsubscribed, they are only moved out of it.
+#[instrument(skip(db))]
+pub async fn delete_folder(db: &Pool, user_id: i32, folder_id: i32) -> Result<(), FolderError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM folders WHERE id = $1 AND user_id = $2
+        "#,
+        folder_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(FolderError::NotFound("folder", folder_id));
+    }
+
+    Ok(())
+}
+
+/// Move a subscription of the user into the given folder, or out of any folder if `None`
+#[instrument(skip(db))]
+pub async fn set_channel_folder(
+    db: &Pool,
+    user_id: i32,
+    channel_id: i32,
+    folder_id: Option<i32>,
+) -> Result<(), FolderError> {
+    if let Some(folder_id) = folder_id {
+        get_folder(db, user_id, folder_id).await?;
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE channel_users SET folder_id = $1 WHERE channel_id = $2 AND user_id = $3
+        "#,
+        folder_id,
+        channel_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(FolderError::NotFound("channel", channel_id));
+    }
+
+    Ok(())
+}
+
+/// Return the given folder of the user
+#[instrument(skip(db))]
+pub async fn get_folder(db: &Pool, user_id: i32, folder_id: i32) -> Result<Folder, FolderError> {
+    sqlx::query_as!(
+        Folder,
+        r#"
+        SELECT id, name, parent_id FROM folders WHERE id = $1 AND user_id = $2
+        "#,
+        folder_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(FolderError::NotFound("folder", folder_id))
+}
+
+/// Check that the given parent can receive the folder: it must belong to the user and be a top level folder.
+/// If the folder already exists, it must not have sub folders itself.
+async fn check_parent(
+    db: &Pool,
+    user_id: i32,
+    parent_id: i32,
+    folder_id: Option<i32>,
+) -> Result<(), FolderError> {
+    let parent = get_folder(db, user_id, parent_id).await?;
+    if parent.parent_id.is_some() || Some(parent_id) == folder_id {
+        return Err(FolderError::TooDeep);
+    }
+
+    if let Some(folder_id) = folder_id {
+        let has_children = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM folders WHERE parent_id = $1) AS "has_children!"
+            "#,
+            folder_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        if has_children {
+            return Err(FolderError::TooDeep);
+        }
+    }
+
+    Ok(())
+}
+
+/// Return the trimmed name of a folder, if it is neither empty nor too long
+fn folder_name(name: &str) -> Result<&str, FolderError> {
+    let name = name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(FolderError::InvalidName);
+    }
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::channels::select_by_id_and_user_id;
+    use crate::common::items::{get_items_of_user, ItemFilters};
+
+    use super::*;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_folders_are_nested_one_level(pool: Pool) -> Result<(), FolderError> {
+        let news = create_folder(&pool, 1, "News", None).await?;
+        let games = create_folder(&pool, 1, "Games", Some(news)).await?;
+
+        assert!(matches!(
+            create_folder(&pool, 1, "Board games", Some(games)).await,
+            Err(FolderError::TooDeep)
+        ));
+
+        let other = create_folder(&pool, 1, "Other", None).await?;
+        assert!(matches!(
+            update_folder(&pool, 1, news, "News", Some(other)).await,
+            Err(FolderError::TooDeep)
+        ));
+
+        let folders = list_folders(&pool, 1).await?;
+        assert_that!(folders).has_length(3);
+
+        assert!(matches!(
+            create_folder(&pool, 1, "  ", None).await,
+            Err(FolderError::InvalidName)
+        ));
+        assert!(matches!(
+            update_folder(&pool, 1, news, &"a".repeat(513), None).await,
+            Err(FolderError::InvalidName)
+        ));
+        let trimmed = create_folder(&pool, 1, " Trimmed ", None).await?;
+        assert_that!(find_or_create_folder(&pool, 1, "Trimmed", None).await?).is_equal_to(trimmed);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_cannot_use_folder_of_someone_else(pool: Pool) -> Result<(), FolderError> {
+        let folder = create_folder(&pool, 1, "Mine", None).await?;
+
+        assert!(matches!(
+            create_folder(&pool, 2, "Yours", Some(folder)).await,
+            Err(FolderError::NotFound(..))
+        ));
+        assert!(matches!(
+            delete_folder(&pool, 2, folder).await,
+            Err(FolderError::NotFound(..))
+        ));
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_read_folder_items(pool: Pool) -> Result<(), FolderError> {
+        let news = create_folder(&pool, 1, "News", None).await?;
+        let games = create_folder(&pool, 1, "Games", Some(news)).await?;
+        set_channel_folder(&pool, 1, 1, Some(games)).await?;
+
+        let channel = select_by_id_and_user_id(&pool, 1, 1).await?.unwrap();
+        assert_that!(channel.folder_id).is_equal_to(Some(games));
+
+        let items = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                folder_id: Some(news),
+                ..Default::default()
+            },
+            1,
+            1,
+            20,
+        )
+        .await?;
+        // The parent folder contains the items of its sub folders
+        assert_that!(items.total_items()).is_equal_to(&60);
+
+        delete_folder(&pool, 1, news).await?;
+        let channel = select_by_id_and_user_id(&pool, 1, 1).await?.unwrap();
+        assert_that!(channel.folder_id).is_none();
+
+        Ok(())
+    }
+}
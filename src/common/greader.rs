@@ -0,0 +1,345 @@
+//! Storage side of the Google Reader API compatibility layer.
+
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use sqlx::Result;
+use tracing::instrument;
+
+use crate::common::errors::FolderError;
+use crate::common::folders::list_folders;
+use crate::common::items::ItemFilters;
+use crate::common::model::{ItemsSort, UserItem};
+use crate::common::tags::list_tags;
+use crate::common::Pool;
+
+/// Prefix of the long form of the items ids
+const ITEM_ID_PREFIX: &str = "tag:google.com,2005:reader/item/";
+pub const READING_LIST: &str = "user/-/state/com.google/reading-list";
+pub const READ: &str = "user/-/state/com.google/read";
+pub const STARRED: &str = "user/-/state/com.google/starred";
+pub const LABEL_PREFIX: &str = "user/-/label/";
+pub const FEED_PREFIX: &str = "feed/";
+
+/// A GReader stream, i.e. a set of items
+#[derive(Debug, PartialEq, Eq)]
+pub enum Stream {
+    ReadingList,
+    Read,
+    Starred,
+    Feed(i32),
+    Label(String),
+}
+
+impl Stream {
+    /// Parse a stream id such as `feed/1` or `user/-/label/News`.
+    /// User ids in the stream ids are ignored, `user/-` always being the authenticated user.
+    pub fn parse(stream_id: &str) -> Option<Self> {
+        let stream_id = match stream_id.strip_prefix("user/") {
+            Some(rest) => format!("user/-/{}", rest.split_once('/')?.1),
+            None => stream_id.to_owned(),
+        };
+
+        match stream_id.as_str() {
+            READING_LIST => Some(Stream::ReadingList),
+            READ => Some(Stream::Read),
+            STARRED => Some(Stream::Starred),
+            id => {
+                if let Some(label) = id.strip_prefix(LABEL_PREFIX) {
+                    Some(Stream::Label(label.to_owned()))
+                } else {
+                    id.strip_prefix(FEED_PREFIX)?.parse().ok().map(Stream::Feed)
+                }
+            }
+        }
+    }
+}
+
+/// Filters of a stream request
+#[derive(Debug, Default)]
+pub struct StreamFilters {
+    /// Stream of the items to exclude, only the read and starred states are supported.
+    pub exclude: Option<Stream>,
+    /// Stream the items must also belong to, only the read and starred states are supported.
+    pub include: Option<Stream>,
+    /// Only return items published after this timestamp, in seconds.
+    pub newer_than: Option<i64>,
+    /// Only return items published before this timestamp, in seconds.
+    pub older_than: Option<i64>,
+    pub oldest_first: bool,
+}
+
+/// Convert a stream with its filters to items filters.
+/// Labels are the folders of the user, or their item tags if no folder has the label name.
+/// Return `None` if the label does not exist.
+#[instrument(skip(db))]
+pub async fn to_item_filters(
+    db: &Pool,
+    user_id: i32,
+    stream: &Stream,
+    filters: &StreamFilters,
+) -> std::result::Result<Option<ItemFilters>, FolderError> {
+    let mut item_filters = ItemFilters {
+        published_after: filters
+            .newer_than
+            .and_then(|ts| Utc.timestamp_opt(ts, 0).single()),
+        published_before: filters
+            .older_than
+            .and_then(|ts| Utc.timestamp_opt(ts, 0).single()),
+        sort: if filters.oldest_first {
+            ItemsSort::Oldest
+        } else {
+            ItemsSort::Newest
+        },
+        ..Default::default()
+    };
+
+    for state in [Some(stream), filters.include.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        match state {
+            Stream::Read => item_filters.read = Some(true),
+            Stream::Starred => item_filters.starred = Some(true),
+            _ => {}
+        }
+    }
+    match filters.exclude {
+        Some(Stream::Read) => item_filters.read = Some(false),
+        Some(Stream::Starred) => item_filters.starred = Some(false),
+        _ => {}
+    }
+
+    match stream {
+        Stream::Feed(channel_id) => item_filters.channel_id = Some(*channel_id),
+        Stream::Label(label) => {
+            if let Some(folder) = list_folders(db, user_id)
+                .await?
+                .into_iter()
+                .find(|folder| &folder.name == label)
+            {
+                item_filters.folder_id = Some(folder.id);
+            } else if let Some(tag) = list_tags(db, user_id)
+                .await?
+                .into_iter()
+                .find(|tag| &tag.name == label)
+            {
+                item_filters.tag_id = Some(tag.id);
+            } else {
+                return Ok(None);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(Some(item_filters))
+}
+
+/// Return the long form of an item id
+pub fn long_item_id(id: i32) -> String {
+    format!("{}{:016x}", ITEM_ID_PREFIX, id)
+}
+
+/// Parse an item id, which may be in its long form, a 16 characters hexadecimal or a decimal
+pub fn parse_item_id(id: &str) -> Option<i32> {
+    if let Some(hex) = id.strip_prefix(ITEM_ID_PREFIX) {
+        i64::from_str_radix(hex, 16).ok()?.try_into().ok()
+    } else if id.len() == 16 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+        i64::from_str_radix(id, 16).ok()?.try_into().ok()
+    } else {
+        id.parse().ok()
+    }
+}
+
+/// A subscription of the user
+#[derive(Debug)]
+pub struct Subscription {
+    pub id: i32,
+    pub name: String,
+    pub url: String,
+    pub site_url: Option<String>,
+    pub folder: Option<String>,
+}
+
+/// Return the subscriptions of the user, with the name of their folders
+#[instrument(skip(db))]
+pub async fn list_subscriptions(db: &Pool, user_id: i32) -> Result<Vec<Subscription>> {
+    sqlx::query_as!(
+        Subscription,
+        r#"
+        SELECT      channels.id,
+                    channel_users.name,
+                    channels.url,
+                    channels.site_url,
+                    folders.name AS "folder?"
+        FROM        channel_users
+                    JOIN channels ON channels.id = channel_users.channel_id
+                    LEFT JOIN folders ON folders.id = channel_users.folder_id
+        WHERE       channel_users.user_id = $1
+        ORDER BY    channel_users.name
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Return the id of the channel with the given URL, if any
+#[instrument(skip(db))]
+pub async fn get_channel_id_by_url(db: &Pool, url: &str) -> Result<Option<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM channels WHERE url = $1
+        "#,
+        url
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Link of an item
+#[derive(Debug, Serialize)]
+pub struct Link {
+    pub href: String,
+}
+
+/// Origin of an item
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Origin {
+    pub stream_id: String,
+    pub title: String,
+}
+
+/// Content of an item
+#[derive(Debug, Serialize)]
+pub struct Content {
+    pub direction: &'static str,
+    pub content: String,
+}
+
+/// An item as returned by the `stream/contents` endpoints
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GReaderItem {
+    pub id: String,
+    pub crawl_time_msec: String,
+    pub timestamp_usec: String,
+    pub published: i64,
+    pub updated: i64,
+    pub title: String,
+    pub author: String,
+    pub canonical: Vec<Link>,
+    pub alternate: Vec<Link>,
+    pub categories: Vec<String>,
+    pub origin: Origin,
+    pub summary: Content,
+}
+
+impl From<UserItem> for GReaderItem {
+    fn from(item: UserItem) -> Self {
+        let published = item.publish_timestamp.unwrap_or(item.fetch_timestamp);
+
+        let mut categories = vec![READING_LIST.to_owned()];
+        if item.read {
+            categories.push(READ.to_owned());
+        }
+        if item.starred {
+            categories.push(STARRED.to_owned());
+        }
+        categories.extend(
+            item.tags
+                .iter()
+                .map(|tag| format!("{}{}", LABEL_PREFIX, tag)),
+        );
+
+        let links = || {
+            item.url
+                .iter()
+                .map(|url| Link { href: url.clone() })
+                .collect::<Vec<_>>()
+        };
+
+        GReaderItem {
+            id: long_item_id(item.id),
+            crawl_time_msec: item.fetch_timestamp.timestamp_millis().to_string(),
+            timestamp_usec: published.timestamp_micros().to_string(),
+            published: published.timestamp(),
+            updated: published.timestamp(),
+            title: item.title.unwrap_or_default(),
+            author: String::new(),
+            canonical: links(),
+            alternate: links(),
+            categories,
+            origin: Origin {
+                stream_id: format!("{}{}", FEED_PREFIX, item.channel_id),
+                title: item.channel_name,
+            },
+            summary: Content {
+                direction: "ltr",
+                content: item.content.unwrap_or_default(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::folders::{create_folder, set_channel_folder};
+    use crate::common::items::get_items_of_user;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_stream() {
+        assert_that!(Stream::parse("user/-/state/com.google/reading-list"))
+            .is_equal_to(Some(Stream::ReadingList));
+        assert_that!(Stream::parse("user/1/state/com.google/starred"))
+            .is_equal_to(Some(Stream::Starred));
+        assert_that!(Stream::parse("user/-/label/News"))
+            .is_equal_to(Some(Stream::Label("News".to_owned())));
+        assert_that!(Stream::parse("feed/12")).is_equal_to(Some(Stream::Feed(12)));
+        assert_that!(Stream::parse("feed/https://example.com")).is_none();
+        assert_that!(Stream::parse("splice/whatever")).is_none();
+    }
+
+    #[test]
+    fn test_item_ids() {
+        let long = long_item_id(26);
+        assert_that!(long)
+            .is_equal_to("tag:google.com,2005:reader/item/000000000000001a".to_owned());
+        assert_that!(parse_item_id(&long)).is_equal_to(Some(26));
+        assert_that!(parse_item_id("000000000000001a")).is_equal_to(Some(26));
+        assert_that!(parse_item_id("26")).is_equal_to(Some(26));
+        assert_that!(parse_item_id("nope")).is_none();
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_label_stream(pool: Pool) -> Result<()> {
+        let news = create_folder(&pool, 1, "News", None).await.unwrap();
+        set_channel_folder(&pool, 1, 2, Some(news)).await.unwrap();
+
+        let filters = StreamFilters {
+            exclude: Some(Stream::Read),
+            ..Default::default()
+        };
+        let item_filters = to_item_filters(&pool, 1, &Stream::Label("News".to_owned()), &filters)
+            .await
+            .unwrap()
+            .unwrap();
+        let items = get_items_of_user(&pool, &item_filters, 1, 1, 50).await?;
+        assert_that!(items.total_items()).is_equal_to(&16);
+
+        let unknown = to_item_filters(&pool, 1, &Stream::Label("Nope".to_owned()), &filters)
+            .await
+            .unwrap();
+        assert_that!(unknown).is_none();
+
+        let subscriptions = list_subscriptions(&pool, 1).await?;
+        let subscription = subscriptions.iter().find(|s| s.id == 2).unwrap();
+        assert_that!(subscription.folder).is_equal_to(Some("News".to_owned()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use deadpool_redis::Pool as RedisPool;
+use opml::{Outline, OPML};
+use sqlx::Result;
+use tracing::{error, info, instrument, warn};
+
+use crate::common::channels::create_or_link_channel;
+use crate::common::counters::forget_counters;
+use crate::common::errors::{FolderError, ImportError};
+use crate::common::folders::{find_or_create_folder, set_channel_folder};
+use crate::common::model::{Import, ImportFailure, ImportStatus};
+use crate::common::Pool;
+
+/// A feed found in an OPML document
+#[derive(Debug, PartialEq, Eq)]
+pub struct OpmlFeed {
+    pub url: String,
+    pub name: Option<String>,
+    /// Path of the folder containing the feed, at most two levels deep.
+    pub folders: Vec<String>,
+}
+
+/// Parse an OPML document, returning all the feeds it contains.
+/// Outlines without a feed URL are considered as folders. As folders can only be nested one level
+/// deep, the feeds of deeper outlines are put in their second level ancestor.
+#[instrument(skip(content))]
+pub fn parse_opml(content: &str) -> std::result::Result<Vec<OpmlFeed>, ImportError> {
+    let document = OPML::from_str(content)?;
+
+    let mut feeds = Vec::new();
+    collect_feeds(&document.body.outlines, &[], &mut feeds);
+
+    if feeds.is_empty() {
+        return Err(ImportError::NoFeed);
+    }
+
+    Ok(feeds)
+}
+
+fn collect_feeds(outlines: &[Outline], folders: &[String], feeds: &mut Vec<OpmlFeed>) {
+    for outline in outlines {
+        let name = outline
+            .title
+            .clone()
+            .or_else(|| Some(outline.text.clone()))
+            .filter(|name| !name.trim().is_empty());
+
+        match &outline.xml_url {
+            Some(url) => feeds.push(OpmlFeed {
+                url: url.clone(),
+                name,
+                folders: folders.to_vec(),
+            }),
+            None => {
+                let mut folders = folders.to_vec();
+                if let Some(name) = name.filter(|_| folders.len() < 2) {
+                    folders.push(name);
+                }
+                collect_feeds(&outline.outlines, &folders, feeds);
+            }
+        }
+    }
+}
+
+/// Register a new import for the user, returning its id
+#[instrument(skip(db))]
+pub async fn create_import(db: &Pool, user_id: i32, total: i32) -> Result<i32> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO imports (user_id, total) VALUES ($1, $2) RETURNING id
+        "#,
+        user_id,
+        total
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Return the given import of the user, along with its failures
+#[instrument(skip(db))]
+pub async fn get_import(db: &Pool, user_id: i32, import_id: i32) -> Result<Option<Import>> {
+    let import = sqlx::query!(
+        r#"
+        SELECT  id, status AS "status: ImportStatus", total, processed, start_timestamp, end_timestamp
+        FROM    imports
+        WHERE   id = $1 AND user_id = $2
+        "#,
+        import_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?;
+
+    let Some(import) = import else {
+        return Ok(None);
+    };
+
+    let failures = sqlx::query_as!(
+        ImportFailure,
+        r#"
+        SELECT url, reason FROM imports_failures WHERE import_id = $1 ORDER BY id
+        "#,
+        import_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(Some(Import {
+        id: import.id,
+        status: import.status,
+        total: import.total,
+        processed: import.processed,
+        start_timestamp: import.start_timestamp,
+        end_timestamp: import.end_timestamp,
+        failures,
+    }))
+}
+
+/// Subscribe the user to each feed of the import, creating the folders along the way.
+/// The progress is saved after each feed, so it can be followed with [`get_import`]. An unexpected error stops the
+/// import, which is then marked as failed.
+#[instrument(skip(db, redis, feeds))]
+pub async fn run_import(
+    db: &Pool,
+    redis: &RedisPool,
+    import_id: i32,
+    user_id: i32,
+    feeds: Vec<OpmlFeed>,
+) -> Result<()> {
+    let result = import_feeds(db, redis, import_id, user_id, feeds).await;
+    forget_counters(redis, &[user_id]).await;
+
+    let Err(e) = result else {
+        info!("Import {} of user {} done", import_id, user_id);
+        return Ok(());
+    };
+
+    error!("Import {} of user {} failed: {}", import_id, user_id, e);
+    sqlx::query!(
+        r#"
+        UPDATE imports SET status = 'failed', end_timestamp = now() WHERE id = $1
+        "#,
+        import_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Import the feeds one by one, saving the progress along the way
+async fn import_feeds(
+    db: &Pool,
+    redis: &RedisPool,
+    import_id: i32,
+    user_id: i32,
+    feeds: Vec<OpmlFeed>,
+) -> Result<()> {
+    let mut folders_cache: HashMap<Vec<String>, i32> = HashMap::new();
+
+    for feed in feeds {
+        if let Err(reason) = import_feed(db, redis, user_id, &feed, &mut folders_cache).await {
+            warn!("Could not import feed {}: {}", feed.url, reason);
+            sqlx::query!(
+                r#"
+                INSERT INTO imports_failures (import_id, url, reason) VALUES ($1, $2, $3)
+                "#,
+                import_id,
+                feed.url,
+                reason
+            )
+            .execute(db)
+            .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE imports SET processed = processed + 1 WHERE id = $1
+            "#,
+            import_id
+        )
+        .execute(db)
+        .await?;
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE imports SET status = 'done', end_timestamp = now() WHERE id = $1
+        "#,
+        import_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Subscribe the user to a single feed, returning the reason of the failure if any
+async fn import_feed(
+    db: &Pool,
+    redis: &RedisPool,
+    user_id: i32,
+    feed: &OpmlFeed,
+    folders_cache: &mut HashMap<Vec<String>, i32>,
+) -> std::result::Result<(), String> {
+    let channel_id = create_or_link_channel(db, redis, &feed.url, feed.name.clone(), None, user_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !feed.folders.is_empty() {
+        let folder_id = get_or_create_folder(db, user_id, &feed.folders, folders_cache)
+            .await
+            .map_err(|e| e.to_string())?;
+        set_channel_folder(db, user_id, channel_id, Some(folder_id))
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Return the id of the folder with the given path, creating the missing folders.
+/// Existing folders with the same name are reused, so importing twice the same document does not
+/// duplicate them.
+async fn get_or_create_folder(
+    db: &Pool,
+    user_id: i32,
+    path: &[String],
+    folders_cache: &mut HashMap<Vec<String>, i32>,
+) -> std::result::Result<i32, FolderError> {
+    let mut parent_id = None;
+
+    for depth in 1..=path.len() {
+        let key = path[..depth].to_vec();
+        if let Some(folder_id) = folders_cache.get(&key) {
+            parent_id = Some(*folder_id);
+            continue;
+        }
+
+        let folder_id = find_or_create_folder(db, user_id, &path[depth - 1], parent_id).await?;
+        folders_cache.insert(key, folder_id);
+        parent_id = Some(folder_id);
+    }
+
+    // The path is never empty, so the parent is always set here
+    parent_id.ok_or(FolderError::SqlError(sqlx::Error::RowNotFound))
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::channels::select_by_id_and_user_id;
+    use crate::common::folders::list_folders;
+    use crate::common::init_redis_connection;
+
+    use super::*;
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Canard PC" type="rss" xmlUrl="https://www.canardpc.com/feed"/>
+    <outline text="News">
+      <outline text="Games">
+        <outline text="Daily">
+          <outline text="Le Monde" type="rss" xmlUrl="https://www.lemonde.fr/rss/une.xml"/>
+        </outline>
+      </outline>
+      <outline text="Broken" type="rss" xmlUrl="http://127.0.0.1:1/feed"/>
+    </outline>
+  </body>
+</opml>"#;
+
+    #[test]
+    fn test_parse_opml() {
+        let feeds = parse_opml(DOCUMENT).unwrap();
+
+        assert_that!(feeds).is_equal_to(vec![
+            OpmlFeed {
+                url: "https://www.canardpc.com/feed".to_owned(),
+                name: Some("Canard PC".to_owned()),
+                folders: vec![],
+            },
+            OpmlFeed {
+                url: "https://www.lemonde.fr/rss/une.xml".to_owned(),
+                name: Some("Le Monde".to_owned()),
+                folders: vec!["News".to_owned(), "Games".to_owned()],
+            },
+            OpmlFeed {
+                url: "http://127.0.0.1:1/feed".to_owned(),
+                name: Some("Broken".to_owned()),
+                folders: vec!["News".to_owned()],
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_invalid_opml() {
+        assert!(matches!(
+            parse_opml("<html></html>"),
+            Err(ImportError::InvalidOpml(_))
+        ));
+        assert!(matches!(
+            parse_opml(r#"<opml version="2.0"><head/><body><outline text="Empty"/></body></opml>"#),
+            Err(ImportError::NoFeed)
+        ));
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_run_import(pool: Pool) -> Result<()> {
+        let redis = init_redis_connection();
+        let feeds = parse_opml(DOCUMENT).unwrap();
+
+        let import_id = create_import(&pool, 2, feeds.len() as i32).await?;
+        run_import(&pool, &redis, import_id, 2, feeds).await?;
+
+        let import = get_import(&pool, 2, import_id).await?.unwrap();
+        assert_that!(import.status).is_equal_to(ImportStatus::Done);
+        assert_that!(import.processed).is_equal_to(3);
+        assert_that!(import.failures).has_length(1);
+        assert_that!(import.failures[0].url).is_equal_to("http://127.0.0.1:1/feed".to_owned());
+
+        let folders = list_folders(&pool, 2).await.unwrap();
+        assert_that!(folders).has_length(2);
+
+        let games = folders.iter().find(|f| f.name == "Games").unwrap();
+        let channel = select_by_id_and_user_id(&pool, 2, 2).await?.unwrap();
+        assert_that!(channel.folder_id).is_equal_to(Some(games.id));
+
+        // Someone else can't see the import
+        assert_that!(get_import(&pool, 1, import_id).await?).is_none();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,284 @@
+//! Invitations to register, when the account creation is not open to everyone.
+//! An invitation can be used a few times before it expires, and gives a role and some channels to the new users.
+
+use chrono::{DateTime, Utc};
+use deadpool_redis::Pool as RedisPool;
+use rand::distributions::{Alphanumeric, DistString};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::Result;
+use tracing::{info, instrument, warn};
+
+use crate::common::channels::create_or_link_channel;
+use crate::common::errors::UserError;
+use crate::common::model::{Invitation, User, UserRole};
+use crate::common::users::{confirm_new_email, insert_user};
+use crate::common::{hash_secret, DbError, Pool};
+
+/// Number of characters of an invitation code
+const CODE_LENGTH: usize = 20;
+
+/// Number of characters of a code kept in clear, to recognize it
+const DISPLAYED_PREFIX_LENGTH: usize = 4;
+
+/// Create an invitation, returning its id and its code
+#[instrument(skip(db))]
+pub async fn create_invitation(
+    db: &Pool,
+    user_id: i32,
+    role: &UserRole,
+    channels: &[String],
+    max_uses: i32,
+    expiration_timestamp: DateTime<Utc>,
+) -> Result<(i32, String)> {
+    let code = Alphanumeric.sample_string(&mut rand::thread_rng(), CODE_LENGTH);
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO invitations (user_id, code_hash, prefix, role, channels, max_uses, expiration_timestamp)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+        user_id,
+        hash_secret(&code),
+        &code[..DISPLAYED_PREFIX_LENGTH],
+        role as &UserRole,
+        channels,
+        max_uses,
+        expiration_timestamp
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok((id, code))
+}
+
+/// Count the registrations the invitations of the user still allow
+#[instrument(skip(db))]
+pub async fn count_remaining_uses(db: &Pool, user_id: i32) -> Result<i64> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT  COALESCE(SUM(max_uses - uses), 0) AS "count!"
+        FROM    invitations
+        WHERE   user_id = $1
+        AND     uses < max_uses
+        AND     expiration_timestamp > now()
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// List the invitations of the user, or all of them if no user is given, used and expired ones included
+#[instrument(skip(db))]
+pub async fn list_invitations(db: &Pool, user_id: Option<i32>) -> Result<Vec<Invitation>> {
+    sqlx::query_as!(
+        Invitation,
+        r#"
+        SELECT      id,
+                    user_id,
+                    prefix,
+                    role AS "role: UserRole",
+                    channels,
+                    max_uses,
+                    uses,
+                    expiration_timestamp,
+                    creation_timestamp
+        FROM        invitations
+        WHERE       ($1::integer IS NULL OR user_id = $1)
+        ORDER BY    id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Revoke an invitation of the user, or any invitation if no user is given
+#[instrument(skip(db))]
+pub async fn revoke_invitation(db: &Pool, user_id: Option<i32>, invitation_id: i32) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM invitations WHERE id = $1 AND ($2::integer IS NULL OR user_id = $2)
+        "#,
+        invitation_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Register a new user with an invitation, returning None if the invitation is unknown, expired or used up.
+/// The user gets the role of the invitation, and is subscribed to its channels.
+#[instrument(skip(db, redis, code, password, email))]
+pub async fn register(
+    db: &Pool,
+    redis: &RedisPool,
+    code: &Secret<String>,
+    username: &str,
+    password: &Secret<String>,
+    email: &Option<Secret<String>>,
+) -> std::result::Result<Option<User>, UserError> {
+    let mut transaction = db.begin().await?;
+
+    // The invitation stays locked until the user is created, so that it is not used more than allowed
+    let Some(invitation) = sqlx::query!(
+        r#"
+        UPDATE      invitations
+        SET         uses = uses + 1
+        WHERE       code_hash = $1
+        AND         uses < max_uses
+        AND         expiration_timestamp > now()
+        RETURNING   id, role AS "role: UserRole", channels
+        "#,
+        hash_secret(code.expose_secret())
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    // A taken username rolls the use of the invitation back
+    let user = insert_user(
+        &mut *transaction,
+        username,
+        password,
+        email,
+        &invitation.role,
+    )
+    .await?;
+    transaction.commit().await?;
+    info!(
+        "User {} registered with the invitation {}",
+        user.id, invitation.id
+    );
+    confirm_new_email(redis, &user, email).await;
+
+    for url in &invitation.channels {
+        if let Err(e) = create_or_link_channel(db, redis, url, None, None, user.id).await {
+            warn!(
+                "Could not subscribe user {} to the channel {} of their invitation: {:?}",
+                user.id, url, e
+            );
+        }
+    }
+
+    Ok(Some(user))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use speculoos::prelude::*;
+
+    use super::*;
+    use crate::common::init_redis_connection;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_register_with_invitation(pool: Pool) -> anyhow::Result<()> {
+        // Without email, nothing is sent nor stored in redis
+        let redis = init_redis_connection();
+        let password = Secret::new(String::from("p4ssword"));
+        let (id, code) = create_invitation(
+            &pool,
+            1,
+            &UserRole::Admin,
+            &[],
+            1,
+            Utc::now() + Duration::days(1),
+        )
+        .await?;
+
+        let user = register(
+            &pool,
+            &redis,
+            &Secret::new(code.clone()),
+            "jane",
+            &password,
+            &None,
+        )
+        .await?
+        .unwrap();
+        assert_that!(user.role).is_equal_to(UserRole::Admin);
+
+        // Used up
+        let user = register(&pool, &redis, &Secret::new(code), "joe", &password, &None).await?;
+        assert_that!(user).is_none();
+
+        let invitations = list_invitations(&pool, Some(1)).await?;
+        assert_that!(invitations).has_length(1);
+        assert_that!(invitations[0].uses).is_equal_to(1);
+        assert_that!(count_remaining_uses(&pool, 1).await?).is_equal_to(0);
+
+        // Only their creator, or an admin, revokes them
+        assert!(revoke_invitation(&pool, Some(2), id).await.is_err());
+        revoke_invitation(&pool, None, id).await?;
+        assert_that!(list_invitations(&pool, None).await?).is_empty();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_register_with_taken_username(pool: Pool) -> anyhow::Result<()> {
+        let redis = init_redis_connection();
+        let (_, code) = create_invitation(
+            &pool,
+            1,
+            &UserRole::Basic,
+            &[],
+            1,
+            Utc::now() + Duration::days(1),
+        )
+        .await?;
+
+        let result = register(
+            &pool,
+            &redis,
+            &Secret::new(code),
+            "root",
+            &Secret::new(String::from("p4ssword")),
+            &None,
+        )
+        .await;
+        assert!(matches!(result, Err(UserError::UsernameTaken)));
+
+        // The invitation is not used
+        assert_that!(count_remaining_uses(&pool, 1).await?).is_equal_to(1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_expired_invitation(pool: Pool) -> anyhow::Result<()> {
+        let redis = init_redis_connection();
+        let (_, code) = create_invitation(
+            &pool,
+            1,
+            &UserRole::Basic,
+            &[],
+            5,
+            Utc::now() - Duration::seconds(1),
+        )
+        .await?;
+
+        let user = register(
+            &pool,
+            &redis,
+            &Secret::new(code),
+            "jane",
+            &Secret::new(String::from("p4ssword")),
+            &None,
+        )
+        .await?;
+        assert_that!(user).is_none();
+
+        Ok(())
+    }
+}
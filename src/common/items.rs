@@ -1,17 +1,41 @@
 use chrono::{DateTime, Utc};
 use sqlx::{Postgres, QueryBuilder, Result};
+use tracing::error;
 
 use crate::common::channels::get_user_ids_of_channel;
-use crate::common::model::{NewItem, PagedResult, UserItem};
+use crate::common::model::{ItemsSort, NewItem, PagedResult, UserItem};
+use crate::common::rules::apply_rules;
 use crate::common::Pool;
 
+/// Filters applied on the items of a user
+#[derive(Debug, Default)]
+pub struct ItemFilters {
+    pub channel_id: Option<i32>,
+    pub read: Option<bool>,
+    pub starred: Option<bool>,
+    pub tag_id: Option<i32>,
+    pub folder_id: Option<i32>,
+    /// Exclude the channels the user has hidden from the unified river
+    pub exclude_hidden: bool,
+    pub published_after: Option<DateTime<Utc>>,
+    pub published_before: Option<DateTime<Utc>>,
+    /// Only the items linked to the user after this date
+    pub added_after: Option<DateTime<Utc>>,
+    /// Only the items of these channels, or of the channels of these folders
+    pub channel_ids: Vec<i32>,
+    pub folder_ids: Vec<i32>,
+    /// Only the items attached to one of these tags
+    pub tag_ids: Vec<i32>,
+    /// Full-text search on the title and the content, in the web search engines syntax
+    pub search: Option<String>,
+    pub sort: ItemsSort,
+}
+
 /// Return a page of items of a given channel for a given user.
 #[tracing::instrument(skip(db))]
 pub async fn get_items_of_user(
     db: &Pool,
-    channel_id: Option<i32>,
-    read: Option<bool>,
-    starred: Option<bool>,
+    filters: &ItemFilters,
     user_id: i32,
     page_number: u64,
     page_size: u64,
@@ -28,7 +52,13 @@ pub async fn get_items_of_user(
                users_items.starred AS starred,
                users_items.notes   AS notes,
                channel_users.name       AS channel_name,
-               items.channel_id AS channel_id
+               items.channel_id AS channel_id,
+               ARRAY(SELECT tags.name
+                     FROM users_items_tags
+                              JOIN tags ON tags.id = users_items_tags.tag_id
+                     WHERE users_items_tags.user_id = users_items.user_id
+                       AND users_items_tags.item_id = users_items.item_id
+                     ORDER BY tags.name) AS tags
         FROM items
                  RIGHT JOIN users_items ON items.id = users_items.item_id
                  RIGHT JOIN channel_users ON items.channel_id = channel_users.channel_id and users_items.user_id = channel_users.user_id
@@ -38,13 +68,12 @@ pub async fn get_items_of_user(
     let mut page_query: QueryBuilder<Postgres> = QueryBuilder::new(base_part);
     page_query.push_bind(user_id);
 
-    add_filters(&mut page_query, channel_id, read, starred);
+    add_filters(&mut page_query, filters);
 
-    page_query.push(
-        r#"
-        ORDER BY items.publish_timestamp DESC
-        "#,
-    );
+    page_query.push(match filters.sort {
+        ItemsSort::Newest => " ORDER BY items.publish_timestamp DESC ",
+        ItemsSort::Oldest => " ORDER BY items.publish_timestamp ASC ",
+    });
 
     page_query.push(" LIMIT ");
     page_query.push_bind(page_size as i64);
@@ -59,7 +88,7 @@ pub async fn get_items_of_user(
     );
     count_query.push(base_part);
     count_query.push_bind(user_id);
-    add_filters(&mut count_query, channel_id, read, starred);
+    add_filters(&mut count_query, filters);
     count_query.push(" ) AS sub_query ");
 
     let content = page_query.build_query_as().fetch_all(db).await?;
@@ -77,6 +106,25 @@ pub async fn get_items_of_user(
     ))
 }
 
+/// Count the items of a user matching the filters
+#[tracing::instrument(skip(db))]
+pub async fn count_items_of_user(db: &Pool, filters: &ItemFilters, user_id: i32) -> Result<i64> {
+    let mut query: QueryBuilder<Postgres> = QueryBuilder::new(
+        r#"
+        SELECT  COUNT(*)
+        FROM    users_items
+                JOIN items ON items.id = users_items.item_id
+                JOIN channel_users ON channel_users.channel_id = users_items.channel_id
+                                  AND channel_users.user_id = users_items.user_id
+        WHERE   users_items.user_id =
+        "#,
+    );
+    query.push_bind(user_id);
+    add_filters(&mut query, filters);
+
+    query.build_query_scalar().fetch_one(db).await
+}
+
 /// Get all the item's GUID of a given channel.
 #[tracing::instrument(skip(db))]
 pub async fn get_all_items_guid_of_channel(
@@ -112,6 +160,31 @@ pub async fn set_item_read(db: &Pool, user_id: i32, ids: Vec<i32>, read: bool) -
     Ok(())
 }
 
+/// Mark as read all the items of the user matching the filters, returning the ids of the items marked
+#[tracing::instrument(skip(db))]
+pub async fn mark_items_as_read(
+    db: &Pool,
+    filters: &ItemFilters,
+    user_id: i32,
+) -> Result<Vec<i32>> {
+    let mut query: QueryBuilder<Postgres> = QueryBuilder::new(
+        r#"
+        UPDATE  users_items SET read = true
+        FROM    items, channel_users
+        WHERE   items.id = users_items.item_id
+        AND     channel_users.channel_id = users_items.channel_id
+        AND     channel_users.user_id = users_items.user_id
+        AND     users_items.user_id =
+        "#,
+    );
+    query.push_bind(user_id);
+    query.push(" AND users_items.read = false");
+    add_filters(&mut query, filters);
+    query.push(" RETURNING users_items.item_id");
+
+    query.build_query_scalar().fetch_all(db).await
+}
+
 /// Update the starred status of an item for a given user
 #[tracing::instrument(skip(db))]
 pub async fn set_item_starred(db: &Pool, user_id: i32, ids: Vec<i32>, starred: bool) -> Result<()> {
@@ -132,6 +205,22 @@ pub async fn set_item_starred(db: &Pool, user_id: i32, ids: Vec<i32>, starred: b
     Ok(())
 }
 
+/// Hide the given items of the user: they are marked as read and removed from the items lists
+#[tracing::instrument(skip(db))]
+pub async fn hide_items(db: &Pool, user_id: i32, ids: Vec<i32>) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE users_items SET hidden = true, read = true WHERE user_id = $1 AND item_id = ANY($2)
+        "#,
+        user_id,
+        &ids[..]
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
 /// Insert an item in the database and associate it to all given users
 #[tracing::instrument(skip(db))]
 pub async fn insert_items_delta_for_all_registered_users(
@@ -145,6 +234,13 @@ pub async fn insert_items_delta_for_all_registered_users(
 
     for user_id in user_ids {
         insert_item_user(db, &channel_id, &user_id, fetch_timestamp).await?;
+        // The items are linked anyway, the failure of the rules of a user must not keep the others from theirs
+        if let Err(e) = apply_rules(db, user_id, channel_id, fetch_timestamp).await {
+            error!(
+                "Could not apply the rules of user {} to channel {}: {}",
+                user_id, channel_id, e
+            );
+        }
     }
 
     Ok(())
@@ -157,6 +253,7 @@ pub async fn insert_items(db: &Pool, items: &Vec<NewItem>) -> Result<Vec<i32>> {
     let mut titles: Vec<Option<String>> = vec![];
     let mut urls: Vec<Option<String>> = vec![];
     let mut contents: Vec<Option<String>> = vec![];
+    let mut authors: Vec<Option<String>> = vec![];
     let mut fetch_timestamps: Vec<DateTime<Utc>> = vec![];
     let mut publish_timestamps: Vec<Option<DateTime<Utc>>> = vec![];
     let mut channel_ids: Vec<i32> = vec![];
@@ -166,6 +263,7 @@ pub async fn insert_items(db: &Pool, items: &Vec<NewItem>) -> Result<Vec<i32>> {
         titles.push(item.title.clone());
         urls.push(item.url.clone());
         contents.push(item.content.clone());
+        authors.push(item.author.clone());
         fetch_timestamps.push(item.fetch_timestamp);
         publish_timestamps.push(item.publish_timestamp);
         channel_ids.push(item.channel_id);
@@ -175,11 +273,11 @@ pub async fn insert_items(db: &Pool, items: &Vec<NewItem>) -> Result<Vec<i32>> {
     // Also, sqlx magic: https://github.com/launchbadge/sqlx/issues/571#issuecomment-664910255
     sqlx::query_scalar!(
         r#"
-        INSERT INTO items (guid, title, url, content, fetch_timestamp, publish_timestamp, channel_id)
-        SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::timestamptz[], $6::timestamptz[], $7::int[])
+        INSERT INTO items (guid, title, url, content, author, fetch_timestamp, publish_timestamp, channel_id)
+        SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::text[], $6::timestamptz[], $7::timestamptz[], $8::int[])
         RETURNING id
         "#,
-        &guids[..] as _, &titles[..] as _, &urls[..] as _, &contents[..] as _, &fetch_timestamps[..], &publish_timestamps[..] as _, &channel_ids[..])
+        &guids[..] as _, &titles[..] as _, &urls[..] as _, &contents[..] as _, &authors[..] as _, &fetch_timestamps[..], &publish_timestamps[..] as _, &channel_ids[..])
         .fetch_all(db).await
 }
 
@@ -222,7 +320,13 @@ pub async fn get_one_item(db: &Pool, item_id: i32, user_id: i32) -> Result<Optio
                users_items.starred AS starred,
                users_items.notes    AS notes,
                channel_users.name       AS channel_name,
-               channel_users.channel_id AS channel_id
+               channel_users.channel_id AS channel_id,
+               ARRAY(SELECT tags.name
+                     FROM users_items_tags
+                              JOIN tags ON tags.id = users_items_tags.tag_id
+                     WHERE users_items_tags.user_id = users_items.user_id
+                       AND users_items_tags.item_id = users_items.item_id
+                     ORDER BY tags.name) AS "tags!"
         FROM items
                RIGHT JOIN users_items ON items.id = users_items.item_id
                RIGHT JOIN channel_users ON items.channel_id = channel_users.channel_id
@@ -258,26 +362,103 @@ async fn insert_item_user(
     Ok(())
 }
 
-fn add_filters(
-    query: &mut QueryBuilder<Postgres>,
-    channel_id: Option<i32>,
-    read: Option<bool>,
-    starred: Option<bool>,
-) {
-    if let Some(channel_id) = channel_id {
+fn add_filters(query: &mut QueryBuilder<Postgres>, filters: &ItemFilters) {
+    query.push(" AND users_items.hidden = false ");
+
+    if let Some(channel_id) = filters.channel_id {
         query.push(" AND users_items.channel_id = ");
         query.push_bind(channel_id);
     }
 
-    if let Some(read) = read {
+    if let Some(read) = filters.read {
         query.push(" AND users_items.read = ");
         query.push_bind(read);
     }
 
-    if let Some(starred) = starred {
+    if let Some(starred) = filters.starred {
         query.push(" AND users_items.starred = ");
         query.push_bind(starred);
     }
+
+    if let Some(tag_id) = filters.tag_id {
+        query.push(
+            r#"
+            AND EXISTS (SELECT 1 FROM users_items_tags
+                        WHERE users_items_tags.user_id = users_items.user_id
+                        AND users_items_tags.item_id = users_items.item_id
+                        AND users_items_tags.tag_id = "#,
+        );
+        query.push_bind(tag_id);
+        query.push(")");
+    }
+
+    if let Some(published_after) = filters.published_after {
+        query.push(" AND items.publish_timestamp >= ");
+        query.push_bind(published_after);
+    }
+
+    if let Some(published_before) = filters.published_before {
+        query.push(" AND items.publish_timestamp <= ");
+        query.push_bind(published_before);
+    }
+
+    if let Some(added_after) = filters.added_after {
+        query.push(" AND users_items.added_timestamp > ");
+        query.push_bind(added_after);
+    }
+
+    if filters.exclude_hidden {
+        query.push(" AND channel_users.hide_from_river = false ");
+    }
+
+    if let Some(folder_id) = filters.folder_id {
+        query.push(
+            r#"
+            AND channel_users.folder_id IN (SELECT folders.id FROM folders
+                                            WHERE folders.id = "#,
+        );
+        query.push_bind(folder_id);
+        query.push(" OR folders.parent_id = ");
+        query.push_bind(folder_id);
+        query.push(")");
+    }
+
+    if !filters.channel_ids.is_empty() || !filters.folder_ids.is_empty() {
+        query.push(" AND (users_items.channel_id = ANY(");
+        query.push_bind(filters.channel_ids.clone());
+        query.push(
+            r#")
+            OR channel_users.folder_id IN (SELECT folders.id FROM folders
+                                           WHERE folders.id = ANY("#,
+        );
+        query.push_bind(filters.folder_ids.clone());
+        query.push(") OR folders.parent_id = ANY(");
+        query.push_bind(filters.folder_ids.clone());
+        query.push(")))");
+    }
+
+    if !filters.tag_ids.is_empty() {
+        query.push(
+            r#"
+            AND EXISTS (SELECT 1 FROM users_items_tags
+                        WHERE users_items_tags.user_id = users_items.user_id
+                        AND users_items_tags.item_id = users_items.item_id
+                        AND users_items_tags.tag_id = ANY("#,
+        );
+        query.push_bind(filters.tag_ids.clone());
+        query.push("))");
+    }
+
+    // Same expression as the items_search index
+    if let Some(search) = &filters.search {
+        query.push(
+            r#"
+            AND to_tsvector('simple', COALESCE(items.title, '') || ' ' || COALESCE(items.content, ''))
+                @@ websearch_to_tsquery('simple', "#,
+        );
+        query.push_bind(search.clone());
+        query.push(")");
+    }
 }
 
 #[cfg(test)]
@@ -288,7 +469,7 @@ mod tests {
 
     #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
     async fn basic_without_filter(pool: Pool) -> Result<()> {
-        let page = get_items_of_user(&pool, None, None, None, 1, 1, 20).await?;
+        let page = get_items_of_user(&pool, &ItemFilters::default(), 1, 1, 20).await?;
 
         assert_that!(page.page_size()).is_equal_to(&20);
         assert_that!(page.total_pages()).is_equal_to(&4);
@@ -302,7 +483,17 @@ mod tests {
 
     #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
     async fn basic_channel_filter(pool: Pool) -> Result<()> {
-        let page = get_items_of_user(&pool, Some(1), None, None, 1, 1, 20).await?;
+        let page = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                channel_id: Some(1),
+                ..Default::default()
+            },
+            1,
+            1,
+            20,
+        )
+        .await?;
 
         assert_that!(page.page_size()).is_equal_to(&20);
         assert_that!(page.total_pages()).is_equal_to(&3);
@@ -316,7 +507,17 @@ mod tests {
 
     #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
     async fn basic_read_filter(pool: Pool) -> Result<()> {
-        let page = get_items_of_user(&pool, None, Some(true), None, 1, 1, 20).await?;
+        let page = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                read: Some(true),
+                ..Default::default()
+            },
+            1,
+            1,
+            20,
+        )
+        .await?;
 
         assert_that!(page.page_size()).is_equal_to(&20);
         assert_that!(page.total_pages()).is_equal_to(&4);
@@ -325,7 +526,17 @@ mod tests {
         assert_that!(page.page_number()).is_equal_to(&1);
         assert_that!(page.content()).has_length(20);
 
-        let page = get_items_of_user(&pool, None, Some(false), None, 1, 1, 20).await?;
+        let page = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                read: Some(false),
+                ..Default::default()
+            },
+            1,
+            1,
+            20,
+        )
+        .await?;
 
         assert_that!(page.page_size()).is_equal_to(&20);
         assert_that!(page.total_pages()).is_equal_to(&1);
@@ -339,7 +550,17 @@ mod tests {
 
     #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
     async fn basic_starred_filter(pool: Pool) -> Result<()> {
-        let page = get_items_of_user(&pool, None, None, Some(true), 1, 1, 20).await?;
+        let page = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                starred: Some(true),
+                ..Default::default()
+            },
+            1,
+            1,
+            20,
+        )
+        .await?;
 
         assert_that!(page.page_size()).is_equal_to(&20);
         assert_that!(page.total_pages()).is_equal_to(&1);
@@ -348,7 +569,17 @@ mod tests {
         assert_that!(page.page_number()).is_equal_to(&1);
         assert_that!(page.content()).has_length(3);
 
-        let page = get_items_of_user(&pool, None, None, Some(false), 1, 1, 20).await?;
+        let page = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                starred: Some(false),
+                ..Default::default()
+            },
+            1,
+            1,
+            20,
+        )
+        .await?;
 
         assert_that!(page.page_size()).is_equal_to(&20);
         assert_that!(page.total_pages()).is_equal_to(&4);
@@ -362,7 +593,19 @@ mod tests {
 
     #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
     async fn basic_all_filters(pool: Pool) -> Result<()> {
-        let page = get_items_of_user(&pool, Some(1), Some(true), Some(true), 1, 1, 20).await?;
+        let page = get_items_of_user(
+            &pool,
+            &ItemFilters {
+                channel_id: Some(1),
+                read: Some(true),
+                starred: Some(true),
+                ..Default::default()
+            },
+            1,
+            1,
+            20,
+        )
+        .await?;
 
         assert_that!(page.page_size()).is_equal_to(&20);
         assert_that!(page.total_pages()).is_equal_to(&1);
@@ -373,4 +616,38 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn mark_filtered_items_as_read(pool: Pool) -> Result<()> {
+        let unread = ItemFilters {
+            read: Some(false),
+            ..Default::default()
+        };
+
+        mark_items_as_read(
+            &pool,
+            &ItemFilters {
+                channel_id: Some(1),
+                ..Default::default()
+            },
+            1,
+        )
+        .await?;
+        let page = get_items_of_user(&pool, &unread, 1, 1, 20).await?;
+        assert_that!(page.total_items()).is_equal_to(&16);
+
+        mark_items_as_read(
+            &pool,
+            &ItemFilters {
+                channel_id: Some(2),
+                ..Default::default()
+            },
+            1,
+        )
+        .await?;
+        let page = get_items_of_user(&pool, &unread, 1, 1, 20).await?;
+        assert_that!(page.total_items()).is_equal_to(&0);
+
+        Ok(())
+    }
 }
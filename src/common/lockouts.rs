@@ -0,0 +1,218 @@
+//! Brute-force protection of the password logins.
+//! Failed attempts are counted in redis per username and per IP address. Each failure is answered more slowly than the
+//! previous one, and once a threshold is reached, the account or the address is locked for a while.
+
+use std::time::Duration;
+
+use deadpool_redis::Pool as RedisPool;
+use redis::AsyncCommands;
+use tracing::{instrument, warn};
+
+use crate::common::model::{Lockout, LockoutKind};
+
+/// Longest delay before answering a failed login, in milliseconds
+const MAX_FAILURE_DELAY: u64 = 10_000;
+
+/// Thresholds and durations of the lockouts, from the configuration
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    /// Failed logins of an account before it is locked, 0 to never lock it
+    pub max_failures_per_account: u32,
+    /// Failed logins from an IP address before it is locked, 0 to never lock it
+    pub max_failures_per_ip: u32,
+    /// Number of seconds a lockout lasts, and the failures are remembered
+    pub lockout_duration: u64,
+    /// Delay before answering the first failed login, doubled at each new failure, in milliseconds
+    pub failure_delay: u64,
+}
+
+impl LockoutPolicy {
+    pub fn from_env() -> Self {
+        LockoutPolicy {
+            max_failures_per_account: env_or("LOGIN_MAX_FAILURES", 5),
+            max_failures_per_ip: env_or("LOGIN_MAX_FAILURES_PER_IP", 20),
+            lockout_duration: env_or("LOGIN_LOCKOUT_DURATION", 15 * 60),
+            failure_delay: env_or("LOGIN_FAILURE_DELAY", 250),
+        }
+    }
+
+    /// Delay before answering a failed login, given the number of failures so far
+    fn delay_after(&self, failures: u32) -> Duration {
+        let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+        Duration::from_millis(
+            self.failure_delay
+                .saturating_mul(factor)
+                .min(MAX_FAILURE_DELAY),
+        )
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(default)
+}
+
+fn kind_name(kind: LockoutKind) -> &'static str {
+    match kind {
+        LockoutKind::Account => "account",
+        LockoutKind::Ip => "ip",
+    }
+}
+
+fn failures_key(kind: LockoutKind, key: &str) -> String {
+    format!("login-failures.{}.{}", kind_name(kind), key)
+}
+
+fn lockout_key(kind: LockoutKind, key: &str) -> String {
+    format!("lockout.{}.{}", kind_name(kind), key)
+}
+
+/// What a login attempt may be locked by: the username, and the IP address when known
+fn targets<'a>(username: &'a str, ip: Option<&'a str>) -> Vec<(LockoutKind, &'a str)> {
+    let mut targets = vec![(LockoutKind::Account, username)];
+    if let Some(ip) = ip {
+        targets.push((LockoutKind::Ip, ip));
+    }
+    targets
+}
+
+/// Return the number of seconds left before a login attempt is accepted, if the account or the address is locked
+#[instrument(skip(redis))]
+pub async fn locked_for(
+    redis: &RedisPool,
+    username: &str,
+    ip: Option<&str>,
+) -> anyhow::Result<Option<i64>> {
+    let mut redis = redis.get().await?;
+
+    let mut locked_for = None;
+    for (kind, key) in targets(username, ip) {
+        // The TTL is negative when the key does not exist
+        let ttl: i64 = redis.ttl(lockout_key(kind, key)).await?;
+        if ttl > 0 {
+            locked_for = locked_for.max(Some(ttl));
+        }
+    }
+
+    Ok(locked_for)
+}
+
+/// Count a failed login, locking the account or the address once its threshold is reached.
+/// Return how long to wait before answering.
+#[instrument(skip(redis, policy))]
+pub async fn record_failure(
+    redis: &RedisPool,
+    policy: &LockoutPolicy,
+    username: &str,
+    ip: Option<&str>,
+) -> anyhow::Result<Duration> {
+    let mut redis = redis.get().await?;
+
+    let mut most_failures = 0;
+    for (kind, key) in targets(username, ip) {
+        let threshold = match kind {
+            LockoutKind::Account => policy.max_failures_per_account,
+            LockoutKind::Ip => policy.max_failures_per_ip,
+        };
+
+        let failures_key = failures_key(kind, key);
+        let failures: u32 = redis.incr(&failures_key, 1).await?;
+        redis
+            .expire::<_, usize>(&failures_key, policy.lockout_duration as i64)
+            .await?;
+        most_failures = most_failures.max(failures);
+
+        if threshold > 0 && failures >= threshold {
+            warn!(
+                "Locking {} {} after {} failed logins",
+                kind_name(kind),
+                key,
+                failures
+            );
+            redis
+                .set_ex::<_, _, ()>(lockout_key(kind, key), failures, policy.lockout_duration)
+                .await?;
+            redis.del::<_, usize>(&failures_key).await?;
+        }
+    }
+
+    Ok(policy.delay_after(most_failures))
+}
+
+/// Forget the failed logins of an account, once its password is given
+#[instrument(skip(redis))]
+pub async fn record_success(redis: &RedisPool, username: &str) -> anyhow::Result<()> {
+    redis
+        .get()
+        .await?
+        .del::<_, usize>(failures_key(LockoutKind::Account, username))
+        .await?;
+
+    Ok(())
+}
+
+/// List the current lockouts
+#[instrument(skip(redis))]
+pub async fn list_lockouts(redis: &RedisPool) -> anyhow::Result<Vec<Lockout>> {
+    let mut redis = redis.get().await?;
+
+    let mut lockouts = vec![];
+    for redis_key in redis.keys::<_, Vec<String>>("lockout.*").await? {
+        let (kind, key) = if let Some(key) = redis_key.strip_prefix("lockout.account.") {
+            (LockoutKind::Account, key)
+        } else if let Some(key) = redis_key.strip_prefix("lockout.ip.") {
+            (LockoutKind::Ip, key)
+        } else {
+            continue;
+        };
+
+        let expires_in: i64 = redis.ttl(&redis_key).await?;
+        if expires_in > 0 {
+            lockouts.push(Lockout {
+                kind,
+                key: key.to_owned(),
+                expires_in,
+            });
+        }
+    }
+    lockouts.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Ok(lockouts)
+}
+
+/// Lift the lockout of an account or an address, along with its failed logins
+#[instrument(skip(redis))]
+pub async fn clear_lockout(redis: &RedisPool, kind: LockoutKind, key: &str) -> anyhow::Result<()> {
+    redis
+        .get()
+        .await?
+        .del::<_, usize>(&[lockout_key(kind, key), failures_key(kind, key)])
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_doubles_up_to_the_limit() {
+        let policy = LockoutPolicy {
+            max_failures_per_account: 5,
+            max_failures_per_ip: 20,
+            lockout_duration: 900,
+            failure_delay: 250,
+        };
+
+        assert_eq!(policy.delay_after(1), Duration::from_millis(250));
+        assert_eq!(policy.delay_after(2), Duration::from_millis(500));
+        assert_eq!(policy.delay_after(4), Duration::from_millis(2000));
+        assert_eq!(
+            policy.delay_after(100),
+            Duration::from_millis(MAX_FAILURE_DELAY)
+        );
+    }
+}
@@ -1,17 +1,40 @@
 use deadpool_redis::{Config, Pool as Redis, Runtime};
+use sha2::{Digest, Sha256};
 use sqlx::postgres::PgPoolOptions;
 pub use sqlx::Error as DbError;
 pub use sqlx::PgPool as Pool;
 
 pub mod channels;
+pub mod counters;
+pub mod digests;
 pub mod email;
+pub mod encryption;
 pub mod errors;
+pub mod events;
+pub mod exports;
+pub mod fever;
+pub mod folders;
+pub mod greader;
+pub mod imports;
+pub mod invitations;
 pub mod items;
+pub mod lockouts;
 pub mod model;
+pub mod nextcloud;
 pub mod observability;
+pub mod oidc;
+pub mod output_feeds;
 pub mod password;
+pub mod password_policy;
+pub mod personal_tokens;
 pub mod rss;
+pub mod rules;
+pub mod saved_searches;
+pub mod sessions;
+pub mod tags;
+pub mod two_factor;
 pub mod users;
+pub mod webhooks;
 
 /// Build the Postgres connection
 pub async fn init_postgres_connection() -> Pool {
@@ -26,8 +49,24 @@ pub async fn init_postgres_connection() -> Pool {
 }
 
 pub fn init_redis_connection() -> Redis {
-    let url = std::env::var("REDIS_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1"));
-    let cfg = Config::from_url(url);
+    let cfg = Config::from_url(redis_url());
     cfg.create_pool(Some(Runtime::Tokio1))
         .expect("Could not connect to redis")
 }
+
+/// URL of the redis server
+pub fn redis_url() -> String {
+    std::env::var("REDIS_URL").unwrap_or_else(|_| String::from("redis://127.0.0.1"))
+}
+
+/// URL the API is publicly reached at, without trailing slash
+pub fn public_url() -> String {
+    std::env::var("PUBLIC_URL")
+        .map(|url| url.trim_end_matches('/').to_owned())
+        .unwrap_or_else(|_| String::from("http://localhost:8080"))
+}
+
+/// Hex SHA-256 of a secret, stored in its place to look it up without keeping it
+pub fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
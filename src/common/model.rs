@@ -2,6 +2,7 @@ use std::fmt::Debug;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
 use sqlx::FromRow;
 
 /// Error associated to a channel
@@ -27,6 +28,21 @@ pub struct UsersChannel {
     pub items_read: Option<i64>,
     pub failure_count: i32,
     pub disabled: bool,
+    pub folder_id: Option<i32>,
+    pub hide_from_river: bool,
+    pub default_sort: ItemsSort,
+}
+
+/// Sort order of the items
+#[derive(sqlx::Type, Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "items_sort", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ItemsSort {
+    /// Most recent items first
+    #[default]
+    Newest,
+    /// Oldest items first
+    Oldest,
 }
 
 /// A HaRss user
@@ -39,6 +55,9 @@ pub struct User {
     pub role: UserRole,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email_verified: Option<bool>,
+    #[serde(skip)]
+    pub token_version: i32,
+    pub disabled: bool,
 }
 
 #[derive(sqlx::Type, Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -58,6 +77,8 @@ pub struct Channel {
     pub registration_timestamp: DateTime<Utc>,
     pub failure_count: i32,
     pub disabled: bool,
+    pub site_url: Option<String>,
+    pub description: Option<String>,
 }
 
 /// Page of elements
@@ -101,6 +122,10 @@ where
         &self.content
     }
 
+    pub fn into_content(self) -> Vec<T> {
+        self.content
+    }
+
     pub fn page_number(&self) -> &u64 {
         &self.page_number
     }
@@ -138,6 +163,7 @@ pub struct UserItem {
     pub channel_name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notes: Option<String>,
+    pub tags: Vec<String>,
 }
 
 /// RSS Item representation to be inserted in the database
@@ -147,11 +173,401 @@ pub struct NewItem {
     pub title: Option<String>,
     pub url: Option<String>,
     pub content: Option<String>,
+    pub author: Option<String>,
     pub fetch_timestamp: DateTime<Utc>,
     pub publish_timestamp: Option<DateTime<Utc>>,
     pub channel_id: i32,
 }
 
+/// A user's tag, along with the number of items it is attached to
+#[derive(Debug, Serialize)]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    pub items_count: i64,
+}
+
+/// A user's folder, grouping subscriptions
+#[derive(Debug, Serialize)]
+pub struct Folder {
+    pub id: i32,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<i32>,
+}
+
+/// Unread and starred counters of a user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Counters {
+    /// Total number of unread items.
+    pub unread: i64,
+    /// Total number of starred items.
+    pub starred: i64,
+    /// Counters of each subscribed channel.
+    pub channels: Vec<ChannelCounters>,
+    /// Counters of each folder, including its sub folders.
+    pub folders: Vec<FolderCounters>,
+    /// Counters of each saved search.
+    #[serde(default)]
+    pub streams: Vec<StreamCounters>,
+}
+
+/// Unread and starred counters of a single channel
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelCounters {
+    pub channel_id: i32,
+    pub folder_id: Option<i32>,
+    pub unread: i64,
+    pub starred: i64,
+}
+
+/// Unread and starred counters of a single folder
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderCounters {
+    pub folder_id: i32,
+    pub unread: i64,
+    pub starred: i64,
+}
+
+/// Unread counter of a saved search
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamCounters {
+    pub stream_id: i32,
+    pub unread: i64,
+}
+
+/// Status of an OPML import
+#[derive(sqlx::Type, Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "import_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Progress report of an OPML import
+#[derive(Debug, Serialize)]
+pub struct Import {
+    pub id: i32,
+    pub status: ImportStatus,
+    /// Number of feeds found in the OPML document.
+    pub total: i32,
+    /// Number of feeds processed so far, failed ones included.
+    pub processed: i32,
+    pub start_timestamp: DateTime<Utc>,
+    pub end_timestamp: Option<DateTime<Utc>>,
+    pub failures: Vec<ImportFailure>,
+}
+
+/// A feed that could not be imported, and why
+#[derive(Debug, Serialize)]
+pub struct ImportFailure {
+    pub url: String,
+    pub reason: String,
+}
+
+/// A webhook of a user, called when new items are fetched
+#[derive(Debug, Serialize)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    /// Only call the webhook for the items of this channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<i32>,
+    /// Only call the webhook for the items containing this keyword.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyword: Option<String>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// An attempt to deliver a webhook
+#[derive(Debug, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub attempt: i32,
+    pub delivery_timestamp: DateTime<Utc>,
+    /// Number of items sent.
+    pub items_count: i32,
+    /// HTTP status code of the response, if any.
+    pub status_code: Option<i32>,
+    /// Why no response was received.
+    pub error: Option<String>,
+}
+
+/// A named query of a user, read like a stream of items
+#[derive(Debug, Serialize)]
+pub struct SavedSearch {
+    pub id: i32,
+    pub name: String,
+    /// Full-text search terms.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terms: Option<String>,
+    /// Only the items of these channels, or of the channels of these folders.
+    pub channel_ids: Vec<i32>,
+    pub folder_ids: Vec<i32>,
+    /// Only the items attached to one of these tags.
+    pub tag_ids: Vec<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starred: Option<bool>,
+    /// Only the items published in the last days.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_days: Option<i32>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// Items republished by an output feed
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "output_feed_source", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFeedSource {
+    /// The starred items of the user
+    Starred,
+    /// The items attached to a tag
+    Tag,
+    /// The items of the channels of a folder
+    Folder,
+    /// The items matching a saved search
+    SavedSearch,
+}
+
+/// A feed republishing some items of a user, readable by anyone knowing its token
+#[derive(Debug, Serialize)]
+pub struct OutputFeed {
+    pub id: i32,
+    pub token: String,
+    pub name: String,
+    pub source: OutputFeedSource,
+    /// Id of the tag, of the folder or of the saved search.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_id: Option<i32>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// Field of an item a filter rule is matched against
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "rule_field", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RuleField {
+    Title,
+    Content,
+    Author,
+    Url,
+}
+
+/// How the pattern of a filter rule is matched
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "rule_matching", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RuleMatching {
+    /// The field contains the pattern, ignoring the case
+    Substring,
+    /// The field matches the pattern as a regular expression
+    Regex,
+}
+
+/// What a filter rule does with the matching items
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "rule_action", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RuleAction {
+    MarkRead,
+    Star,
+    /// Attach the tag of the rule
+    Tag,
+    /// Mark as read and remove from the items lists
+    Hide,
+}
+
+/// A rule of a user, applied to the new items of their channels
+#[derive(Debug, Serialize)]
+pub struct FilterRule {
+    pub id: i32,
+    /// Only apply the rule to the items of this channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<i32>,
+    pub field: RuleField,
+    pub matching: RuleMatching,
+    pub pattern: String,
+    pub action: RuleAction,
+    /// Tag attached by the `tag` action.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_id: Option<i32>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// An existing item matched by a filter rule
+#[derive(Debug, Serialize)]
+pub struct MatchedItem {
+    pub id: i32,
+    pub channel_id: i32,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub publish_timestamp: Option<DateTime<Utc>>,
+}
+
+/// How often a digest is sent
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "digest_frequency", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum DigestFrequency {
+    Daily,
+    Weekly,
+}
+
+/// Items listed in a digest
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "digest_content", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum DigestContent {
+    /// The unread items
+    Unread,
+    /// The starred items
+    Starred,
+    /// The unread items of the channels of a folder
+    Folder,
+}
+
+/// Email digest preferences of a user. The address it is sent to is never returned.
+#[derive(Debug, Serialize)]
+pub struct Digest {
+    pub frequency: DigestFrequency,
+    pub content: DigestContent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folder_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_sent_timestamp: Option<DateTime<Utc>>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// Two-factor authentication state of a user
+#[derive(Debug, Serialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// Whether HTTP Basic authentication, and the other password based logins, are still accepted
+    pub allow_basic_auth: bool,
+    pub recovery_codes_left: i64,
+}
+
+/// What a personal token is allowed to do. All the scopes allow reading.
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[sqlx(type_name = "token_scope", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// Read only
+    Read,
+    /// Change the state, notes and tags of the items
+    Items,
+    /// Manage the subscriptions, folders, rules, streams, webhooks and output feeds
+    Subscriptions,
+    /// Manage the account, and keep the admin role of the user
+    Admin,
+}
+
+impl PgHasArrayType for TokenScope {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_token_scope")
+    }
+}
+
+/// A personal token of a user. The token itself is only returned once, at its creation.
+#[derive(Debug, Serialize)]
+pub struct PersonalToken {
+    pub id: i32,
+    pub name: String,
+    /// First characters of the token, to recognize it.
+    pub prefix: String,
+    pub scopes: Vec<TokenScope>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration_timestamp: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_timestamp: Option<DateTime<Utc>>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// An invitation to register. The code itself is only returned once, at its creation.
+#[derive(Debug, Serialize)]
+pub struct Invitation {
+    pub id: i32,
+    /// The user who created the invitation
+    pub user_id: i32,
+    /// First characters of the code, to recognize it.
+    pub prefix: String,
+    /// Role of the users registering with the invitation
+    pub role: UserRole,
+    /// URLs of the channels the users registering with the invitation are subscribed to
+    pub channels: Vec<String>,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expiration_timestamp: DateTime<Utc>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// A session of a user, opened at login and kept alive by its refresh tokens
+#[derive(Debug, Serialize)]
+pub struct Session {
+    pub id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_address: Option<String>,
+    pub creation_timestamp: DateTime<Utc>,
+    pub last_use_timestamp: DateTime<Utc>,
+    pub expiration_timestamp: DateTime<Utc>,
+}
+
+/// A user as listed to the admins
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub id: i32,
+    pub username: String,
+    pub role: UserRole,
+    pub disabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    pub creation_timestamp: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_login_timestamp: Option<DateTime<Utc>>,
+    /// Number of channels the user is subscribed to
+    pub subscriptions: i64,
+}
+
+/// A rule of the password policy
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordRule {
+    TooShort,
+    TooLong,
+    ContainsUsername,
+    TooWeak,
+    Breached,
+}
+
+/// A rule of the password policy broken by a new password
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordViolation {
+    pub rule: PasswordRule,
+    pub detail: String,
+}
+
+/// What a lockout applies to
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockoutKind {
+    Account,
+    Ip,
+}
+
+/// A temporary lockout, after too many failed logins for an account or from an IP address
+#[derive(Debug, Serialize)]
+pub struct Lockout {
+    pub kind: LockoutKind,
+    pub key: String,
+    pub expires_in: i64,
+}
+
 #[derive(Debug, Eq, PartialEq, Serialize)]
 pub struct FoundRssChannel {
     url: String,
@@ -0,0 +1,340 @@
+//! Storage side of the Nextcloud News API compatibility layer.
+//! See <https://nextcloud.github.io/news/api/api-v1-3/> for the specification.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::Result;
+use tracing::instrument;
+
+use crate::common::Pool;
+
+/// A Nextcloud News feed, which is a HaRSS channel with the user's metadata
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewsFeed {
+    pub id: i32,
+    pub url: String,
+    pub title: String,
+    pub favicon_link: Option<String>,
+    pub added: i64,
+    pub folder_id: Option<i32>,
+    pub unread_count: i64,
+    pub ordering: i32,
+    pub link: Option<String>,
+    pub pinned: bool,
+    pub update_error_count: i32,
+    pub last_update_error: Option<String>,
+}
+
+/// A Nextcloud News item, with the user's read and starred status
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewsItem {
+    pub id: i32,
+    pub guid: String,
+    pub guid_hash: String,
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub pub_date: i64,
+    pub body: String,
+    pub feed_id: i32,
+    pub unread: bool,
+    pub starred: bool,
+    pub rtl: bool,
+    pub last_modified: i64,
+    pub fingerprint: String,
+    pub content_hash: String,
+}
+
+/// The items a request applies to, from the `type` and `id` parameters
+#[derive(Debug, PartialEq, Eq)]
+pub enum Selection {
+    Feed(i32),
+    /// Items of a folder and its sub folders. The folder `0` is the root one, i.e. the feeds without folder.
+    Folder(i32),
+    Starred,
+    All,
+}
+
+impl Selection {
+    /// Build a selection from the Nextcloud `type` and `id` parameters
+    pub fn from_type(item_type: u8, id: i32) -> Option<Self> {
+        match item_type {
+            0 => Some(Selection::Feed(id)),
+            1 => Some(Selection::Folder(id)),
+            2 => Some(Selection::Starred),
+            3 => Some(Selection::All),
+            _ => None,
+        }
+    }
+
+    fn ids(&self) -> (Option<i32>, Option<i32>, bool) {
+        match self {
+            Selection::Feed(id) => (Some(*id), None, false),
+            Selection::Folder(id) => (None, Some(*id), false),
+            Selection::Starred => (None, None, true),
+            Selection::All => (None, None, false),
+        }
+    }
+}
+
+/// Parameters of an items request
+#[derive(Debug)]
+pub struct ItemsQuery {
+    pub selection: Selection,
+    /// Include the read items.
+    pub get_read: bool,
+    /// Maximum number of items, all of them if `None`.
+    pub batch_size: Option<i64>,
+    /// Only return the items older than this one, or newer if `oldest_first`.
+    pub offset: Option<i32>,
+    pub oldest_first: bool,
+    /// Only return the items created or updated since this date.
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Return the feeds of the user
+#[instrument(skip(db))]
+pub async fn get_feeds(db: &Pool, user_id: i32) -> Result<Vec<NewsFeed>> {
+    sqlx::query_as!(
+        NewsFeed,
+        r#"
+        SELECT      channels.id,
+                    channels.url,
+                    channel_users.name AS title,
+                    NULL::text AS favicon_link,
+                    FLOOR(EXTRACT(EPOCH FROM channel_users.registration_timestamp))::bigint AS "added!",
+                    channel_users.folder_id,
+                    (SELECT COUNT(*) FROM users_items
+                     WHERE users_items.user_id = channel_users.user_id
+                     AND users_items.channel_id = channels.id
+                     AND users_items.read = false) AS "unread_count!",
+                    0 AS "ordering!",
+                    channels.site_url AS link,
+                    false AS "pinned!",
+                    channels.failure_count AS update_error_count,
+                    (SELECT error_reason FROM channels_errors
+                     WHERE channels_errors.channel_id = channels.id
+                     ORDER BY error_timestamp DESC LIMIT 1) AS last_update_error
+        FROM        channels
+                    JOIN channel_users ON channel_users.channel_id = channels.id
+        WHERE       channel_users.user_id = $1
+        ORDER BY    channels.id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Return the number of starred items of the user
+#[instrument(skip(db))]
+pub async fn count_starred(db: &Pool, user_id: i32) -> Result<i64> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM users_items WHERE user_id = $1 AND starred = true
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Return the id of the newest item of the user
+#[instrument(skip(db))]
+pub async fn newest_item_id(db: &Pool, user_id: i32) -> Result<Option<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT MAX(item_id) FROM users_items WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Return the items of the user matching the query, ordered by id
+#[instrument(skip(db))]
+pub async fn get_items(db: &Pool, user_id: i32, query: &ItemsQuery) -> Result<Vec<NewsItem>> {
+    let (feed_id, folder_id, starred) = query.selection.ids();
+
+    sqlx::query_as!(
+        NewsItem,
+        r#"
+        SELECT      items.id,
+                    COALESCE(items.guid, items.id::text) AS "guid!",
+                    MD5(COALESCE(items.guid, items.id::text)) AS "guid_hash!",
+                    items.url,
+                    items.title,
+                    items.author,
+                    FLOOR(EXTRACT(EPOCH FROM COALESCE(items.publish_timestamp, items.fetch_timestamp)))::bigint AS "pub_date!",
+                    COALESCE(items.content, '') AS "body!",
+                    items.channel_id AS feed_id,
+                    NOT users_items.read AS "unread!",
+                    users_items.starred,
+                    false AS "rtl!",
+                    FLOOR(EXTRACT(EPOCH FROM users_items.updated_timestamp))::bigint AS "last_modified!",
+                    MD5(COALESCE(items.title, '') || COALESCE(items.url, '') || COALESCE(items.content, '')) AS "fingerprint!",
+                    MD5(COALESCE(items.content, '')) AS "content_hash!"
+        FROM        items
+                    JOIN users_items ON users_items.item_id = items.id
+                    JOIN channel_users ON channel_users.channel_id = users_items.channel_id
+                                      AND channel_users.user_id = users_items.user_id
+        WHERE       users_items.user_id = $1
+        AND         users_items.hidden = false
+        AND         ($2::integer IS NULL OR items.channel_id = $2)
+        AND         ($3::integer IS NULL
+                     OR ($3 = 0 AND channel_users.folder_id IS NULL)
+                     OR channel_users.folder_id IN (SELECT id FROM folders WHERE id = $3 OR parent_id = $3))
+        AND         ($4 = false OR users_items.starred = true)
+        AND         ($5 = true OR users_items.read = false)
+        AND         ($6::integer IS NULL OR CASE WHEN $7 THEN items.id > $6 ELSE items.id < $6 END)
+        AND         ($8::timestamptz IS NULL OR users_items.updated_timestamp >= $8)
+        ORDER BY    CASE WHEN $7 THEN items.id ELSE -items.id END
+        LIMIT       $9
+        "#,
+        user_id,
+        feed_id,
+        folder_id,
+        starred,
+        query.get_read,
+        query.offset,
+        query.oldest_first,
+        query.last_modified,
+        query.batch_size
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Mark as read the selected items of the user, up to the given item id, returning the ids of the items marked
+#[instrument(skip(db))]
+pub async fn mark_as_read(
+    db: &Pool,
+    user_id: i32,
+    selection: &Selection,
+    newest_item_id: i32,
+) -> Result<Vec<i32>> {
+    let (feed_id, folder_id, starred) = selection.ids();
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE  users_items SET read = true
+        FROM    channel_users
+        WHERE   channel_users.channel_id = users_items.channel_id
+        AND     channel_users.user_id = users_items.user_id
+        AND     users_items.user_id = $1
+        AND     users_items.item_id <= $2
+        AND     ($3::integer IS NULL OR users_items.channel_id = $3)
+        AND     ($4::integer IS NULL
+                 OR ($4 = 0 AND channel_users.folder_id IS NULL)
+                 OR channel_users.folder_id IN (SELECT id FROM folders WHERE id = $4 OR parent_id = $4))
+        AND     ($5 = false OR users_items.starred = true)
+        AND     users_items.read = false
+        RETURNING users_items.item_id
+        "#,
+        user_id,
+        newest_item_id,
+        feed_id,
+        folder_id,
+        starred
+    )
+    .fetch_all(db)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::folders::{create_folder, set_channel_folder};
+    use crate::common::items::set_item_read;
+
+    use super::*;
+
+    fn query(selection: Selection) -> ItemsQuery {
+        ItemsQuery {
+            selection,
+            get_read: true,
+            batch_size: None,
+            offset: None,
+            oldest_first: false,
+            last_modified: None,
+        }
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_get_items(pool: Pool) -> Result<()> {
+        let all = get_items(&pool, 1, &query(Selection::All)).await?;
+        assert_that!(all).has_length(78);
+        assert_that!(all[0].id).is_greater_than(all[1].id);
+
+        let page = get_items(
+            &pool,
+            1,
+            &ItemsQuery {
+                batch_size: Some(10),
+                offset: Some(all[4].id),
+                ..query(Selection::All)
+            },
+        )
+        .await?;
+        assert_that!(page).has_length(10);
+        assert_that!(page[0].id).is_equal_to(all[5].id);
+
+        let unread = get_items(
+            &pool,
+            1,
+            &ItemsQuery {
+                get_read: false,
+                ..query(Selection::Feed(2))
+            },
+        )
+        .await?;
+        assert_that!(unread).has_length(16);
+
+        let news = create_folder(&pool, 1, "News", None).await.unwrap();
+        set_channel_folder(&pool, 1, 2, Some(news)).await.unwrap();
+        let in_folder = get_items(&pool, 1, &query(Selection::Folder(news))).await?;
+        let at_root = get_items(&pool, 1, &query(Selection::Folder(0))).await?;
+        assert_that!(in_folder.len() + at_root.len()).is_equal_to(78);
+        assert_that!(at_root.iter().all(|item| item.feed_id == 1)).is_true();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_updated_items(pool: Pool) -> Result<()> {
+        let since = Utc::now();
+        let item_id = get_items(&pool, 1, &query(Selection::All)).await?[0].id;
+        set_item_read(&pool, 1, vec![item_id], true).await?;
+        set_item_read(&pool, 1, vec![item_id], false).await?;
+
+        let updated = get_items(
+            &pool,
+            1,
+            &ItemsQuery {
+                last_modified: Some(since),
+                ..query(Selection::All)
+            },
+        )
+        .await?;
+        assert_that!(updated).has_length(1);
+        assert_that!(updated[0].id).is_equal_to(item_id);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_mark_as_read(pool: Pool) -> Result<()> {
+        let newest = newest_item_id(&pool, 1).await?.unwrap();
+        mark_as_read(&pool, 1, &Selection::Feed(2), newest).await?;
+
+        let feeds = get_feeds(&pool, 1).await?;
+        let feed = feeds.iter().find(|feed| feed.id == 2).unwrap();
+        assert_that!(feed.unread_count).is_equal_to(0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,724 @@
+//! Single sign-on with an OpenID Connect identity provider, with the authorization code flow and PKCE.
+//! The ID token is received straight from the token endpoint of the provider, so TLS stands for its signature
+//! (OpenID Connect Core 1.0, 3.1.3.7). Its issuer, audience, expiration and nonce are still checked.
+
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use deadpool_redis::Pool as RedisPool;
+use once_cell::sync::Lazy;
+use rand::distributions::{Alphanumeric, DistString};
+use redis::AsyncCommands;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tracing::{debug_span, info, instrument, Instrument};
+use uuid::Uuid;
+
+use crate::common::errors::OidcError;
+use crate::common::model::{User, UserRole};
+use crate::common::password::encode_password;
+use crate::common::{public_url, Pool};
+
+/// Number of seconds left to come back from the identity provider
+pub const LOGIN_TTL: u64 = 10 * 60;
+
+/// Cookie binding a started login to the browser it was started from
+pub const STATE_COOKIE: &str = "harss_oidc_state";
+
+static CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .user_agent("HaRSS single sign-on (+https://github.com/fistons/rss-aggregator)")
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Could not build the single sign-on client")
+});
+
+/// Configuration of the identity provider
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<Secret<String>>,
+    pub scopes: String,
+    /// Claim holding the username, matched against the harss accounts
+    pub username_claim: String,
+    /// Claim holding the groups of the user
+    pub groups_claim: String,
+    /// Group whose members are admins. If None, the roles are managed in harss.
+    pub admin_group: Option<String>,
+    /// Whether the unknown users get an account at their first login
+    pub provisioning: bool,
+    /// Whether an identity is linked to the account with the same username, unless it is an admin or uses 2FA
+    pub link_existing_accounts: bool,
+    pub redirect_url: String,
+}
+
+impl OidcConfig {
+    /// Read the configuration from the environment, returning None if single sign-on is not configured
+    pub fn from_env() -> Option<Self> {
+        let issuer = std::env::var("OIDC_ISSUER").ok()?;
+        let client_id = std::env::var("OIDC_CLIENT_ID").ok()?;
+
+        Some(OidcConfig {
+            issuer: issuer.trim_end_matches('/').to_owned(),
+            client_id,
+            client_secret: std::env::var("OIDC_CLIENT_SECRET").ok().map(Secret::new),
+            scopes: std::env::var("OIDC_SCOPES").unwrap_or_else(|_| String::from("openid profile")),
+            username_claim: std::env::var("OIDC_USERNAME_CLAIM")
+                .unwrap_or_else(|_| String::from("preferred_username")),
+            groups_claim: std::env::var("OIDC_GROUPS_CLAIM")
+                .unwrap_or_else(|_| String::from("groups")),
+            admin_group: std::env::var("OIDC_ADMIN_GROUP").ok(),
+            provisioning: std::env::var("OIDC_PROVISIONING")
+                .map(|x| x.parse().unwrap_or(true))
+                .unwrap_or(true),
+            link_existing_accounts: std::env::var("OIDC_LINK_EXISTING_ACCOUNTS")
+                .map(|x| x.parse().unwrap_or(false))
+                .unwrap_or(false),
+            redirect_url: format!("{}/api/v1/auth/oidc/callback", public_url()),
+        })
+    }
+}
+
+/// The endpoints of the identity provider, from its discovery document
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+/// A login started on the identity provider, kept until the user comes back
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PendingLogin {
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// The identity returned by the provider
+#[derive(Debug)]
+pub struct Identity {
+    pub subject: String,
+    pub username: String,
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    access_token: Option<String>,
+}
+
+/// Start a login, returning the URL of the identity provider to redirect the user to, the state identifying the
+/// login, and the login to keep until the user comes back
+#[instrument]
+pub async fn start_login(config: &OidcConfig) -> Result<(String, String, PendingLogin), OidcError> {
+    let metadata = discover(config).await?;
+
+    let state = Uuid::new_v4().simple().to_string();
+    let login = PendingLogin {
+        nonce: Uuid::new_v4().simple().to_string(),
+        code_verifier: Alphanumeric.sample_string(&mut rand::thread_rng(), 64),
+    };
+    let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(login.code_verifier.as_bytes()));
+
+    let query: String = form_urlencoded::Serializer::new(String::new())
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_url)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &login.nonce)
+        .append_pair("code_challenge", &code_challenge)
+        .append_pair("code_challenge_method", "S256")
+        .finish();
+    let separator = if metadata.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    Ok((
+        format!("{}{}{}", metadata.authorization_endpoint, separator, query),
+        state,
+        login,
+    ))
+}
+
+/// Exchange the authorization code for the identity of the user
+#[instrument(skip(code, login))]
+pub async fn exchange_code(
+    config: &OidcConfig,
+    code: &str,
+    login: &PendingLogin,
+) -> Result<Identity, OidcError> {
+    let metadata = discover(config).await?;
+
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &config.redirect_url),
+        ("client_id", &config.client_id),
+        ("code_verifier", &login.code_verifier),
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.expose_secret()));
+    }
+
+    let response = CLIENT
+        .post(&metadata.token_endpoint)
+        .form(&form)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(OidcError::ProviderError(format!(
+            "The token endpoint answered with HTTP status {}",
+            response.status().as_u16()
+        )));
+    }
+    let tokens: TokenResponse = response.json().await?;
+
+    let mut claims = check_id_token(config, &metadata, &tokens.id_token, &login.nonce)?;
+    let subject = claims
+        .get("sub")
+        .and_then(Value::as_str)
+        .map(String::from)
+        .ok_or(OidcError::InvalidIdToken("no subject"))?;
+
+    // The ID token may only hold the minimal claims, the others are asked to the userinfo endpoint
+    let missing_claims = !claims.contains_key(&config.username_claim)
+        || (config.admin_group.is_some() && !claims.contains_key(&config.groups_claim));
+    if let (true, Some(endpoint), Some(access_token)) = (
+        missing_claims,
+        &metadata.userinfo_endpoint,
+        &tokens.access_token,
+    ) {
+        let response = CLIENT
+            .get(endpoint)
+            .bearer_auth(access_token)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(OidcError::ProviderError(format!(
+                "The userinfo endpoint answered with HTTP status {}",
+                response.status().as_u16()
+            )));
+        }
+        let userinfo: Map<String, Value> = response.json().await?;
+        if userinfo.get("sub").and_then(Value::as_str) != Some(subject.as_str()) {
+            return Err(OidcError::ProviderError(String::from(
+                "The userinfo subject does not match the ID token",
+            )));
+        }
+        for (claim, value) in userinfo {
+            claims.entry(claim).or_insert(value);
+        }
+    }
+
+    let username = claims
+        .get(&config.username_claim)
+        .and_then(Value::as_str)
+        .filter(|username| !username.is_empty())
+        .map(String::from)
+        .ok_or_else(|| OidcError::MissingClaim(config.username_claim.clone()))?;
+    let groups = match claims.get(&config.groups_claim) {
+        Some(Value::Array(groups)) => groups
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect(),
+        Some(Value::String(group)) => vec![group.clone()],
+        _ => vec![],
+    };
+
+    Ok(Identity {
+        subject,
+        username,
+        groups,
+    })
+}
+
+/// Return the account of the identity, linking it by username if allowed, or creating it.
+/// The admins and the users with two-factor authentication are never linked: whoever controls the username on the
+/// identity provider would take their account over.
+/// When an admin group is configured, the role of the user follows their membership.
+#[instrument(skip(db))]
+pub async fn link_account(
+    db: &Pool,
+    config: &OidcConfig,
+    identity: &Identity,
+) -> Result<User, OidcError> {
+    let mut transaction = db.begin().await?;
+
+    let linked = sqlx::query_as!(
+        User,
+        r#"
+        SELECT id, username, password, role as "role: UserRole", email_verified, token_version, disabled
+        FROM users WHERE oidc_subject = $1
+        "#,
+        identity.subject
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let user = match linked {
+        Some(user) => user,
+        None => {
+            let existing = sqlx::query!(
+                r#"
+                SELECT  id,
+                        oidc_subject,
+                        role AS "role: UserRole",
+                        EXISTS(SELECT 1 FROM two_factor WHERE user_id = users.id AND enabled = true) AS "two_factor!"
+                FROM    users
+                WHERE   username = $1
+                "#,
+                identity.username
+            )
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+            match existing {
+                Some(existing) if existing.oidc_subject.is_some() => {
+                    return Err(OidcError::AlreadyLinked)
+                }
+                Some(existing)
+                    if !config.link_existing_accounts
+                        || existing.role == UserRole::Admin
+                        || existing.two_factor =>
+                {
+                    return Err(OidcError::NotLinkable)
+                }
+                Some(existing) => {
+                    info!(
+                        "Linking user {} to the identity {}",
+                        existing.id, identity.subject
+                    );
+                    sqlx::query_as!(
+                        User,
+                        r#"
+                        UPDATE users SET oidc_subject = $2 WHERE id = $1
+                        RETURNING id, username, password, role as "role: UserRole", email_verified, token_version, disabled
+                        "#,
+                        existing.id,
+                        identity.subject
+                    )
+                    .fetch_one(&mut *transaction)
+                    .await?
+                }
+                None if config.provisioning => {
+                    info!(
+                        "Creating user {} for the identity {}",
+                        identity.username, identity.subject
+                    );
+                    // The password is never given: the user logs in with the identity provider
+                    let password = Secret::new(format!(
+                        "{}{}",
+                        Uuid::new_v4().simple(),
+                        Uuid::new_v4().simple()
+                    ));
+                    sqlx::query_as!(
+                        User,
+                        r#"
+                        INSERT INTO users (username, password, role, email_verified, oidc_subject)
+                        VALUES ($1, $2, $3, false, $4)
+                        RETURNING id, username, password, role as "role: UserRole", email_verified, token_version, disabled
+                        "#,
+                        identity.username,
+                        encode_password(&password).await,
+                        UserRole::Basic as UserRole,
+                        identity.subject
+                    )
+                    .fetch_one(&mut *transaction)
+                    .await?
+                }
+                None => return Err(OidcError::UnknownAccount),
+            }
+        }
+    };
+
+    if user.disabled {
+        return Err(OidcError::DisabledAccount);
+    }
+
+    let role = match &config.admin_group {
+        Some(admin_group) if identity.groups.contains(admin_group) => UserRole::Admin,
+        Some(_) => UserRole::Basic,
+        None => user.role.clone(),
+    };
+
+    let user = if role != user.role {
+        info!("Changing the role of user {} to {:?}", user.id, role);
+        // The access tokens issued with the previous role are revoked
+        sqlx::query_as!(
+            User,
+            r#"
+            UPDATE users SET role = $2, token_version = token_version + 1 WHERE id = $1
+            RETURNING id, username, password, role as "role: UserRole", email_verified, token_version, disabled
+            "#,
+            user.id,
+            role as UserRole
+        )
+        .fetch_one(&mut *transaction)
+        .await?
+    } else {
+        user
+    };
+
+    transaction.commit().await?;
+
+    Ok(user)
+}
+
+/// Keep a started login until the user comes back from the identity provider
+#[instrument(skip(redis, login))]
+pub async fn save_pending_login(
+    redis: &RedisPool,
+    state: &str,
+    login: &PendingLogin,
+) -> anyhow::Result<()> {
+    redis
+        .get()
+        .await?
+        .set_ex::<_, _, ()>(
+            pending_login_key(state),
+            serde_json::to_string(login)?,
+            LOGIN_TTL,
+        )
+        .instrument(debug_span!("store_oidc_login_in_redis"))
+        .await?;
+
+    Ok(())
+}
+
+/// Return, and forget, the login started with the state
+#[instrument(skip(redis))]
+pub async fn take_pending_login(
+    redis: &RedisPool,
+    state: &str,
+) -> anyhow::Result<Option<PendingLogin>> {
+    let key = pending_login_key(state);
+    let mut redis = redis.get().await?;
+
+    let value: Option<String> = redis
+        .get(&key)
+        .instrument(debug_span!("get_oidc_login_in_redis"))
+        .await?;
+    redis.del::<_, usize>(&key).await?;
+
+    Ok(value
+        .map(|value| serde_json::from_str(&value))
+        .transpose()?)
+}
+
+fn pending_login_key(state: &str) -> String {
+    format!("oidc-login.{}", state)
+}
+
+async fn discover(config: &OidcConfig) -> Result<ProviderMetadata, OidcError> {
+    let response = CLIENT
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer
+        ))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(OidcError::ProviderError(format!(
+            "The discovery document answered with HTTP status {}",
+            response.status().as_u16()
+        )));
+    }
+
+    let metadata: ProviderMetadata = response.json().await?;
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        return Err(OidcError::ProviderError(String::from(
+            "The discovery document is for another issuer",
+        )));
+    }
+
+    Ok(metadata)
+}
+
+/// Check the ID token, returning its claims
+fn check_id_token(
+    config: &OidcConfig,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+) -> Result<Map<String, Value>, OidcError> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .ok_or(OidcError::InvalidIdToken("malformed token"))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .map_err(|_| OidcError::InvalidIdToken("malformed token"))?;
+    let claims: Map<String, Value> = serde_json::from_slice(&payload)
+        .map_err(|_| OidcError::InvalidIdToken("malformed token"))?;
+
+    if claims.get("iss").and_then(Value::as_str) != Some(metadata.issuer.as_str()) {
+        return Err(OidcError::InvalidIdToken("wrong issuer"));
+    }
+
+    let audience_matches = match claims.get("aud") {
+        Some(Value::String(audience)) => *audience == config.client_id,
+        Some(Value::Array(audiences)) => audiences
+            .iter()
+            .any(|audience| audience.as_str() == Some(config.client_id.as_str())),
+        _ => false,
+    };
+    if !audience_matches {
+        return Err(OidcError::InvalidIdToken("wrong audience"));
+    }
+
+    let expiration = claims.get("exp").and_then(Value::as_i64).unwrap_or(0);
+    if expiration < Utc::now().timestamp() {
+        return Err(OidcError::InvalidIdToken("expired token"));
+    }
+
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(OidcError::InvalidIdToken("wrong nonce"));
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use speculoos::prelude::*;
+    use wiremock::matchers::{body_string_contains, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::common::{init_redis_connection, users};
+
+    fn config(issuer: &str) -> OidcConfig {
+        OidcConfig {
+            issuer: issuer.to_owned(),
+            client_id: String::from("harss"),
+            client_secret: None,
+            scopes: String::from("openid profile"),
+            username_claim: String::from("preferred_username"),
+            groups_claim: String::from("groups"),
+            admin_group: Some(String::from("harss-admins")),
+            provisioning: true,
+            link_existing_accounts: true,
+            redirect_url: String::from("http://localhost:8080/api/v1/auth/oidc/callback"),
+        }
+    }
+
+    fn id_token(claims: Value) -> String {
+        format!(
+            "{}.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    async fn mock_issuer() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/.well-known/openid-configuration"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "issuer": server.uri(),
+                "authorization_endpoint": format!("{}/authorize", server.uri()),
+                "token_endpoint": format!("{}/token", server.uri()),
+                "userinfo_endpoint": format!("{}/userinfo", server.uri()),
+            })))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_code_exchange() {
+        let server = mock_issuer().await;
+        let config = config(&server.uri());
+
+        let (url, state, login) = start_login(&config).await.unwrap();
+        assert_that!(url.starts_with(&format!("{}/authorize?", server.uri()))).is_true();
+        assert_that!(url.contains(&format!("state={}", state))).is_true();
+        assert_that!(url.contains("code_challenge_method=S256")).is_true();
+
+        Mock::given(method("POST"))
+            .and(path("/token"))
+            .and(body_string_contains("code=c0de"))
+            .and(body_string_contains(format!(
+                "code_verifier={}",
+                login.code_verifier
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "4cc355",
+                "token_type": "Bearer",
+                "id_token": id_token(json!({
+                    "iss": server.uri(),
+                    "aud": "harss",
+                    "sub": "a1b2",
+                    "exp": Utc::now().timestamp() + 60,
+                    "nonce": login.nonce,
+                })),
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/userinfo"))
+            .and(header("Authorization", "Bearer 4cc355"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "sub": "a1b2",
+                "preferred_username": "jane",
+                "groups": ["staff", "harss-admins"],
+            })))
+            .mount(&server)
+            .await;
+
+        let identity = exchange_code(&config, "c0de", &login).await.unwrap();
+        assert_that!(identity.subject.as_str()).is_equal_to("a1b2");
+        assert_that!(identity.username.as_str()).is_equal_to("jane");
+        assert_that!(identity.groups).contains(String::from("harss-admins"));
+
+        let other_login = PendingLogin {
+            nonce: String::from("another"),
+            code_verifier: login.code_verifier.clone(),
+        };
+        assert!(matches!(
+            exchange_code(&config, "c0de", &other_login).await,
+            Err(OidcError::InvalidIdToken("wrong nonce"))
+        ));
+    }
+
+    #[test]
+    fn test_id_token_checks() {
+        let config = config("https://idp.example");
+        let metadata = ProviderMetadata {
+            issuer: String::from("https://idp.example"),
+            authorization_endpoint: String::from("https://idp.example/authorize"),
+            token_endpoint: String::from("https://idp.example/token"),
+            userinfo_endpoint: None,
+        };
+        let claims = json!({
+            "iss": "https://idp.example",
+            "aud": ["other", "harss"],
+            "sub": "a1b2",
+            "exp": Utc::now().timestamp() + 60,
+            "nonce": "n0nce",
+        });
+        assert_that!(check_id_token(
+            &config,
+            &metadata,
+            &id_token(claims.clone()),
+            "n0nce"
+        ))
+        .is_ok();
+
+        let mut wrong_audience = claims.clone();
+        wrong_audience["aud"] = json!("other");
+        assert!(matches!(
+            check_id_token(&config, &metadata, &id_token(wrong_audience), "n0nce"),
+            Err(OidcError::InvalidIdToken("wrong audience"))
+        ));
+
+        let mut expired = claims.clone();
+        expired["exp"] = json!(Utc::now().timestamp() - 60);
+        assert!(matches!(
+            check_id_token(&config, &metadata, &id_token(expired), "n0nce"),
+            Err(OidcError::InvalidIdToken("expired token"))
+        ));
+
+        let mut wrong_issuer = claims;
+        wrong_issuer["iss"] = json!("https://evil.example");
+        assert!(matches!(
+            check_id_token(&config, &metadata, &id_token(wrong_issuer), "n0nce"),
+            Err(OidcError::InvalidIdToken("wrong issuer"))
+        ));
+
+        assert!(matches!(
+            check_id_token(&config, &metadata, "garbage", "n0nce"),
+            Err(OidcError::InvalidIdToken("malformed token"))
+        ));
+    }
+
+    fn identity(subject: &str, username: &str, groups: &[&str]) -> Identity {
+        Identity {
+            subject: subject.to_owned(),
+            username: username.to_owned(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+        }
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_account_linking(pool: Pool) -> anyhow::Result<()> {
+        let mut config = config("https://idp.example");
+
+        // The existing account is linked by username, and its role follows the admin group
+        let user = link_account(
+            &pool,
+            &config,
+            &identity("s1", "john_doe", &["harss-admins"]),
+        )
+        .await?;
+        assert_that!(user.id).is_equal_to(2);
+        assert_that!(user.role).is_equal_to(UserRole::Admin);
+        assert_that!(user.token_version).is_equal_to(1);
+
+        // Then by subject, even when renamed on the identity provider
+        let user = link_account(&pool, &config, &identity("s1", "johnny", &[])).await?;
+        assert_that!(user.id).is_equal_to(2);
+        assert_that!(user.role).is_equal_to(UserRole::Basic);
+
+        // An account is only linked to one identity
+        assert!(matches!(
+            link_account(&pool, &config, &identity("s2", "john_doe", &[])).await,
+            Err(OidcError::AlreadyLinked)
+        ));
+
+        // Unknown users get an account
+        let user = link_account(&pool, &config, &identity("s3", "jane", &[])).await?;
+        assert_that!(user.username.as_str()).is_equal_to("jane");
+        assert_that!(user.role).is_equal_to(UserRole::Basic);
+
+        config.provisioning = false;
+        assert!(matches!(
+            link_account(&pool, &config, &identity("s4", "nobody", &[])).await,
+            Err(OidcError::UnknownAccount)
+        ));
+
+        // The admins are never linked, nor are the accounts when linking is not allowed
+        assert!(matches!(
+            link_account(&pool, &config, &identity("s5", "root", &[])).await,
+            Err(OidcError::NotLinkable)
+        ));
+        config.link_existing_accounts = false;
+        assert!(matches!(
+            link_account(&pool, &config, &identity("s6", "jane", &[])).await,
+            Err(OidcError::AlreadyLinked)
+        ));
+        users::create_user(
+            &init_redis_connection(),
+            &pool,
+            "joe",
+            &Secret::new(String::from("p4ssword")),
+            &None,
+            &UserRole::Basic,
+        )
+        .await?;
+        assert!(matches!(
+            link_account(&pool, &config, &identity("s7", "joe", &[])).await,
+            Err(OidcError::NotLinkable)
+        ));
+
+        // Without an admin group, the role is left untouched
+        config.admin_group = None;
+        let user = link_account(&pool, &config, &identity("s3", "jane", &[])).await?;
+        assert_that!(user.role).is_equal_to(UserRole::Basic);
+
+        Ok(())
+    }
+}
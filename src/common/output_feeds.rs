@@ -0,0 +1,469 @@
+//! Output feeds, republishing some items of a user as RSS 2.0, Atom or JSON Feed documents.
+//! They are read without authentication, through an unguessable token which can be revoked.
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Result;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::common::items::{get_items_of_user, ItemFilters};
+use crate::common::model::{OutputFeed, OutputFeedSource, UserItem};
+use crate::common::saved_searches::{get_saved_search, to_item_filters};
+use crate::common::{DbError, Pool};
+
+/// Number of items published in an output feed
+const FEED_SIZE: u64 = 50;
+
+/// Format of an output feed
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Rss => "application/rss+xml; charset=utf-8",
+            OutputFormat::Atom => "application/atom+xml; charset=utf-8",
+            OutputFormat::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+}
+
+/// An output feed found by its token, along with its owner
+#[derive(Debug)]
+pub struct PublishedFeed {
+    pub user_id: i32,
+    pub name: String,
+    pub source: OutputFeedSource,
+    pub source_id: Option<i32>,
+    pub creation_timestamp: DateTime<Utc>,
+}
+
+/// Create an output feed for the user, returning its id and its token
+#[instrument(skip(db))]
+pub async fn create_output_feed(
+    db: &Pool,
+    user_id: i32,
+    name: &str,
+    source: OutputFeedSource,
+    source_id: Option<i32>,
+) -> Result<(i32, String)> {
+    let token = new_token();
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO output_feeds (user_id, token, name, source, source_id)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        user_id,
+        token,
+        name,
+        source as OutputFeedSource,
+        source_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok((id, token))
+}
+
+/// List the output feeds of the user
+#[instrument(skip(db))]
+pub async fn list_output_feeds(db: &Pool, user_id: i32) -> Result<Vec<OutputFeed>> {
+    sqlx::query_as!(
+        OutputFeed,
+        r#"
+        SELECT id, token, name, source AS "source: OutputFeedSource", source_id, creation_timestamp
+        FROM output_feeds
+        WHERE user_id = $1
+        ORDER BY id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Delete an output feed of the user, revoking its token
+#[instrument(skip(db))]
+pub async fn delete_output_feed(db: &Pool, user_id: i32, feed_id: i32) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM output_feeds WHERE id = $1 AND user_id = $2
+        "#,
+        feed_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Replace the token of an output feed of the user, revoking the previous one
+#[instrument(skip(db))]
+pub async fn regenerate_token(db: &Pool, user_id: i32, feed_id: i32) -> Result<String> {
+    let token = new_token();
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE output_feeds SET token = $1 WHERE id = $2 AND user_id = $3
+        "#,
+        token,
+        feed_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(token)
+}
+
+/// Find an output feed by its token
+#[instrument(skip(db, token))]
+pub async fn get_by_token(db: &Pool, token: &str) -> Result<Option<PublishedFeed>> {
+    sqlx::query_as!(
+        PublishedFeed,
+        r#"
+        SELECT user_id, name, source AS "source: OutputFeedSource", source_id, creation_timestamp
+        FROM output_feeds
+        WHERE token = $1
+        "#,
+        token
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Check that the tag, the folder or the saved search an output feed is built from belongs to the user
+#[instrument(skip(db))]
+pub async fn source_exists(
+    db: &Pool,
+    user_id: i32,
+    source: OutputFeedSource,
+    source_id: i32,
+) -> Result<bool> {
+    let exists = match source {
+        OutputFeedSource::Starred => Some(true),
+        OutputFeedSource::Tag => {
+            sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM tags WHERE id = $1 AND user_id = $2)"#,
+                source_id,
+                user_id
+            )
+            .fetch_one(db)
+            .await?
+        }
+        OutputFeedSource::Folder => {
+            sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM folders WHERE id = $1 AND user_id = $2)"#,
+                source_id,
+                user_id
+            )
+            .fetch_one(db)
+            .await?
+        }
+        OutputFeedSource::SavedSearch => {
+            sqlx::query_scalar!(
+                r#"SELECT EXISTS(SELECT 1 FROM saved_searches WHERE id = $1 AND user_id = $2)"#,
+                source_id,
+                user_id
+            )
+            .fetch_one(db)
+            .await?
+        }
+    };
+
+    Ok(exists.unwrap_or(false))
+}
+
+/// Return the last items published by an output feed, most recent first
+#[instrument(skip(db, feed))]
+pub async fn get_published_items(db: &Pool, feed: &PublishedFeed) -> Result<Vec<UserItem>> {
+    let filters = match feed.source {
+        OutputFeedSource::Starred => ItemFilters {
+            starred: Some(true),
+            ..Default::default()
+        },
+        OutputFeedSource::Tag => ItemFilters {
+            tag_id: feed.source_id,
+            ..Default::default()
+        },
+        OutputFeedSource::Folder => ItemFilters {
+            folder_id: feed.source_id,
+            ..Default::default()
+        },
+        OutputFeedSource::SavedSearch => {
+            let search_id = feed.source_id.ok_or(DbError::RowNotFound)?;
+            to_item_filters(&get_saved_search(db, feed.user_id, search_id).await?)
+        }
+    };
+
+    Ok(get_items_of_user(db, &filters, feed.user_id, 1, FEED_SIZE)
+        .await?
+        .into_content())
+}
+
+/// Date of the feed: the fetch of its most recent item, or its creation
+fn last_modified(feed: &PublishedFeed, items: &[UserItem]) -> DateTime<Utc> {
+    items
+        .iter()
+        .map(|item| item.fetch_timestamp)
+        .max()
+        .unwrap_or(feed.creation_timestamp)
+}
+
+/// Render the feed in the given format. `self_url` is the URL the feed is read from.
+pub fn render(
+    feed: &PublishedFeed,
+    items: &[UserItem],
+    format: OutputFormat,
+    self_url: &str,
+) -> String {
+    let updated = last_modified(feed, items);
+
+    match format {
+        OutputFormat::Rss => render_rss(feed, items, self_url, updated),
+        OutputFormat::Atom => render_atom(feed, items, self_url, updated),
+        OutputFormat::Json => render_json(feed, items, self_url),
+    }
+}
+
+fn render_rss(
+    feed: &PublishedFeed,
+    items: &[UserItem],
+    self_url: &str,
+    updated: DateTime<Utc>,
+) -> String {
+    let items = items
+        .iter()
+        .map(|item| rss::Item {
+            title: item.title.clone(),
+            link: item.url.clone(),
+            description: item.content.clone(),
+            guid: Some(rss::Guid {
+                value: item_guid(item),
+                permalink: false,
+            }),
+            pub_date: item.publish_timestamp.map(|date| date.to_rfc2822()),
+            source: Some(rss::Source {
+                url: self_url.to_owned(),
+                title: Some(item.channel_name.clone()),
+            }),
+            categories: item
+                .tags
+                .iter()
+                .map(|tag| rss::Category {
+                    name: tag.clone(),
+                    domain: None,
+                })
+                .collect(),
+            ..Default::default()
+        })
+        .collect();
+
+    rss::Channel {
+        title: feed.name.clone(),
+        link: self_url.to_owned(),
+        description: feed.name.clone(),
+        last_build_date: Some(updated.to_rfc2822()),
+        generator: Some(String::from("HaRSS")),
+        items,
+        ..Default::default()
+    }
+    .to_string()
+}
+
+fn render_atom(
+    feed: &PublishedFeed,
+    items: &[UserItem],
+    self_url: &str,
+    updated: DateTime<Utc>,
+) -> String {
+    let entries = items
+        .iter()
+        .map(|item| atom_syndication::Entry {
+            title: atom_syndication::Text::plain(item.title.clone().unwrap_or_default()),
+            id: item_guid(item),
+            updated: item
+                .publish_timestamp
+                .unwrap_or(item.fetch_timestamp)
+                .into(),
+            published: item.publish_timestamp.map(Into::into),
+            links: item
+                .url
+                .iter()
+                .map(|url| atom_syndication::Link {
+                    href: url.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+            content: item
+                .content
+                .as_ref()
+                .map(|content| atom_syndication::Content {
+                    value: Some(content.clone()),
+                    content_type: Some(String::from("html")),
+                    ..Default::default()
+                }),
+            categories: item
+                .tags
+                .iter()
+                .map(|tag| atom_syndication::Category {
+                    term: tag.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        })
+        .collect();
+
+    atom_syndication::Feed {
+        title: atom_syndication::Text::plain(feed.name.clone()),
+        id: self_url.to_owned(),
+        updated: updated.into(),
+        links: vec![atom_syndication::Link {
+            href: self_url.to_owned(),
+            rel: String::from("self"),
+            ..Default::default()
+        }],
+        generator: Some(atom_syndication::Generator {
+            value: String::from("HaRSS"),
+            ..Default::default()
+        }),
+        entries,
+        ..Default::default()
+    }
+    .to_string()
+}
+
+/// Render the feed as a [JSON Feed 1.1](https://www.jsonfeed.org/version/1.1/) document
+fn render_json(feed: &PublishedFeed, items: &[UserItem], self_url: &str) -> String {
+    let items = items
+        .iter()
+        .map(|item| {
+            json!({
+                "id": item_guid(item),
+                "url": item.url,
+                "title": item.title,
+                "content_html": item.content,
+                "date_published": item.publish_timestamp,
+                "tags": item.tags,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.name,
+        "feed_url": self_url,
+        "items": items,
+    })
+    .to_string()
+}
+
+/// Identifier of an item in an output feed: its original GUID if any
+fn item_guid(item: &UserItem) -> String {
+    item.guid
+        .clone()
+        .or_else(|| item.url.clone())
+        .unwrap_or_else(|| format!("urn:harss:item:{}", item.id))
+}
+
+fn new_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    fn published_feed() -> PublishedFeed {
+        PublishedFeed {
+            user_id: 1,
+            name: String::from("My <starred> items"),
+            source: OutputFeedSource::Starred,
+            source_id: None,
+            creation_timestamp: Utc::now(),
+        }
+    }
+
+    fn item() -> UserItem {
+        UserItem {
+            id: 12,
+            guid: None,
+            title: Some(String::from("Rust & friends")),
+            url: Some(String::from("https://example.com/rust")),
+            content: Some(String::from("<p>Hello</p>")),
+            fetch_timestamp: Utc::now(),
+            publish_timestamp: Some(Utc::now()),
+            read: false,
+            starred: true,
+            channel_id: 1,
+            channel_name: String::from("Example"),
+            notes: None,
+            tags: vec![String::from("rust")],
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let feed = published_feed();
+        let items = vec![item()];
+        let url = "https://harss.net/feeds/token/rss";
+
+        let rss = render(&feed, &items, OutputFormat::Rss, url);
+        let parsed = feed_rs::parser::parse(rss.as_bytes()).unwrap();
+        assert_that!(parsed.title.unwrap().content).is_equal_to(feed.name.clone());
+        assert_that!(parsed.entries).has_length(1);
+        assert_that!(parsed.entries[0].title.as_ref().unwrap().content)
+            .is_equal_to(String::from("Rust & friends"));
+
+        let atom = render(&feed, &items, OutputFormat::Atom, url);
+        let parsed = feed_rs::parser::parse(atom.as_bytes()).unwrap();
+        assert_that!(parsed.entries).has_length(1);
+        assert_that!(parsed.entries[0].id.as_str()).is_equal_to("https://example.com/rust");
+
+        let json = render(&feed, &items, OutputFormat::Json, url);
+        let parsed = feed_rs::parser::parse(json.as_bytes()).unwrap();
+        assert_that!(parsed.entries).has_length(1);
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_output_feeds(pool: Pool) -> Result<()> {
+        let (id, token) =
+            create_output_feed(&pool, 1, "Starred", OutputFeedSource::Starred, None).await?;
+        assert_that!(list_output_feeds(&pool, 1).await?).has_length(1);
+
+        let feed = get_by_token(&pool, &token).await?.unwrap();
+        assert_that!(feed.user_id).is_equal_to(1);
+        assert_that!(source_exists(&pool, 2, OutputFeedSource::Folder, 1).await?).is_false();
+
+        let new_token = regenerate_token(&pool, 1, id).await?;
+        assert_that!(get_by_token(&pool, &token).await?).is_none();
+        assert_that!(get_by_token(&pool, &new_token).await?).is_some();
+
+        assert_that!(delete_output_feed(&pool, 2, id).await).is_err();
+        delete_output_feed(&pool, 1, id).await?;
+        assert_that!(get_by_token(&pool, &new_token).await?).is_none();
+
+        Ok(())
+    }
+}
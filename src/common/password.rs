@@ -1,27 +1,269 @@
+//! Hashing of the passwords with Argon2id.
+//! The cost of the hashes comes from the configuration. The hashes made with a weaker cost, or another variant of
+//! Argon2, are still checked, and are replaced at the next successful login.
+//! Hashing takes a while on purpose, so it runs on the blocking threads, not to stall the workers of the server, and
+//! only a few hashes are computed at once, not to exhaust the memory and the CPU on a burst of logins.
+
 use argon2::password_hash::SaltString;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use once_cell::sync::Lazy;
 use rand_core::OsRng;
 use secrecy::{ExposeSecret, Secret};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{error, warn};
+
+/// Cost of the new hashes, read once from the configuration
+static PARAMETERS: Lazy<HashingParameters> = Lazy::new(HashingParameters::from_env);
+
+/// Permits to hash or check a password, one per hash computed at once
+static PERMITS: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(concurrency()));
+
+/// Hash checked when the user is unknown, so that it takes as long as with a known one
+static DUMMY_HASH: Lazy<String> =
+    Lazy::new(|| hash_password(&PARAMETERS, &Secret::new(uuid::Uuid::new_v4().to_string())));
+
+/// Cost of the new hashes, from the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashingParameters {
+    /// Memory used, in KiB
+    pub memory: u32,
+    /// Number of passes over the memory
+    pub iterations: u32,
+    /// Number of lanes
+    pub parallelism: u32,
+}
+
+impl Default for HashingParameters {
+    fn default() -> Self {
+        HashingParameters {
+            memory: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl HashingParameters {
+    /// Read the parameters from the environment, falling back to the defaults of Argon2 if they are not valid
+    pub fn from_env() -> Self {
+        let parameters = HashingParameters {
+            memory: env_or("PASSWORD_HASH_MEMORY", Params::DEFAULT_M_COST),
+            iterations: env_or("PASSWORD_HASH_ITERATIONS", Params::DEFAULT_T_COST),
+            parallelism: env_or("PASSWORD_HASH_PARALLELISM", Params::DEFAULT_P_COST),
+        };
+
+        match parameters.params() {
+            Ok(_) => parameters,
+            Err(e) => {
+                warn!(
+                    "Invalid password hashing parameters {:?}, using the defaults: {}",
+                    parameters, e
+                );
+                HashingParameters::default()
+            }
+        }
+    }
+
+    fn params(&self) -> argon2::Result<Params> {
+        Params::new(self.memory, self.iterations, self.parallelism, None)
+    }
+
+    fn hasher(&self) -> Argon2<'static> {
+        Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            self.params().unwrap_or_default(),
+        )
+    }
+
+    /// Whether a hash is made with another variant or version of Argon2, or is cheaper than these parameters
+    fn is_outdated(&self, hash: &PasswordHash) -> bool {
+        let Ok(params) = Params::try_from(hash) else {
+            return true;
+        };
+
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() < self.memory
+            || params.t_cost() < self.iterations
+            || params.p_cost() < self.parallelism
+    }
+}
+
+/// Outcome of a password check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordCheck {
+    /// The password does not match, or the hash could not be read
+    Invalid,
+    /// The password matches
+    Valid,
+    /// The password matches, but its hash should be replaced by a stronger one
+    Outdated,
+}
+
+impl PasswordCheck {
+    pub fn is_valid(&self) -> bool {
+        *self != PasswordCheck::Invalid
+    }
+}
 
 /// Encode the password using argon2
 #[tracing::instrument(skip_all)]
-pub fn encode_password(password: &Secret<String>) -> String {
-    let argon2 = Argon2::default();
+pub async fn encode_password(password: &Secret<String>) -> String {
+    let _permit = acquire_permit().await;
+    let password = password.clone();
+
+    tokio::task::spawn_blocking(move || hash_password(&PARAMETERS, &password))
+        .await
+        .expect("The password hashing task failed")
+}
+
+/// Check if the candidate match the hashed user password
+#[tracing::instrument(skip_all)]
+pub async fn verify_password(user_password: &str, candidate: &Secret<String>) -> PasswordCheck {
+    let _permit = acquire_permit().await;
+    let user_password = user_password.to_owned();
+    let candidate = candidate.clone();
+
+    tokio::task::spawn_blocking(move || check_password(&PARAMETERS, &user_password, &candidate))
+        .await
+        .expect("The password checking task failed")
+}
+
+/// Spend the time of a password check, for a user that does not exist. Always invalid.
+#[tracing::instrument(skip_all)]
+pub async fn verify_dummy_password(candidate: &Secret<String>) -> PasswordCheck {
+    let _permit = acquire_permit().await;
+    let candidate = candidate.clone();
+
+    tokio::task::spawn_blocking(move || check_password(&PARAMETERS, &DUMMY_HASH, &candidate))
+        .await
+        .expect("The password checking task failed");
+    PasswordCheck::Invalid
+}
+
+/// Wait for a permit to hash or check a password, released when dropped
+async fn acquire_permit() -> SemaphorePermit<'static> {
+    PERMITS
+        .acquire()
+        .await
+        .expect("The password hashing permits are never closed")
+}
+
+fn hash_password(parameters: &HashingParameters, password: &Secret<String>) -> String {
     let salt = SaltString::generate(&mut OsRng);
 
-    let password_hash = argon2
+    parameters
+        .hasher()
         .hash_password(password.expose_secret().as_bytes(), &salt)
         .unwrap()
-        .to_string();
-
-    password_hash
+        .to_string()
 }
 
-/// Check if the candidate match the hashed user password
-#[tracing::instrument(skip_all)]
-pub fn verify_password(user_password: &str, candidate: &Secret<String>) -> bool {
-    let parsed_hash = PasswordHash::new(user_password).unwrap();
-    Argon2::default()
+fn check_password(
+    parameters: &HashingParameters,
+    user_password: &str,
+    candidate: &Secret<String>,
+) -> PasswordCheck {
+    let parsed_hash = match PasswordHash::new(user_password) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(e) => {
+            error!("Could not parse a stored password hash: {}", e);
+            return PasswordCheck::Invalid;
+        }
+    };
+
+    // The variant and the cost of the stored hash are used, not the configured ones
+    if Argon2::default()
         .verify_password(candidate.expose_secret().as_bytes(), &parsed_hash)
-        .is_ok()
+        .is_err()
+    {
+        PasswordCheck::Invalid
+    } else if parameters.is_outdated(&parsed_hash) {
+        PasswordCheck::Outdated
+    } else {
+        PasswordCheck::Valid
+    }
+}
+
+/// Number of passwords hashed or checked at once, from the configuration or else the number of CPUs
+fn concurrency() -> usize {
+    std::env::var("PASSWORD_HASH_CONCURRENCY")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|concurrency| *concurrency > 0)
+        .unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|parallelism| parallelism.get())
+                .unwrap_or(1)
+        })
+}
+
+fn env_or(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    /// Hash of "root" seeded by the init migration
+    const LEGACY_HASH: &str =
+        "$argon2i$v=19$m=4096,t=2,p=1$bGVwZXRpdGNlcmVib3M$MCSscpJ5MlsPvEpK7J5203kQ2tmdXKF5s2Oo47aQOyg";
+
+    #[test]
+    fn test_check_password() {
+        let parameters = HashingParameters {
+            memory: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let password = Secret::new(String::from("p4ssword"));
+        let hash = hash_password(&parameters, &password);
+        assert_that!(hash.as_str()).starts_with("$argon2id$v=19$m=1024,t=1,p=1$");
+
+        assert_that!(check_password(&parameters, &hash, &password))
+            .is_equal_to(PasswordCheck::Valid);
+        assert_that!(check_password(
+            &parameters,
+            &hash,
+            &Secret::new(String::from("wrong"))
+        ))
+        .is_equal_to(PasswordCheck::Invalid);
+
+        // Stronger parameters make the existing hashes outdated
+        let stronger = HashingParameters {
+            iterations: 2,
+            ..parameters.clone()
+        };
+        assert_that!(check_password(&stronger, &hash, &password))
+            .is_equal_to(PasswordCheck::Outdated);
+    }
+
+    #[test]
+    fn test_check_legacy_and_malformed_hashes() {
+        let parameters = HashingParameters {
+            memory: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let root = Secret::new(String::from("root"));
+
+        // Argon2i is not the variant in use anymore, even with a higher cost
+        assert_that!(check_password(&parameters, LEGACY_HASH, &root))
+            .is_equal_to(PasswordCheck::Outdated);
+        assert_that!(check_password(
+            &parameters,
+            LEGACY_HASH,
+            &Secret::new(String::from("wrong"))
+        ))
+        .is_equal_to(PasswordCheck::Invalid);
+
+        assert_that!(check_password(&parameters, "no", &root)).is_equal_to(PasswordCheck::Invalid);
+    }
 }
@@ -0,0 +1,228 @@
+//! Policy the new passwords must follow: a minimum and a maximum length, no username, a minimum strength estimated with zxcvbn, and
+//! optionally not being part of a known breach.
+//! The breached passwords are looked up in a local directory of SHA-1 ranges, laid out like the k-anonymity API of
+//! Have I Been Pwned: a file per uppercase 5 characters prefix of the hashes, holding `SUFFIX:COUNT` lines.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use secrecy::{ExposeSecret, Secret};
+use sha1::{Digest, Sha1};
+use tracing::instrument;
+
+use crate::common::errors::PasswordPolicyError;
+use crate::common::model::{PasswordRule, PasswordViolation};
+
+/// Number of characters of the SHA-1 prefix naming the range files
+const RANGE_PREFIX_LENGTH: usize = 5;
+
+/// Maximum number of characters of a password, zxcvbn getting very slow on long ones
+const MAX_LENGTH: usize = 256;
+
+/// Rules of the password policy, from the configuration
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    /// Minimum number of characters
+    pub min_length: usize,
+    /// Minimum zxcvbn score, from 0 to 4, 0 to accept any password
+    pub min_strength: u8,
+    /// Directory of the breached passwords ranges, if any
+    pub breached_list: Option<PathBuf>,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        PasswordPolicy {
+            min_length: std::env::var("PASSWORD_MIN_LENGTH")
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(8usize)
+                .max(1),
+            min_strength: std::env::var("PASSWORD_MIN_STRENGTH")
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or(2u8)
+                .min(4),
+            breached_list: std::env::var("PASSWORD_BREACHED_LIST")
+                .ok()
+                .map(PathBuf::from),
+        }
+    }
+}
+
+/// Check a new password of the user against the policy, returning all the rules it breaks
+#[instrument(skip(password))]
+pub async fn check_password(
+    password: &Secret<String>,
+    username: &str,
+) -> Result<(), PasswordPolicyError> {
+    let policy = PasswordPolicy::from_env();
+    let breached_list = policy.breached_list.clone();
+    let candidate = password.clone();
+    let username = username.to_owned();
+
+    // The strength estimation takes a while, so it runs on the blocking threads, not to stall the workers of the server
+    let mut violations = tokio::task::spawn_blocking(move || {
+        check_rules(&policy, candidate.expose_secret(), &username)
+    })
+    .await
+    .expect("The password policy task failed");
+    if let Some(breached_list) = &breached_list {
+        if is_breached(breached_list, password.expose_secret()).await? {
+            violations.push(PasswordViolation {
+                rule: PasswordRule::Breached,
+                detail: String::from("The password is part of a known data breach"),
+            });
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(PasswordPolicyError::Violations(violations))
+    }
+}
+
+/// Check the rules which do not need the breached passwords. A too long password is not checked any further.
+fn check_rules(policy: &PasswordPolicy, password: &str, username: &str) -> Vec<PasswordViolation> {
+    let mut violations = vec![];
+    let length = password.chars().count();
+
+    if length > MAX_LENGTH {
+        violations.push(PasswordViolation {
+            rule: PasswordRule::TooLong,
+            detail: format!(
+                "The password must be at most {} characters long",
+                MAX_LENGTH
+            ),
+        });
+        return violations;
+    }
+
+    if length < policy.min_length {
+        violations.push(PasswordViolation {
+            rule: PasswordRule::TooShort,
+            detail: format!(
+                "The password must be at least {} characters long",
+                policy.min_length
+            ),
+        });
+    }
+
+    if is_derived_from_username(password, username) {
+        violations.push(PasswordViolation {
+            rule: PasswordRule::ContainsUsername,
+            detail: String::from("The password cannot contain the username"),
+        });
+    } else if policy.min_strength > 0 {
+        // An empty password cannot be evaluated, but it is already too short anyway
+        let score = zxcvbn::zxcvbn(password, &[username])
+            .map(|entropy| entropy.score())
+            .unwrap_or(0);
+        if score < policy.min_strength {
+            violations.push(PasswordViolation {
+                rule: PasswordRule::TooWeak,
+                detail: String::from(
+                    "The password is too easy to guess, add some words or some unusual characters",
+                ),
+            });
+        }
+    }
+
+    violations
+}
+
+/// Whether the password contains the username, as is or reversed, regardless of the case
+fn is_derived_from_username(password: &str, username: &str) -> bool {
+    let username = username.trim().to_lowercase();
+    if username.chars().count() < 3 {
+        return false;
+    }
+
+    let password = password.to_lowercase();
+    let reversed: String = username.chars().rev().collect();
+    password.contains(&username) || password.contains(&reversed)
+}
+
+/// Look the SHA-1 hash of the password up in its range file. A missing range file holds no breached password.
+async fn is_breached(breached_list: &Path, password: &str) -> Result<bool, PasswordPolicyError> {
+    let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(RANGE_PREFIX_LENGTH);
+
+    let range = match tokio::fs::read_to_string(breached_list.join(prefix)).await {
+        Ok(range) => range,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(range.lines().any(|line| {
+        line.split(':')
+            .next()
+            .is_some_and(|candidate| candidate.trim().eq_ignore_ascii_case(suffix))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn rules(violations: Vec<PasswordViolation>) -> Vec<PasswordRule> {
+        violations
+            .into_iter()
+            .map(|violation| violation.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_check_rules() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            min_strength: 2,
+            breached_list: None,
+        };
+
+        assert_that!(rules(check_rules(&policy, "", "john_doe")))
+            .is_equal_to(vec![PasswordRule::TooShort, PasswordRule::TooWeak]);
+        assert_that!(rules(check_rules(&policy, "password", "john_doe")))
+            .is_equal_to(vec![PasswordRule::TooWeak]);
+        assert_that!(rules(check_rules(&policy, "John_Doe1984!", "john_doe")))
+            .is_equal_to(vec![PasswordRule::ContainsUsername]);
+        assert_that!(rules(check_rules(&policy, "eod_nhoj", "john_doe")))
+            .is_equal_to(vec![PasswordRule::ContainsUsername]);
+        assert_that!(check_rules(
+            &policy,
+            "correct horse battery staple",
+            "john_doe"
+        ))
+        .is_empty();
+        assert_that!(rules(check_rules(&policy, &"a".repeat(257), "john_doe")))
+            .is_equal_to(vec![PasswordRule::TooLong]);
+
+        let lenient = PasswordPolicy {
+            min_length: 1,
+            min_strength: 0,
+            breached_list: None,
+        };
+        assert_that!(check_rules(&lenient, "password", "john_doe")).is_empty();
+    }
+
+    #[tokio::test]
+    async fn test_breached_password() -> anyhow::Result<()> {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().simple().to_string());
+        std::fs::create_dir_all(&directory)?;
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        std::fs::write(
+            directory.join("5BAA6"),
+            "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9659365\r\n",
+        )?;
+
+        assert_that!(is_breached(&directory, "password").await?).is_true();
+        assert_that!(is_breached(&directory, "correct horse battery staple").await?).is_false();
+
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Personal tokens, authenticating scripts and integrations with the `Token` scheme.
+//! They are random enough to be stored as a plain SHA-256 hash, and checked without the cost of a password hash.
+
+use chrono::{DateTime, Utc};
+use sqlx::Result;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::common::model::{PersonalToken, TokenScope, UserRole};
+use crate::common::{hash_secret, DbError, Pool};
+
+/// Prefix of all the personal tokens, to spot them in scripts and in leaks
+const TOKEN_PREFIX: &str = "harss_";
+
+/// Number of characters of a token kept in clear, to recognize it
+const DISPLAYED_PREFIX_LENGTH: usize = 14;
+
+/// The owner of a personal token, along with its scopes
+#[derive(Debug)]
+pub struct TokenOwner {
+    pub user_id: i32,
+    pub username: String,
+    pub role: UserRole,
+    pub scopes: Vec<TokenScope>,
+}
+
+/// Create a personal token for the user, returning its id and the token itself
+#[instrument(skip(db))]
+pub async fn create_token(
+    db: &Pool,
+    user_id: i32,
+    name: &str,
+    scopes: &[TokenScope],
+    expiration_timestamp: Option<DateTime<Utc>>,
+) -> Result<(i32, String)> {
+    let token = format!(
+        "{}{}{}",
+        TOKEN_PREFIX,
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    );
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO personal_tokens (user_id, name, token_hash, prefix, scopes, expiration_timestamp)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+        user_id,
+        name,
+        hash_secret(&token),
+        &token[..DISPLAYED_PREFIX_LENGTH],
+        scopes as &[TokenScope],
+        expiration_timestamp
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok((id, token))
+}
+
+/// List the personal tokens of the user, expired ones included
+#[instrument(skip(db))]
+pub async fn list_tokens(db: &Pool, user_id: i32) -> Result<Vec<PersonalToken>> {
+    sqlx::query_as!(
+        PersonalToken,
+        r#"
+        SELECT      id,
+                    name,
+                    prefix,
+                    scopes AS "scopes: Vec<TokenScope>",
+                    expiration_timestamp,
+                    last_used_timestamp,
+                    creation_timestamp
+        FROM        personal_tokens
+        WHERE       user_id = $1
+        ORDER BY    id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Revoke a personal token of the user
+#[instrument(skip(db))]
+pub async fn revoke_token(db: &Pool, user_id: i32, token_id: i32) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM personal_tokens WHERE id = $1 AND user_id = $2
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Return the owner of a valid personal token, and record its use
+#[instrument(skip_all)]
+pub async fn use_token(db: &Pool, token: &str) -> Result<Option<TokenOwner>> {
+    if !token.starts_with(TOKEN_PREFIX) {
+        return Ok(None);
+    }
+
+    sqlx::query_as!(
+        TokenOwner,
+        r#"
+        UPDATE      personal_tokens
+        SET         last_used_timestamp = now()
+        FROM        users
+        WHERE       personal_tokens.token_hash = $1
+        AND         users.id = personal_tokens.user_id
+        AND         NOT users.disabled
+        AND         (personal_tokens.expiration_timestamp IS NULL OR personal_tokens.expiration_timestamp > now())
+        RETURNING   users.id AS user_id,
+                    users.username,
+                    users.role AS "role: UserRole",
+                    personal_tokens.scopes AS "scopes: Vec<TokenScope>"
+        "#,
+        hash_secret(token)
+    )
+    .fetch_optional(db)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_personal_tokens(pool: Pool) -> anyhow::Result<()> {
+        let (id, token) = create_token(
+            &pool,
+            1,
+            "Script",
+            &[TokenScope::Read, TokenScope::Items],
+            None,
+        )
+        .await?;
+        assert_that!(token.starts_with(TOKEN_PREFIX)).is_true();
+
+        let tokens = list_tokens(&pool, 1).await?;
+        assert_that!(tokens).has_length(1);
+        assert_that!(tokens[0].id).is_equal_to(id);
+        assert_that!(token.starts_with(&tokens[0].prefix)).is_true();
+        assert_that!(tokens[0].scopes).is_equal_to(vec![TokenScope::Read, TokenScope::Items]);
+        assert_that!(tokens[0].last_used_timestamp).is_none();
+
+        // Only the hash is stored
+        let stored =
+            sqlx::query_scalar!("SELECT token_hash FROM personal_tokens WHERE id = $1", id)
+                .fetch_one(&pool)
+                .await?;
+        assert_that!(stored).is_not_equal_to(&token);
+
+        let owner = use_token(&pool, &token).await?.unwrap();
+        assert_that!(owner.user_id).is_equal_to(1);
+        assert_that!(owner.role).is_equal_to(UserRole::Admin);
+        assert_that!(list_tokens(&pool, 1).await?[0].last_used_timestamp).is_some();
+
+        assert_that!(use_token(&pool, "harss_nope").await?).is_none();
+        assert_that!(use_token(&pool, &token[1..]).await?).is_none();
+
+        assert_that!(revoke_token(&pool, 2, id).await).is_err();
+        revoke_token(&pool, 1, id).await?;
+        assert_that!(use_token(&pool, &token).await?).is_none();
+        assert_that!(list_tokens(&pool, 1).await?).is_empty();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_expired_token(pool: Pool) -> anyhow::Result<()> {
+        let yesterday = Utc::now() - Duration::days(1);
+        let tomorrow = Utc::now() + Duration::days(1);
+        let (_, expired) =
+            create_token(&pool, 2, "Old", &[TokenScope::Read], Some(yesterday)).await?;
+        let (_, valid) = create_token(&pool, 2, "New", &[TokenScope::Read], Some(tomorrow)).await?;
+
+        assert_that!(use_token(&pool, &expired).await?).is_none();
+        assert_that!(use_token(&pool, &valid).await?.map(|owner| owner.user_id))
+            .is_equal_to(Some(2));
+
+        Ok(())
+    }
+}
@@ -72,6 +72,22 @@ pub async fn check_feed(url: &str) -> Result<Feed, RssParsingError> {
     Ok(feed_rs::parser::parse(&feed_content[..])?)
 }
 
+/// Return the URL of the website of the feed, if any
+pub fn site_url(feed: &Feed) -> Option<String> {
+    feed.links
+        .iter()
+        .find(|link| link.rel.as_deref().map_or(true, |rel| rel == "alternate"))
+        .map(|link| link.href.clone())
+}
+
+/// Return the description of the feed, if any
+pub fn description(feed: &Feed) -> Option<String> {
+    feed.description
+        .as_ref()
+        .map(|description| description.content.clone())
+        .filter(|description| !description.trim().is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use wiremock::matchers::{method, path};
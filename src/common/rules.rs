@@ -0,0 +1,465 @@
+//! Filter rules, acting on the new items of a user as soon as they are linked to them.
+
+use chrono::{DateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use tracing::{instrument, warn};
+
+use crate::common::errors::RuleError;
+use crate::common::items::{hide_items, set_item_read, set_item_starred};
+use crate::common::model::{FilterRule, MatchedItem, RuleAction, RuleField, RuleMatching};
+use crate::common::tags::attach_tag;
+use crate::common::Pool;
+
+/// Maximal size of a compiled regular expression, in bytes
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Number of items returned by a dry run
+const DRY_RUN_LIMIT: usize = 100;
+
+/// Number of items read at once by a dry run, while looking for matches
+const DRY_RUN_PAGE_SIZE: i64 = 500;
+
+/// Definition of a filter rule, to create or replace one
+#[derive(Debug)]
+pub struct RuleDefinition {
+    pub channel_id: Option<i32>,
+    pub field: RuleField,
+    pub matching: RuleMatching,
+    pub pattern: String,
+    pub action: RuleAction,
+    pub tag_id: Option<i32>,
+}
+
+/// The pattern of a rule, ready to be matched
+enum Matcher {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(matching: RuleMatching, pattern: &str) -> Result<Self, RuleError> {
+        if pattern.is_empty() {
+            return Err(RuleError::EmptyPattern);
+        }
+
+        Ok(match matching {
+            RuleMatching::Substring => Matcher::Substring(pattern.to_lowercase()),
+            RuleMatching::Regex => Matcher::Regex(
+                RegexBuilder::new(pattern)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()?,
+            ),
+        })
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Matcher::Substring(pattern) => text.to_lowercase().contains(pattern),
+            Matcher::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// An item of the user, as seen by the rules
+struct Candidate {
+    id: i32,
+    channel_id: i32,
+    title: Option<String>,
+    content: Option<String>,
+    author: Option<String>,
+    url: Option<String>,
+    publish_timestamp: Option<DateTime<Utc>>,
+}
+
+impl Candidate {
+    fn field(&self, field: RuleField) -> Option<&str> {
+        match field {
+            RuleField::Title => self.title.as_deref(),
+            RuleField::Content => self.content.as_deref(),
+            RuleField::Author => self.author.as_deref(),
+            RuleField::Url => self.url.as_deref(),
+        }
+    }
+
+    fn matches(&self, field: RuleField, matcher: &Matcher) -> bool {
+        self.field(field).is_some_and(|text| matcher.is_match(text))
+    }
+}
+
+/// Create a filter rule for the user, returning its id
+#[instrument(skip(db))]
+pub async fn create_rule(db: &Pool, user_id: i32, rule: &RuleDefinition) -> Result<i32, RuleError> {
+    validate(db, user_id, rule).await?;
+
+    Ok(sqlx::query_scalar!(
+        r#"
+        INSERT INTO filter_rules (user_id, channel_id, field, matching, pattern, action, tag_id)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id
+        "#,
+        user_id,
+        rule.channel_id,
+        rule.field as RuleField,
+        rule.matching as RuleMatching,
+        rule.pattern,
+        rule.action as RuleAction,
+        tag_of(rule)
+    )
+    .fetch_one(db)
+    .await?)
+}
+
+/// List the filter rules of the user
+#[instrument(skip(db))]
+pub async fn list_rules(db: &Pool, user_id: i32) -> sqlx::Result<Vec<FilterRule>> {
+    sqlx::query_as!(
+        FilterRule,
+        r#"
+        SELECT      id,
+                    channel_id,
+                    field AS "field: RuleField",
+                    matching AS "matching: RuleMatching",
+                    pattern,
+                    action AS "action: RuleAction",
+                    tag_id,
+                    creation_timestamp
+        FROM        filter_rules
+        WHERE       user_id = $1
+        ORDER BY    id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Replace a filter rule of the user
+#[instrument(skip(db))]
+pub async fn update_rule(
+    db: &Pool,
+    user_id: i32,
+    rule_id: i32,
+    rule: &RuleDefinition,
+) -> Result<(), RuleError> {
+    validate(db, user_id, rule).await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE  filter_rules
+        SET     channel_id = $1, field = $2, matching = $3, pattern = $4, action = $5, tag_id = $6
+        WHERE   id = $7 AND user_id = $8
+        "#,
+        rule.channel_id,
+        rule.field as RuleField,
+        rule.matching as RuleMatching,
+        rule.pattern,
+        rule.action as RuleAction,
+        tag_of(rule),
+        rule_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RuleError::NotFound("rule", rule_id));
+    }
+
+    Ok(())
+}
+
+/// Delete a filter rule of the user
+#[instrument(skip(db))]
+pub async fn delete_rule(db: &Pool, user_id: i32, rule_id: i32) -> Result<(), RuleError> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM filter_rules WHERE id = $1 AND user_id = $2
+        "#,
+        rule_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(RuleError::NotFound("rule", rule_id));
+    }
+
+    Ok(())
+}
+
+/// Return the existing items of the user the rule would match, most recent first.
+/// Nothing is changed.
+#[instrument(skip(db))]
+pub async fn dry_run(
+    db: &Pool,
+    user_id: i32,
+    rule: &RuleDefinition,
+) -> Result<Vec<MatchedItem>, RuleError> {
+    let matcher = validate(db, user_id, rule).await?;
+    let mut matched = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        let page = get_candidates(
+            db,
+            user_id,
+            rule.channel_id,
+            None,
+            Some(DRY_RUN_PAGE_SIZE),
+            offset,
+        )
+        .await?;
+        let is_last = (page.len() as i64) < DRY_RUN_PAGE_SIZE;
+
+        matched.extend(
+            page.into_iter()
+                .filter(|candidate| candidate.matches(rule.field, &matcher))
+                .take(DRY_RUN_LIMIT - matched.len())
+                .map(|candidate| MatchedItem {
+                    id: candidate.id,
+                    channel_id: candidate.channel_id,
+                    title: candidate.title,
+                    url: candidate.url,
+                    publish_timestamp: candidate.publish_timestamp,
+                }),
+        );
+        if is_last || matched.len() == DRY_RUN_LIMIT {
+            return Ok(matched);
+        }
+        offset += DRY_RUN_PAGE_SIZE;
+    }
+}
+
+/// Apply the rules of the user to the items of the channel linked to them at the given time
+#[instrument(skip(db))]
+pub async fn apply_rules(
+    db: &Pool,
+    user_id: i32,
+    channel_id: i32,
+    added_timestamp: &DateTime<Utc>,
+) -> sqlx::Result<()> {
+    let rules = list_rules(db, user_id)
+        .await?
+        .into_iter()
+        .filter(|rule| rule.channel_id.is_none() || rule.channel_id == Some(channel_id))
+        .collect::<Vec<_>>();
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let candidates = get_candidates(
+        db,
+        user_id,
+        Some(channel_id),
+        Some(added_timestamp),
+        None,
+        0,
+    )
+    .await?;
+    if candidates.is_empty() {
+        return Ok(());
+    }
+
+    for rule in rules {
+        let matcher = match Matcher::new(rule.matching, &rule.pattern) {
+            Ok(matcher) => matcher,
+            Err(error) => {
+                warn!("Skipping invalid rule {}: {}", rule.id, error);
+                continue;
+            }
+        };
+        let item_ids = candidates
+            .iter()
+            .filter(|candidate| candidate.matches(rule.field, &matcher))
+            .map(|candidate| candidate.id)
+            .collect::<Vec<_>>();
+        if item_ids.is_empty() {
+            continue;
+        }
+
+        match (rule.action, rule.tag_id) {
+            (RuleAction::MarkRead, _) => set_item_read(db, user_id, item_ids, true).await?,
+            (RuleAction::Star, _) => set_item_starred(db, user_id, item_ids, true).await?,
+            (RuleAction::Tag, Some(tag_id)) => attach_tag(db, user_id, tag_id, item_ids).await?,
+            (RuleAction::Tag, None) => {}
+            (RuleAction::Hide, _) => hide_items(db, user_id, item_ids).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Return the items of the user, optionally restricted to a channel and to the ones linked at a given time.
+/// Without a limit, all the items from the offset are returned.
+async fn get_candidates(
+    db: &Pool,
+    user_id: i32,
+    channel_id: Option<i32>,
+    added_timestamp: Option<&DateTime<Utc>>,
+    limit: Option<i64>,
+    offset: i64,
+) -> sqlx::Result<Vec<Candidate>> {
+    sqlx::query_as!(
+        Candidate,
+        r#"
+        SELECT      items.id,
+                    items.channel_id,
+                    items.title,
+                    items.content,
+                    items.author,
+                    items.url,
+                    items.publish_timestamp
+        FROM        users_items
+                    JOIN items ON items.id = users_items.item_id
+        WHERE       users_items.user_id = $1
+        AND         ($2::integer IS NULL OR users_items.channel_id = $2)
+        AND         ($3::timestamptz IS NULL OR users_items.added_timestamp = $3)
+        ORDER BY    items.publish_timestamp DESC NULLS LAST, items.id DESC
+        LIMIT       $4
+        OFFSET      $5
+        "#,
+        user_id,
+        channel_id,
+        added_timestamp,
+        limit,
+        offset
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Check that the rule is valid, and that its channel and tag belong to the user
+async fn validate(db: &Pool, user_id: i32, rule: &RuleDefinition) -> Result<Matcher, RuleError> {
+    let matcher = Matcher::new(rule.matching, &rule.pattern)?;
+
+    if let Some(channel_id) = rule.channel_id {
+        sqlx::query_scalar!(
+            r#"
+            SELECT channel_id FROM channel_users WHERE channel_id = $1 AND user_id = $2
+            "#,
+            channel_id,
+            user_id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(RuleError::NotFound("channel", channel_id))?;
+    }
+
+    if rule.action == RuleAction::Tag {
+        let tag_id = rule.tag_id.ok_or(RuleError::MissingTag)?;
+        sqlx::query_scalar!(
+            r#"
+            SELECT id FROM tags WHERE id = $1 AND user_id = $2
+            "#,
+            tag_id,
+            user_id
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(RuleError::NotFound("tag", tag_id))?;
+    }
+
+    Ok(matcher)
+}
+
+/// The tag is only kept for the tag action
+fn tag_of(rule: &RuleDefinition) -> Option<i32> {
+    match rule.action {
+        RuleAction::Tag => rule.tag_id,
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::items::{get_items_of_user, ItemFilters};
+
+    use super::*;
+
+    fn definition(field: RuleField, matching: RuleMatching, pattern: &str) -> RuleDefinition {
+        RuleDefinition {
+            channel_id: None,
+            field,
+            matching,
+            pattern: pattern.to_owned(),
+            action: RuleAction::Hide,
+            tag_id: None,
+        }
+    }
+
+    #[test]
+    fn test_matcher() {
+        let substring = Matcher::new(RuleMatching::Substring, "Sponsored").unwrap();
+        assert_that!(substring.is_match("[SPONSORED] Buy this")).is_true();
+        assert_that!(substring.is_match("Nothing to see")).is_false();
+
+        let regex = Matcher::new(RuleMatching::Regex, r"^\[(?i)ad\]").unwrap();
+        assert_that!(regex.is_match("[AD] Buy this")).is_true();
+        assert_that!(regex.is_match("Read [ad]")).is_false();
+
+        assert_that!(Matcher::new(RuleMatching::Regex, "(").is_err()).is_true();
+        assert_that!(Matcher::new(RuleMatching::Substring, "").is_err()).is_true();
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_rules(pool: Pool) -> Result<(), RuleError> {
+        let rule = definition(RuleField::Title, RuleMatching::Substring, "a");
+        let id = create_rule(&pool, 1, &rule).await?;
+        assert_that!(list_rules(&pool, 1).await?).has_length(1);
+
+        let missing_tag = RuleDefinition {
+            action: RuleAction::Tag,
+            ..definition(RuleField::Title, RuleMatching::Substring, "a")
+        };
+        assert_that!(update_rule(&pool, 1, id, &missing_tag).await).is_err();
+        let other_channel = RuleDefinition {
+            channel_id: Some(3),
+            ..definition(RuleField::Title, RuleMatching::Substring, "a")
+        };
+        assert_that!(create_rule(&pool, 1, &other_channel).await).is_err();
+
+        assert_that!(delete_rule(&pool, 2, id).await).is_err();
+        delete_rule(&pool, 1, id).await?;
+        assert_that!(list_rules(&pool, 1).await?).is_empty();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_dry_run_and_apply(pool: Pool) -> Result<(), RuleError> {
+        let rule = RuleDefinition {
+            channel_id: Some(2),
+            ..definition(RuleField::Url, RuleMatching::Regex, "/sport/")
+        };
+        let matched = dry_run(&pool, 1, &rule).await?;
+        assert_that!(matched).is_not_empty();
+        assert_that!(matched.iter().all(|item| item.channel_id == 2)).is_true();
+
+        // Pretend that the items of the channel were all linked by the same fetch
+        create_rule(&pool, 1, &rule).await?;
+        let added_timestamp = Utc::now();
+        sqlx::query!(
+            "UPDATE users_items SET added_timestamp = $1 WHERE user_id = 1 AND channel_id = 2",
+            added_timestamp
+        )
+        .execute(&pool)
+        .await?;
+        apply_rules(&pool, 1, 2, &added_timestamp).await?;
+
+        let filters = ItemFilters {
+            channel_id: Some(2),
+            ..Default::default()
+        };
+        let items = get_items_of_user(&pool, &filters, 1, 1, 100).await?;
+        assert_that!(items.content().iter().any(|item| item
+            .url
+            .as_deref()
+            .is_some_and(|url| url.contains("/sport/"))))
+        .is_false();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,455 @@
+//! Saved searches, named queries on the items of a user which are read like streams.
+
+use chrono::{Duration, Utc};
+use sqlx::Result;
+use tracing::instrument;
+
+use crate::common::errors::SavedSearchError;
+use crate::common::items::{count_items_of_user, get_items_of_user, ItemFilters};
+use crate::common::model::{PagedResult, SavedSearch, StreamCounters, UserItem};
+use crate::common::{DbError, Pool};
+
+/// Maximum number of days of the date window of a saved search
+const MAX_WINDOW_DAYS: i32 = 3650;
+
+/// Definition of a saved search, to create or replace one
+#[derive(Debug, Default)]
+pub struct SavedSearchDefinition {
+    pub name: String,
+    pub terms: Option<String>,
+    pub channel_ids: Vec<i32>,
+    pub folder_ids: Vec<i32>,
+    pub tag_ids: Vec<i32>,
+    pub read: Option<bool>,
+    pub starred: Option<bool>,
+    pub window_days: Option<i32>,
+}
+
+/// Create a saved search for the user, returning its id
+#[instrument(skip(db))]
+pub async fn create_saved_search(
+    db: &Pool,
+    user_id: i32,
+    search: &SavedSearchDefinition,
+) -> Result<i32, SavedSearchError> {
+    validate(db, user_id, search).await?;
+
+    Ok(sqlx::query_scalar!(
+        r#"
+        INSERT INTO saved_searches (user_id, name, terms, channel_ids, folder_ids, tag_ids, read, starred, window_days)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id
+        "#,
+        user_id,
+        search.name.trim(),
+        terms_of(search),
+        &search.channel_ids,
+        &search.folder_ids,
+        &search.tag_ids,
+        search.read,
+        search.starred,
+        search.window_days
+    )
+    .fetch_one(db)
+    .await?)
+}
+
+/// List the saved searches of the user
+#[instrument(skip(db))]
+pub async fn list_saved_searches(db: &Pool, user_id: i32) -> Result<Vec<SavedSearch>> {
+    sqlx::query_as!(
+        SavedSearch,
+        r#"
+        SELECT      id, name, terms, channel_ids, folder_ids, tag_ids, read, starred, window_days, creation_timestamp
+        FROM        saved_searches
+        WHERE       user_id = $1
+        ORDER BY    id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Get a saved search of the user
+#[instrument(skip(db))]
+pub async fn get_saved_search(db: &Pool, user_id: i32, search_id: i32) -> Result<SavedSearch> {
+    sqlx::query_as!(
+        SavedSearch,
+        r#"
+        SELECT  id, name, terms, channel_ids, folder_ids, tag_ids, read, starred, window_days, creation_timestamp
+        FROM    saved_searches
+        WHERE   id = $1 AND user_id = $2
+        "#,
+        search_id,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Replace a saved search of the user
+#[instrument(skip(db))]
+pub async fn update_saved_search(
+    db: &Pool,
+    user_id: i32,
+    search_id: i32,
+    search: &SavedSearchDefinition,
+) -> Result<(), SavedSearchError> {
+    validate(db, user_id, search).await?;
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE  saved_searches
+        SET     name = $1, terms = $2, channel_ids = $3, folder_ids = $4, tag_ids = $5,
+                read = $6, starred = $7, window_days = $8
+        WHERE   id = $9 AND user_id = $10
+        "#,
+        search.name.trim(),
+        terms_of(search),
+        &search.channel_ids,
+        &search.folder_ids,
+        &search.tag_ids,
+        search.read,
+        search.starred,
+        search.window_days,
+        search_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(SavedSearchError::NotFound("stream", search_id));
+    }
+
+    Ok(())
+}
+
+/// Delete a saved search of the user, along with the output feeds publishing it
+#[instrument(skip(db))]
+pub async fn delete_saved_search(db: &Pool, user_id: i32, search_id: i32) -> Result<()> {
+    let mut transaction = db.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM saved_searches WHERE id = $1 AND user_id = $2
+        "#,
+        search_id,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    sqlx::query!(
+        r#"
+        DELETE FROM output_feeds WHERE user_id = $1 AND source = 'saved_search' AND source_id = $2
+        "#,
+        user_id,
+        search_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await
+}
+
+/// Return a page of the items matching a saved search of the user
+#[instrument(skip(db))]
+pub async fn get_stream_items(
+    db: &Pool,
+    user_id: i32,
+    search_id: i32,
+    page_number: u64,
+    page_size: u64,
+) -> Result<PagedResult<UserItem>> {
+    let search = get_saved_search(db, user_id, search_id).await?;
+
+    get_items_of_user(
+        db,
+        &to_item_filters(&search),
+        user_id,
+        page_number,
+        page_size,
+    )
+    .await
+}
+
+/// Count the unread items matching each saved search of the user
+#[instrument(skip(db))]
+pub async fn count_unread(db: &Pool, user_id: i32) -> Result<Vec<StreamCounters>> {
+    let mut counters = vec![];
+
+    for search in list_saved_searches(db, user_id).await? {
+        let unread = match search.read {
+            // Only read items are matched
+            Some(true) => 0,
+            _ => {
+                let filters = ItemFilters {
+                    read: Some(false),
+                    ..to_item_filters(&search)
+                };
+                count_items_of_user(db, &filters, user_id).await?
+            }
+        };
+
+        counters.push(StreamCounters {
+            stream_id: search.id,
+            unread,
+        });
+    }
+
+    Ok(counters)
+}
+
+/// Translate a saved search into the filters of the items.
+/// The date window is relative to the time of the call.
+pub fn to_item_filters(search: &SavedSearch) -> ItemFilters {
+    ItemFilters {
+        read: search.read,
+        starred: search.starred,
+        published_after: search
+            .window_days
+            .and_then(|days| Utc::now().checked_sub_signed(Duration::days(days as i64))),
+        channel_ids: search.channel_ids.clone(),
+        folder_ids: search.folder_ids.clone(),
+        tag_ids: search.tag_ids.clone(),
+        search: search.terms.clone(),
+        ..Default::default()
+    }
+}
+
+/// Check the saved search only refers to the subscriptions, folders and tags of the user
+async fn validate(
+    db: &Pool,
+    user_id: i32,
+    search: &SavedSearchDefinition,
+) -> Result<(), SavedSearchError> {
+    if search.name.trim().is_empty() {
+        return Err(SavedSearchError::EmptyName);
+    }
+
+    if search
+        .window_days
+        .is_some_and(|days| !(1..=MAX_WINDOW_DAYS).contains(&days))
+    {
+        return Err(SavedSearchError::InvalidWindow);
+    }
+
+    let channel_ids = sqlx::query_scalar!(
+        r#"
+        SELECT channel_id FROM channel_users WHERE user_id = $1 AND channel_id = ANY($2)
+        "#,
+        user_id,
+        &search.channel_ids
+    )
+    .fetch_all(db)
+    .await?;
+    if let Some(missing) = first_missing(&search.channel_ids, &channel_ids) {
+        return Err(SavedSearchError::NotFound("channel", missing));
+    }
+
+    let folder_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM folders WHERE user_id = $1 AND id = ANY($2)
+        "#,
+        user_id,
+        &search.folder_ids
+    )
+    .fetch_all(db)
+    .await?;
+    if let Some(missing) = first_missing(&search.folder_ids, &folder_ids) {
+        return Err(SavedSearchError::NotFound("folder", missing));
+    }
+
+    let tag_ids = sqlx::query_scalar!(
+        r#"
+        SELECT id FROM tags WHERE user_id = $1 AND id = ANY($2)
+        "#,
+        user_id,
+        &search.tag_ids
+    )
+    .fetch_all(db)
+    .await?;
+    if let Some(missing) = first_missing(&search.tag_ids, &tag_ids) {
+        return Err(SavedSearchError::NotFound("tag", missing));
+    }
+
+    Ok(())
+}
+
+fn first_missing(wanted: &[i32], found: &[i32]) -> Option<i32> {
+    wanted.iter().find(|id| !found.contains(id)).copied()
+}
+
+/// Blank terms are the same as no terms at all
+fn terms_of(search: &SavedSearchDefinition) -> Option<&str> {
+    search
+        .terms
+        .as_deref()
+        .map(str::trim)
+        .filter(|terms| !terms.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::tags::{attach_tag, create_tag};
+
+    use super::*;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_saved_searches(pool: Pool) -> anyhow::Result<()> {
+        let definition = SavedSearchDefinition {
+            name: String::from("  Unread news  "),
+            terms: Some(String::from("  ")),
+            channel_ids: vec![2],
+            read: Some(false),
+            ..Default::default()
+        };
+        let id = create_saved_search(&pool, 1, &definition).await?;
+
+        let search = get_saved_search(&pool, 1, id).await?;
+        assert_that!(search.name).is_equal_to(String::from("Unread news"));
+        assert_that!(search.terms).is_none();
+        assert_that!(search.channel_ids).is_equal_to(vec![2]);
+
+        assert_that!(get_saved_search(&pool, 2, id).await).is_err();
+        assert_that!(list_saved_searches(&pool, 2).await?).is_empty();
+
+        let renamed = SavedSearchDefinition {
+            name: String::from("Everything"),
+            ..Default::default()
+        };
+        update_saved_search(&pool, 1, id, &renamed).await?;
+        assert_that!(list_saved_searches(&pool, 1).await?[0].channel_ids).is_empty();
+        let result = update_saved_search(&pool, 2, id, &renamed).await;
+        assert!(matches!(
+            result,
+            Err(SavedSearchError::NotFound("stream", _))
+        ));
+
+        delete_saved_search(&pool, 1, id).await?;
+        assert_that!(list_saved_searches(&pool, 1).await?).is_empty();
+        assert_that!(delete_saved_search(&pool, 1, id).await).is_err();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_validation(pool: Pool) -> anyhow::Result<()> {
+        let invalid = |definition: SavedSearchDefinition| SavedSearchDefinition {
+            name: String::from("Invalid"),
+            ..definition
+        };
+
+        let result = create_saved_search(&pool, 1, &SavedSearchDefinition::default()).await;
+        assert!(matches!(result, Err(SavedSearchError::EmptyName)));
+
+        let window = invalid(SavedSearchDefinition {
+            window_days: Some(0),
+            ..Default::default()
+        });
+        let result = create_saved_search(&pool, 1, &window).await;
+        assert!(matches!(result, Err(SavedSearchError::InvalidWindow)));
+        let window = invalid(SavedSearchDefinition {
+            window_days: Some(2_000_000_000),
+            ..Default::default()
+        });
+        let result = create_saved_search(&pool, 1, &window).await;
+        assert!(matches!(result, Err(SavedSearchError::InvalidWindow)));
+
+        // Channel 3 exists but the user is not subscribed to it
+        let channels = invalid(SavedSearchDefinition {
+            channel_ids: vec![1, 3],
+            ..Default::default()
+        });
+        let result = create_saved_search(&pool, 1, &channels).await;
+        assert!(matches!(
+            result,
+            Err(SavedSearchError::NotFound("channel", 3))
+        ));
+
+        let tags = invalid(SavedSearchDefinition {
+            tag_ids: vec![42],
+            ..Default::default()
+        });
+        let result = create_saved_search(&pool, 1, &tags).await;
+        assert!(matches!(result, Err(SavedSearchError::NotFound("tag", 42))));
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_stream_items_and_counters(pool: Pool) -> anyhow::Result<()> {
+        let unread = create_saved_search(
+            &pool,
+            1,
+            &SavedSearchDefinition {
+                name: String::from("Unread"),
+                read: Some(false),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let read = create_saved_search(
+            &pool,
+            1,
+            &SavedSearchDefinition {
+                name: String::from("Read"),
+                read: Some(true),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let items = get_stream_items(&pool, 1, unread, 1, 200).await?;
+        assert_that!(*items.total_items()).is_equal_to(16);
+        let read_items = get_stream_items(&pool, 1, read, 1, 200).await?;
+        assert_that!(*read_items.total_items()).is_equal_to(78 - 16);
+
+        // Full-text search on a word of a single item, tagged afterward
+        let item = &items.content()[0];
+        let word = item
+            .title
+            .as_deref()
+            .and_then(|title| title.split_whitespace().max_by_key(|word| word.len()))
+            .unwrap()
+            .to_owned();
+        let tag_id = create_tag(&pool, 1, "Later").await?;
+        attach_tag(&pool, 1, tag_id, vec![item.id]).await?;
+        let searched = create_saved_search(
+            &pool,
+            1,
+            &SavedSearchDefinition {
+                name: String::from("Searched"),
+                terms: Some(word),
+                tag_ids: vec![tag_id],
+                channel_ids: vec![2],
+                window_days: Some(MAX_WINDOW_DAYS),
+                ..Default::default()
+            },
+        )
+        .await?;
+        let items = get_stream_items(&pool, 1, searched, 1, 20).await?;
+        assert_that!(items
+            .content()
+            .iter()
+            .map(|item| item.id)
+            .collect::<Vec<i32>>())
+        .is_equal_to(vec![item.id]);
+
+        let counters = count_unread(&pool, 1).await?;
+        let unread_of = |id: i32| counters.iter().find(|c| c.stream_id == id).unwrap().unread;
+        assert_that!(unread_of(unread)).is_equal_to(16);
+        assert_that!(unread_of(read)).is_equal_to(0);
+        assert_that!(unread_of(searched)).is_equal_to(1);
+
+        Ok(())
+    }
+}
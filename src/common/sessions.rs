@@ -0,0 +1,285 @@
+//! Sessions of the users, kept alive by opaque refresh tokens.
+//! A refresh token is rotated each time it is used. Using it again means it leaked: the whole session is revoked.
+
+use chrono::{Duration, Utc};
+use sqlx::Result;
+use tracing::{instrument, warn};
+use uuid::Uuid;
+
+use crate::common::model::Session;
+use crate::common::{hash_secret, DbError, Pool};
+
+/// Number of days a session lives without refreshing it
+const SESSION_TTL_IN_DAYS: i64 = 5;
+
+/// Open a session for the user, recording their login, and return its first refresh token
+#[instrument(skip(db))]
+pub async fn create_session(
+    db: &Pool,
+    user_id: i32,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> Result<String> {
+    let mut transaction = db.begin().await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions WHERE user_id = $1 AND expiration_timestamp < now()
+        "#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let session_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO sessions (user_id, user_agent, ip_address, expiration_timestamp)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+        user_id,
+        user_agent,
+        ip_address,
+        Utc::now() + Duration::days(SESSION_TTL_IN_DAYS)
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    let token = new_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (token_hash, session_id) VALUES ($1, $2)
+        "#,
+        hash_secret(&token),
+        session_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE users SET last_login_timestamp = now() WHERE id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(token)
+}
+
+/// Exchange a refresh token for a new one, extending its session.
+/// Return the user of the session along with the new token, or None if the token is not valid anymore.
+#[instrument(skip_all)]
+pub async fn rotate_refresh_token(db: &Pool, token: &str) -> Result<Option<(i32, String)>> {
+    let mut transaction = db.begin().await?;
+    let token_hash = hash_secret(token);
+
+    let Some(found) = sqlx::query!(
+        r#"
+        SELECT refresh_tokens.session_id, refresh_tokens.used, sessions.user_id, sessions.expiration_timestamp
+        FROM refresh_tokens
+             JOIN sessions ON sessions.id = refresh_tokens.session_id
+        WHERE refresh_tokens.token_hash = $1
+        FOR UPDATE
+        "#,
+        token_hash
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    if found.used {
+        warn!(
+            "Reuse of a refresh token of user {}, revoking session {}",
+            found.user_id, found.session_id
+        );
+        sqlx::query!(r#"DELETE FROM sessions WHERE id = $1"#, found.session_id)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        return Ok(None);
+    }
+
+    if found.expiration_timestamp < Utc::now() {
+        return Ok(None);
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE refresh_tokens SET used = true WHERE token_hash = $1
+        "#,
+        token_hash
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    let new_token = new_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO refresh_tokens (token_hash, session_id) VALUES ($1, $2)
+        "#,
+        hash_secret(&new_token),
+        found.session_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE sessions SET last_use_timestamp = now(), expiration_timestamp = $1 WHERE id = $2
+        "#,
+        Utc::now() + Duration::days(SESSION_TTL_IN_DAYS),
+        found.session_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Some((found.user_id, new_token)))
+}
+
+/// Close the session of a refresh token, if any
+#[instrument(skip_all)]
+pub async fn revoke_session_of_token(db: &Pool, token: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions
+        WHERE id = (SELECT session_id FROM refresh_tokens WHERE token_hash = $1)
+        "#,
+        hash_secret(token)
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// List the active sessions of the user, most recently used first
+#[instrument(skip(db))]
+pub async fn list_sessions(db: &Pool, user_id: i32) -> Result<Vec<Session>> {
+    sqlx::query_as!(
+        Session,
+        r#"
+        SELECT      id, user_agent, ip_address, creation_timestamp, last_use_timestamp, expiration_timestamp
+        FROM        sessions
+        WHERE       user_id = $1
+        AND         expiration_timestamp > now()
+        ORDER BY    last_use_timestamp DESC
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Close a session of the user
+#[instrument(skip(db))]
+pub async fn revoke_session(db: &Pool, user_id: i32, session_id: i32) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM sessions WHERE id = $1 AND user_id = $2
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Close all the sessions of the user
+#[instrument(skip(db))]
+pub async fn revoke_sessions(db: &Pool, user_id: i32) -> Result<()> {
+    sqlx::query!(
+        r#"
+        DELETE FROM sessions WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+fn new_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_rotation(pool: Pool) -> Result<()> {
+        let first = create_session(&pool, 1, Some("curl/8.0"), Some("127.0.0.1")).await?;
+
+        let (user_id, second) = rotate_refresh_token(&pool, &first).await?.unwrap();
+        assert_that!(user_id).is_equal_to(1);
+        assert_that!(second).is_not_equal_to(&first);
+
+        let (_, third) = rotate_refresh_token(&pool, &second).await?.unwrap();
+        let sessions = list_sessions(&pool, 1).await?;
+        assert_that!(sessions).has_length(1);
+        assert_that!(sessions[0].user_agent).is_equal_to(Some(String::from("curl/8.0")));
+        assert_that!(rotate_refresh_token(&pool, &third).await?).is_some();
+
+        assert_that!(rotate_refresh_token(&pool, "unknown").await?).is_none();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_reuse_revokes_the_session(pool: Pool) -> Result<()> {
+        let first = create_session(&pool, 1, None, None).await?;
+        let other = create_session(&pool, 1, None, None).await?;
+        let (_, second) = rotate_refresh_token(&pool, &first).await?.unwrap();
+
+        // The first token leaked and is used again
+        assert_that!(rotate_refresh_token(&pool, &first).await?).is_none();
+        assert_that!(rotate_refresh_token(&pool, &second).await?).is_none();
+        assert_that!(list_sessions(&pool, 1).await?).has_length(1);
+
+        // The other sessions are left untouched
+        assert_that!(rotate_refresh_token(&pool, &other).await?).is_some();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_revocation(pool: Pool) -> Result<()> {
+        let first = create_session(&pool, 1, None, None).await?;
+        let second = create_session(&pool, 1, None, None).await?;
+        let third = create_session(&pool, 1, None, None).await?;
+        let of_user_2 = create_session(&pool, 2, None, None).await?;
+
+        revoke_session_of_token(&pool, &first).await?;
+        assert_that!(rotate_refresh_token(&pool, &first).await?).is_none();
+        assert_that!(list_sessions(&pool, 1).await?).has_length(2);
+
+        let session_id = list_sessions(&pool, 1).await?[0].id;
+        assert_that!(revoke_session(&pool, 2, session_id).await).is_err();
+        revoke_session(&pool, 1, session_id).await?;
+        assert_that!(list_sessions(&pool, 1).await?).has_length(1);
+
+        revoke_sessions(&pool, 1).await?;
+        assert_that!(rotate_refresh_token(&pool, &second).await?).is_none();
+        assert_that!(rotate_refresh_token(&pool, &third).await?).is_none();
+        assert_that!(rotate_refresh_token(&pool, &of_user_2).await?).is_some();
+
+        Ok(())
+    }
+}
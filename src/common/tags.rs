@@ -0,0 +1,215 @@
+use sqlx::Result;
+use tracing::instrument;
+
+use crate::common::model::Tag;
+use crate::common::{DbError, Pool};
+
+/// List the tags of a user, along with the number of items attached to each of them
+#[instrument(skip(db))]
+pub async fn list_tags(db: &Pool, user_id: i32) -> Result<Vec<Tag>> {
+    sqlx::query_as!(
+        Tag,
+        r#"
+        SELECT      tags.id,
+                    tags.name,
+                    COUNT(users_items_tags.item_id) AS "items_count!"
+        FROM        tags
+                    LEFT JOIN users_items_tags ON users_items_tags.tag_id = tags.id
+        WHERE       tags.user_id = $1
+        GROUP BY    tags.id
+        ORDER BY    tags.name
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Create a new tag for the user, returning its id.
+/// If the user already has a tag with the same name, return the existing one.
+#[instrument(skip(db))]
+pub async fn create_tag(db: &Pool, user_id: i32, name: &str) -> Result<i32> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO tags (user_id, name) VALUES ($1, $2)
+        ON CONFLICT (user_id, name) DO UPDATE SET name = EXCLUDED.name
+        RETURNING id
+        "#,
+        user_id,
+        name
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Rename a tag of the user
+#[instrument(skip(db))]
+pub async fn rename_tag(db: &Pool, user_id: i32, tag_id: i32, name: &str) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE tags SET name = $1 WHERE id = $2 AND user_id = $3
+        "#,
+        name,
+        tag_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Delete a tag of the user, detaching it from all its items
+#[instrument(skip(db))]
+pub async fn delete_tag(db: &Pool, user_id: i32, tag_id: i32) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM tags WHERE id = $1 AND user_id = $2
+        "#,
+        tag_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Attach a tag to the given items of the user
+#[instrument(skip(db))]
+pub async fn attach_tag(db: &Pool, user_id: i32, tag_id: i32, item_ids: Vec<i32>) -> Result<()> {
+    check_tag_owner(db, user_id, tag_id).await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO users_items_tags (tag_id, user_id, item_id, channel_id)
+        SELECT  $1, users_items.user_id, users_items.item_id, users_items.channel_id
+        FROM    users_items
+        WHERE   users_items.user_id = $2
+        AND     users_items.item_id = ANY($3)
+        ON CONFLICT DO NOTHING
+        "#,
+        tag_id,
+        user_id,
+        &item_ids[..]
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Detach a tag from the given items of the user
+#[instrument(skip(db))]
+pub async fn detach_tag(db: &Pool, user_id: i32, tag_id: i32, item_ids: Vec<i32>) -> Result<()> {
+    check_tag_owner(db, user_id, tag_id).await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM users_items_tags WHERE tag_id = $1 AND user_id = $2 AND item_id = ANY($3)
+        "#,
+        tag_id,
+        user_id,
+        &item_ids[..]
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Make sure the tag exists and belongs to the user
+async fn check_tag_owner(db: &Pool, user_id: i32, tag_id: i32) -> Result<()> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM tags WHERE id = $1 AND user_id = $2
+        "#,
+        tag_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(DbError::RowNotFound)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use crate::common::items::{get_items_of_user, ItemFilters};
+
+    use super::*;
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_tag_items(pool: Pool) -> Result<()> {
+        let tag_id = create_tag(&pool, 1, "to read later").await?;
+        attach_tag(&pool, 1, tag_id, vec![4, 5, 6]).await?;
+
+        let tags = list_tags(&pool, 1).await?;
+        assert_that!(tags).has_length(1);
+        assert_that!(tags[0].name).is_equal_to("to read later".to_owned());
+        assert_that!(tags[0].items_count).is_equal_to(3);
+
+        let filters = ItemFilters {
+            tag_id: Some(tag_id),
+            ..Default::default()
+        };
+        let items = get_items_of_user(&pool, &filters, 1, 1, 20).await?;
+        assert_that!(items.content()).has_length(3);
+        for item in items.content() {
+            assert_that!(item.tags).is_equal_to(vec!["to read later".to_owned()]);
+        }
+
+        detach_tag(&pool, 1, tag_id, vec![4]).await?;
+        let items = get_items_of_user(&pool, &filters, 1, 1, 20).await?;
+        assert_that!(items.content()).has_length(2);
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_cannot_use_tag_of_someone_else(pool: Pool) -> Result<()> {
+        let tag_id = create_tag(&pool, 1, "mine").await?;
+
+        assert!(matches!(
+            attach_tag(&pool, 2, tag_id, vec![4]).await,
+            Err(DbError::RowNotFound)
+        ));
+        assert!(matches!(
+            rename_tag(&pool, 2, tag_id, "yours").await,
+            Err(DbError::RowNotFound)
+        ));
+        assert!(matches!(
+            delete_tag(&pool, 2, tag_id).await,
+            Err(DbError::RowNotFound)
+        ));
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_delete_tag(pool: Pool) -> Result<()> {
+        let tag_id = create_tag(&pool, 1, "ephemeral").await?;
+        attach_tag(&pool, 1, tag_id, vec![4, 5]).await?;
+
+        delete_tag(&pool, 1, tag_id).await?;
+
+        assert_that!(list_tags(&pool, 1).await?).is_empty();
+        let item = crate::common::items::get_one_item(&pool, 4, 1)
+            .await?
+            .unwrap();
+        assert_that!(item.tags).is_empty();
+
+        Ok(())
+    }
+}
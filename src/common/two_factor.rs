@@ -0,0 +1,492 @@
+//! Two-factor authentication with time-based one-time passwords (RFC 6238), and one-time recovery codes.
+//! The TOTP secret is encrypted at rest, as it must be read back to check the codes.
+
+use chrono::Utc;
+use deadpool_redis::Pool as RedisPool;
+use hmac::{Hmac, Mac};
+use rand::distributions::{Alphanumeric, DistString};
+use rand::Rng;
+use redis::AsyncCommands;
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use sha1::Sha1;
+use tracing::{debug_span, instrument, Instrument};
+use uuid::Uuid;
+
+use crate::common::encryption::{decrypt, encrypt};
+use crate::common::errors::TwoFactorError;
+use crate::common::model::TwoFactorStatus;
+use crate::common::{hash_secret, Pool};
+
+/// Issuer shown by the authenticator applications
+const ISSUER: &str = "harss";
+
+/// Duration of a TOTP step, in seconds
+const STEP_IN_SECONDS: i64 = 30;
+
+/// Number of digits of a TOTP code
+const DIGITS: u32 = 6;
+
+/// Size of the TOTP secret, in bytes
+const SECRET_SIZE: usize = 20;
+
+/// Number of recovery codes given at confirmation
+const RECOVERY_CODES: usize = 10;
+
+/// Number of seconds left to give the second factor once the password is checked
+pub const CHALLENGE_TTL: u64 = 5 * 60;
+
+/// Number of wrong codes a login challenge survives
+const CHALLENGE_ATTEMPTS: u32 = 5;
+
+/// A pending enrollment, to be registered in an authenticator application
+#[derive(Debug, Serialize)]
+pub struct Enrollment {
+    /// The TOTP secret, in base32
+    pub secret: String,
+    /// The `otpauth://` URI of the secret, usually shown as a QR code
+    pub uri: String,
+}
+
+/// Start the enrollment of the user with a new secret, replacing any pending enrollment
+#[instrument(skip(db))]
+pub async fn enroll(db: &Pool, user_id: i32, login: &str) -> Result<Enrollment, TwoFactorError> {
+    let bytes = rand::thread_rng().gen::<[u8; SECRET_SIZE]>();
+    let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes);
+    let encrypted_secret = encrypt(&Secret::new(secret.clone()))?;
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO two_factor (user_id, encrypted_secret) VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE SET encrypted_secret = $2, last_used_step = 0, creation_timestamp = now()
+                                        WHERE two_factor.enabled = false
+        "#,
+        user_id,
+        encrypted_secret
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+
+    let label: String =
+        form_urlencoded::byte_serialize(format!("{}:{}", ISSUER, login).as_bytes()).collect();
+    let uri = format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        label, secret, ISSUER, DIGITS, STEP_IN_SECONDS
+    );
+
+    Ok(Enrollment { secret, uri })
+}
+
+/// Enable the pending enrollment of the user with a first valid code, returning the recovery codes
+#[instrument(skip(db, code))]
+pub async fn confirm(
+    db: &Pool,
+    user_id: i32,
+    code: &Secret<String>,
+) -> Result<Vec<String>, TwoFactorError> {
+    let Some(enrollment) = sqlx::query!(
+        r#"
+        SELECT encrypted_secret, enabled FROM two_factor WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    else {
+        return Err(TwoFactorError::NotEnrolled);
+    };
+
+    if enrollment.enabled {
+        return Err(TwoFactorError::AlreadyEnabled);
+    }
+
+    if !check_totp(db, user_id, &enrollment.encrypted_secret, code).await? {
+        return Err(TwoFactorError::InvalidCode);
+    }
+
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            let code = Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 10)
+                .to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+
+    let mut transaction = db.begin().await?;
+
+    sqlx::query!(
+        r#"UPDATE two_factor SET enabled = true WHERE user_id = $1"#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::text[])
+        "#,
+        user_id,
+        &hashes
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(codes)
+}
+
+/// Return the two-factor authentication state of the user
+#[instrument(skip(db))]
+pub async fn get_status(db: &Pool, user_id: i32) -> sqlx::Result<TwoFactorStatus> {
+    let status = sqlx::query_as!(
+        TwoFactorStatus,
+        r#"
+        SELECT  COALESCE(two_factor.enabled, false) AS "enabled!",
+                COALESCE(two_factor.allow_basic_auth, true) AS "allow_basic_auth!",
+                (SELECT COUNT(*) FROM recovery_codes WHERE recovery_codes.user_id = $1) AS "recovery_codes_left!"
+        FROM    users
+                LEFT JOIN two_factor ON two_factor.user_id = users.id
+        WHERE   users.id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(status)
+}
+
+/// Return whether the user has enabled two-factor authentication
+#[instrument(skip(db))]
+pub async fn is_enabled(db: &Pool, user_id: i32) -> sqlx::Result<bool> {
+    let enabled = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM two_factor WHERE user_id = $1 AND enabled = true)
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(enabled.unwrap_or(false))
+}
+
+/// Return whether the password alone must be refused for the user, as with HTTP Basic authentication
+#[instrument(skip(db))]
+pub async fn refuses_password_auth(db: &Pool, user_id: i32) -> sqlx::Result<bool> {
+    let refused = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM two_factor WHERE user_id = $1 AND enabled = true AND allow_basic_auth = false)
+        "#,
+        user_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(refused.unwrap_or(false))
+}
+
+/// Accept or refuse HTTP Basic authentication, and the other password based logins, for the user
+#[instrument(skip(db))]
+pub async fn set_allow_basic_auth(
+    db: &Pool,
+    user_id: i32,
+    allow_basic_auth: bool,
+) -> Result<(), TwoFactorError> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE two_factor SET allow_basic_auth = $2 WHERE user_id = $1 AND enabled = true
+        "#,
+        user_id,
+        allow_basic_auth
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(TwoFactorError::NotEnrolled);
+    }
+
+    Ok(())
+}
+
+/// Check a TOTP code, or consume a recovery code, of a user having enabled two-factor authentication
+#[instrument(skip(db, code))]
+pub async fn verify_code(
+    db: &Pool,
+    user_id: i32,
+    code: &Secret<String>,
+) -> Result<bool, TwoFactorError> {
+    let Some(encrypted_secret) = sqlx::query_scalar!(
+        r#"
+        SELECT encrypted_secret FROM two_factor WHERE user_id = $1 AND enabled = true
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    else {
+        return Err(TwoFactorError::NotEnrolled);
+    };
+
+    if check_totp(db, user_id, &encrypted_secret, code).await? {
+        return Ok(true);
+    }
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM recovery_codes WHERE user_id = $1 AND code_hash = $2
+        "#,
+        user_id,
+        hash_recovery_code(code.expose_secret())
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Disable the two-factor authentication of the user, forgetting its secret and recovery codes
+#[instrument(skip(db))]
+pub async fn disable(db: &Pool, user_id: i32) -> sqlx::Result<()> {
+    let mut transaction = db.begin().await?;
+
+    let result = sqlx::query!(r#"DELETE FROM two_factor WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound);
+    }
+
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await
+}
+
+/// Create the challenge a user must complete with a code to log in, once their password is checked
+#[instrument(skip(redis))]
+pub async fn create_challenge(redis: &RedisPool, user_id: i32) -> anyhow::Result<String> {
+    let challenge = Uuid::new_v4().simple().to_string();
+
+    redis
+        .get()
+        .await?
+        .set_ex::<_, _, ()>(challenge_key(&challenge), user_id, CHALLENGE_TTL)
+        .instrument(debug_span!("store_two_factor_challenge_in_redis"))
+        .await?;
+
+    Ok(challenge)
+}
+
+/// Return the user logging in with a challenge, if it is still pending
+#[instrument(skip_all)]
+pub async fn challenge_user(redis: &RedisPool, challenge: &str) -> anyhow::Result<Option<i32>> {
+    Ok(redis
+        .get()
+        .await?
+        .get::<_, Option<i32>>(challenge_key(challenge))
+        .instrument(debug_span!("get_two_factor_challenge_in_redis"))
+        .await?)
+}
+
+/// Complete a login challenge with a code, returning the user logging in.
+/// The challenge is consumed on success, or after too many wrong codes.
+#[instrument(skip_all)]
+pub async fn complete_challenge(
+    db: &Pool,
+    redis: &RedisPool,
+    challenge: &str,
+    code: &Secret<String>,
+) -> anyhow::Result<Option<i32>> {
+    let key = challenge_key(challenge);
+    let mut redis = redis.get().await?;
+
+    let Some(user_id) = redis
+        .get::<_, Option<i32>>(&key)
+        .instrument(debug_span!("get_two_factor_challenge_in_redis"))
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    if verify_code(db, user_id, code).await? {
+        redis.del::<_, usize>(&key).await?;
+        return Ok(Some(user_id));
+    }
+
+    let attempts_key = format!("{}.attempts", key);
+    let attempts = redis.incr::<_, _, u32>(&attempts_key, 1).await?;
+    redis
+        .expire::<_, usize>(&attempts_key, CHALLENGE_TTL as i64)
+        .await?;
+    if attempts >= CHALLENGE_ATTEMPTS {
+        redis.del::<_, usize>(&[&key, &attempts_key]).await?;
+    }
+
+    Ok(None)
+}
+
+/// Check a TOTP code against the secret, accepting the previous and next steps for clock drifts.
+/// A step is only accepted once, so a code cannot be replayed.
+async fn check_totp(
+    db: &Pool,
+    user_id: i32,
+    encrypted_secret: &str,
+    code: &Secret<String>,
+) -> Result<bool, TwoFactorError> {
+    let code = code.expose_secret().trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Ok(false);
+    }
+
+    let secret = decrypt(encrypted_secret)?;
+    let Some(secret) = base32::decode(
+        base32::Alphabet::RFC4648 { padding: false },
+        secret.expose_secret(),
+    ) else {
+        return Ok(false);
+    };
+
+    let current_step = Utc::now().timestamp() / STEP_IN_SECONDS;
+    let Some(step) = (current_step - 1..=current_step + 1)
+        .find(|step| format!("{:0width$}", totp(&secret, *step), width = DIGITS as usize) == code)
+    else {
+        return Ok(false);
+    };
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE two_factor SET last_used_step = $2 WHERE user_id = $1 AND last_used_step < $2
+        "#,
+        user_id,
+        step
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() == 1)
+}
+
+/// Compute the TOTP code of a step, as HOTP (RFC 4226) of the step number
+fn totp(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    code % 10u32.pow(DIGITS)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    hash_secret(&normalized)
+}
+
+fn challenge_key(challenge: &str) -> String {
+    format!("two-factor-challenge.{}", challenge)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+    use crate::common::encryption::tests::set_test_key;
+
+    #[test]
+    fn test_totp() {
+        // RFC 6238 test vectors, truncated to 6 digits
+        let secret = b"12345678901234567890";
+        assert_that!(totp(secret, 59 / 30)).is_equal_to(287082);
+        assert_that!(totp(secret, 1111111109 / 30)).is_equal_to(81804);
+        assert_that!(totp(secret, 2000000000 / 30)).is_equal_to(279037);
+    }
+
+    fn current_code(secret: &str) -> Secret<String> {
+        let secret = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret).unwrap();
+        let step = Utc::now().timestamp() / STEP_IN_SECONDS;
+        Secret::new(format!("{:06}", totp(&secret, step)))
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_enrollment(pool: Pool) -> anyhow::Result<()> {
+        set_test_key();
+
+        let enrollment = enroll(&pool, 1, "root").await?;
+        assert_that!(enrollment
+            .uri
+            .starts_with("otpauth://totp/harss%3Aroot?secret="))
+        .is_true();
+        assert_that!(is_enabled(&pool, 1).await?).is_false();
+
+        // A pending enrollment can be restarted
+        let enrollment = enroll(&pool, 1, "root").await?;
+
+        let wrong = Secret::new(String::from("000000"));
+        let code = current_code(&enrollment.secret);
+        if code.expose_secret() != wrong.expose_secret() {
+            assert!(matches!(
+                confirm(&pool, 1, &wrong).await,
+                Err(TwoFactorError::InvalidCode)
+            ));
+        }
+        let recovery_codes = confirm(&pool, 1, &code).await?;
+        assert_that!(recovery_codes).has_length(RECOVERY_CODES);
+        assert_that!(is_enabled(&pool, 1).await?).is_true();
+        assert_that!(refuses_password_auth(&pool, 1).await?).is_true();
+
+        assert!(matches!(
+            enroll(&pool, 1, "root").await,
+            Err(TwoFactorError::AlreadyEnabled)
+        ));
+
+        // The code of the confirmation cannot be replayed
+        assert_that!(verify_code(&pool, 1, &code).await?).is_false();
+
+        // A recovery code is only accepted once
+        let recovery_code = Secret::new(recovery_codes[0].to_uppercase());
+        assert_that!(verify_code(&pool, 1, &recovery_code).await?).is_true();
+        assert_that!(verify_code(&pool, 1, &recovery_code).await?).is_false();
+        assert_that!(get_status(&pool, 1).await?.recovery_codes_left)
+            .is_equal_to(RECOVERY_CODES as i64 - 1);
+
+        set_allow_basic_auth(&pool, 1, true).await?;
+        assert_that!(refuses_password_auth(&pool, 1).await?).is_false();
+
+        disable(&pool, 1).await?;
+        let status = get_status(&pool, 1).await?;
+        assert_that!(status.enabled).is_false();
+        assert_that!(status.recovery_codes_left).is_equal_to(0);
+        assert!(matches!(
+            verify_code(&pool, 1, &recovery_code).await,
+            Err(TwoFactorError::NotEnrolled)
+        ));
+
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 use redis::{AsyncCommands, SetExpiry, SetOptions};
 use secrecy::{ExposeSecret, Secret};
 use serde::Serialize;
-use sqlx::Result;
-use tracing::{debug, debug_span, info, instrument, Instrument};
+use sqlx::{PgExecutor, Result};
+use tracing::{debug, debug_span, error, info, instrument, Instrument};
 
-use crate::common::model::{PagedResult, User, UserRole};
+use crate::common::errors::UserError;
+use crate::common::model::{PagedResult, User, UserRole, UserSummary};
 use crate::common::password::encode_password;
 use crate::common::Pool;
 use sha2::{Digest, Sha256};
@@ -19,7 +20,7 @@ pub async fn get_user_by_username(db: &Pool, wanted_username: &str) -> Result<Op
     sqlx::query_as!(
         User,
         r#"
-        SELECT id, username, password, role as "role: UserRole", email_verified FROM users WHERE username = $1
+        SELECT id, username, password, role as "role: UserRole", email_verified, token_version, disabled FROM users WHERE username = $1
         "#,
         wanted_username
     )
@@ -33,7 +34,7 @@ pub async fn get_user_by_id(db: &Pool, id: i32) -> Result<Option<User>> {
     sqlx::query_as!(
         User,
         r#"
-        SELECT id, username, password, role as "role: UserRole", email_verified FROM users WHERE id = $1
+        SELECT id, username, password, role as "role: UserRole", email_verified, token_version, disabled FROM users WHERE id = $1
         "#,
         id
     )
@@ -48,7 +49,7 @@ pub async fn get_user_by_email(db: &Pool, email: &Secret<String>) -> Result<Opti
     sqlx::query_as!(
         User,
         r#"
-        SELECT id, username, password, role as "role: UserRole", email_verified FROM users WHERE email = $1 AND email_verified = true
+        SELECT id, username, password, role as "role: UserRole", email_verified, token_version, disabled FROM users WHERE email = $1 AND email_verified = true
         "#,
         encoded_email
     )
@@ -56,16 +57,51 @@ pub async fn get_user_by_email(db: &Pool, email: &Secret<String>) -> Result<Opti
     .await
 }
 
-/// List all the users
+/// Check that the email is the verified email of the user
+#[instrument(skip(db, email))]
+pub async fn has_verified_email(db: &Pool, user_id: i32, email: &Secret<String>) -> Result<bool> {
+    let verified = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM users WHERE id = $1 AND email = $2 AND email_verified = true)
+        "#,
+        user_id,
+        hash_email(&Some(email.clone()))
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(verified.unwrap_or(false))
+}
+
+/// List the users matching the filters, with some details for the admins.
+/// The search matches any part of the username, regardless of the case.
 #[instrument(skip(db))]
-pub async fn list_users(db: &Pool, page_number: u64, page_size: u64) -> Result<PagedResult<User>> {
+pub async fn list_users(
+    db: &Pool,
+    role: Option<&UserRole>,
+    disabled: Option<bool>,
+    search: Option<&str>,
+    page_number: u64,
+    page_size: u64,
+) -> Result<PagedResult<UserSummary>> {
+    let pattern = search.map(|search| format!("%{}%", escape_like(search)));
+
     let content = sqlx::query_as!(
-        User,
+        UserSummary,
         r#"
-        SELECT id, username, password, role as "role: UserRole", email_verified FROM users
-        ORDER BY id
-        LIMIT $1 OFFSET $2
+        SELECT      id, username, role as "role: UserRole", disabled, email_verified, creation_timestamp,
+                    last_login_timestamp,
+                    (SELECT COUNT(*) FROM channel_users WHERE channel_users.user_id = users.id) AS "subscriptions!"
+        FROM        users
+        WHERE       ($1::user_role IS NULL OR role = $1)
+        AND         ($2::boolean IS NULL OR disabled = $2)
+        AND         ($3::text IS NULL OR username ILIKE $3)
+        ORDER BY    id
+        LIMIT $4 OFFSET $5
         "#,
+        role as Option<&UserRole>,
+        disabled,
+        pattern,
         page_size as i64,
         (page_number as i64 - 1) * page_size as i64
     )
@@ -74,8 +110,15 @@ pub async fn list_users(db: &Pool, page_number: u64, page_size: u64) -> Result<P
 
     let total_items = sqlx::query_scalar!(
         r#"
-        SELECT COUNT(*) FROM users
+        SELECT      COUNT(*)
+        FROM        users
+        WHERE       ($1::user_role IS NULL OR role = $1)
+        AND         ($2::boolean IS NULL OR disabled = $2)
+        AND         ($3::text IS NULL OR username ILIKE $3)
         "#,
+        role as Option<&UserRole>,
+        disabled,
+        pattern,
     )
     .fetch_one(db)
     .await?
@@ -89,7 +132,84 @@ pub async fn list_users(db: &Pool, page_number: u64, page_size: u64) -> Result<P
     ))
 }
 
-/// Create a new user
+/// Escape the wildcards of a LIKE pattern
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Change the username, the role or the disabled state of an account.
+/// The access tokens of the user are revoked when their username or role change, or when they are disabled, along with
+/// their sessions in the latter case. Return whether the tokens were revoked.
+#[instrument(skip(db))]
+pub async fn update_account(
+    db: &Pool,
+    user_id: i32,
+    username: Option<&str>,
+    role: Option<&UserRole>,
+    disabled: Option<bool>,
+) -> std::result::Result<bool, UserError> {
+    if username.is_some_and(|username| username.trim().is_empty()) {
+        return Err(UserError::EmptyUsername);
+    }
+
+    let mut transaction = db.begin().await?;
+
+    let user = sqlx::query_as!(
+        User,
+        r#"
+        SELECT id, username, password, role as "role: UserRole", email_verified, token_version, disabled FROM users
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        user_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?
+    .ok_or(UserError::NotFound("user", user_id))?;
+
+    let disabling = disabled == Some(true) && !user.disabled;
+    let revoke = disabling
+        || username.is_some_and(|username| username != user.username)
+        || role.is_some_and(|role| *role != user.role);
+
+    sqlx::query!(
+        r#"
+        UPDATE  users
+        SET     username = COALESCE($2, username),
+                role = COALESCE($3, role),
+                disabled = COALESCE($4, disabled),
+                token_version = token_version + $5
+        WHERE   id = $1
+        "#,
+        user_id,
+        username,
+        role as Option<&UserRole>,
+        disabled,
+        i32::from(revoke)
+    )
+    .execute(&mut *transaction)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref error) if error.is_unique_violation() => UserError::UsernameTaken,
+        _ => UserError::SqlError(e),
+    })?;
+
+    if disabling {
+        sqlx::query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+            .execute(&mut *transaction)
+            .await?;
+        info!("Disabled user {}", user_id);
+    }
+
+    transaction.commit().await?;
+
+    Ok(revoke)
+}
+
+/// Create a new user, sending them a confirmation email if they gave their email
 #[instrument(skip(db, redis, password, email))]
 pub async fn create_user(
     redis: &RedisPool,
@@ -98,47 +218,78 @@ pub async fn create_user(
     password: &Secret<String>,
     email: &Option<Secret<String>>,
     user_role: &UserRole,
-) -> anyhow::Result<User> {
-    let user = sqlx::query_as!(
+) -> std::result::Result<User, UserError> {
+    let user = insert_user(db, login, password, email, user_role).await?;
+    confirm_new_email(redis, &user, email).await;
+
+    Ok(user)
+}
+
+/// Insert a new user, within the transaction of the caller if any
+pub(crate) async fn insert_user<'c>(
+    executor: impl PgExecutor<'c>,
+    login: &str,
+    password: &Secret<String>,
+    email: &Option<Secret<String>>,
+    user_role: &UserRole,
+) -> std::result::Result<User, UserError> {
+    sqlx::query_as!(
         User,
         r#"
         INSERT INTO users (username, password, email, role, email_verified) VALUES ($1, $2, $3, $4, false) 
-        RETURNING id, username, password, role as "role: UserRole", email_verified
+        RETURNING id, username, password, role as "role: UserRole", email_verified, token_version, disabled
         "#,
         login,
-        encode_password(password),
+        encode_password(password).await,
         hash_email(email),
         user_role as &UserRole
     )
-    .fetch_one(db)
-    .await?; //TODO Make a beautifull error on unique constraint violation
+    .fetch_one(executor)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref error) if error.is_unique_violation() => UserError::UsernameTaken,
+        _ => UserError::SqlError(e),
+    })
+}
 
+/// Send the confirmation email of a user just created, if they gave their email. The user exists anyway, so a failure
+/// is only logged: a new confirmation can be asked for later.
+pub(crate) async fn confirm_new_email(
+    redis: &RedisPool,
+    user: &User,
+    email: &Option<Secret<String>>,
+) {
     if let Some(email) = email {
         debug!(
             "User {} (id. {}) has provided an email during creation, sending confirmation",
-            login, user.id
+            user.username, user.id
         );
-        send_confirmation_email(redis, &user, email).await?;
+        if let Err(e) = send_confirmation_email(redis, user, email).await {
+            error!(
+                "Could not send the confirmation email of user {}: {:?}",
+                user.id, e
+            );
+        }
     }
-
-    Ok(user)
 }
 
-/// Update a user's password
+/// Update a user's password, revoking their access tokens and their sessions
 #[instrument(skip(db, new_password))]
 pub async fn update_user_password(
     db: &Pool,
     user_id: i32,
     new_password: &Secret<String>,
 ) -> Result<()> {
+    let mut transaction = db.begin().await?;
+
     let result = sqlx::query!(
         r#"
-        UPDATE users SET password = $1 WHERE id=$2
+        UPDATE users SET password = $1, token_version = token_version + 1 WHERE id=$2
         "#,
-        encode_password(new_password),
+        encode_password(new_password).await,
         user_id
     )
-    .execute(db)
+    .execute(&mut *transaction)
     .await?;
 
     //TODO: Must return a dedicated error
@@ -146,9 +297,83 @@ pub async fn update_user_password(
         return Err(sqlx::Error::RowNotFound);
     }
 
+    sqlx::query!(r#"DELETE FROM sessions WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
     Ok(())
 }
 
+/// Replace the outdated password hash of the user by one made with the current parameters.
+/// Unlike a password change, the tokens and the sessions are kept. The hash is left alone if it changed meanwhile.
+#[instrument(skip(db, user, password), fields(user_id = user.id))]
+pub async fn rehash_password(db: &Pool, user: &User, password: &Secret<String>) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE users SET password = $1 WHERE id = $2 AND password = $3
+        "#,
+        encode_password(password).await,
+        user.id,
+        user.password
+    )
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Return the current token version of the user, or None if the user does not exist anymore or is disabled
+#[instrument(skip(db))]
+pub async fn get_token_version(db: &Pool, user_id: i32) -> Result<Option<i32>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT token_version FROM users WHERE id = $1 AND NOT disabled
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+}
+
+/// Revoke all the access tokens issued to the user, by bumping their token version
+#[instrument(skip(db, redis))]
+pub async fn bump_token_version(db: &Pool, redis: &RedisPool, user_id: i32) -> anyhow::Result<()> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE users SET token_version = token_version + 1 WHERE id = $1
+        "#,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound)?;
+    }
+
+    forget_token_version(redis, user_id).await
+}
+
+/// Drop the token version of the user cached in redis, once changed
+#[instrument(skip(redis))]
+pub async fn forget_token_version(redis: &RedisPool, user_id: i32) -> anyhow::Result<()> {
+    redis
+        .get()
+        .await?
+        .del::<String, usize>(token_version_key(user_id))
+        .instrument(debug_span!("delete_redis_token_version"))
+        .await?;
+
+    Ok(())
+}
+
+/// Redis key caching the token version of the user
+pub(crate) fn token_version_key(user_id: i32) -> String {
+    format!("user.{}.token-version", user_id)
+}
+
 #[instrument(skip(db, redis, new_password, token))]
 pub async fn reset_password(
     db: &Pool,
@@ -173,6 +398,7 @@ pub async fn reset_password(
                 update_user_password(db, user.id, new_password).await?;
 
                 redis.get().await?.del::<String, usize>(key).await?;
+                delete_user_redis_keys(redis, user.id).await?;
 
                 return Ok(());
             }
@@ -234,6 +460,11 @@ pub async fn update_user(
         )
         .execute(db)
         .await?;
+
+        // The digest was sent to the previous address
+        sqlx::query!(r#"DELETE FROM digests WHERE user_id = $1"#, user.id)
+            .execute(db)
+            .await?;
     }
 
     Ok(())
@@ -322,7 +553,7 @@ async fn generate_and_persist_token(
 
 #[instrument]
 /// Hash an email adresse using sha256
-fn hash_email(email: &Option<Secret<String>>) -> Option<String> {
+pub(crate) fn hash_email(email: &Option<Secret<String>>) -> Option<String> {
     if let Some(email) = email {
         let mut hasher = Sha256::new();
 
@@ -362,3 +593,111 @@ struct ResetPasswordData<'a> {
     token_ttl: usize,
     user_id: i32,
 }
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+
+    use super::*;
+    use crate::common::password::{verify_password, PasswordCheck};
+    use crate::common::sessions::{create_session, list_sessions};
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_password_update_revokes_tokens(pool: Pool) -> anyhow::Result<()> {
+        create_session(&pool, 1, None, None).await?;
+        let version = get_token_version(&pool, 1).await?.unwrap();
+
+        update_user_password(&pool, 1, &Secret::new(String::from("n3w_p4ssword"))).await?;
+
+        assert_that!(get_token_version(&pool, 1).await?).is_equal_to(Some(version + 1));
+        assert_that!(list_sessions(&pool, 1).await?).is_empty();
+        assert_that!(get_token_version(&pool, 999).await?).is_none();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_rehash_password(pool: Pool) -> anyhow::Result<()> {
+        let version = get_token_version(&pool, 1).await?;
+        let user = get_user_by_id(&pool, 1).await?.unwrap();
+        let password = Secret::new(String::from("r00t_p4ssword"));
+
+        assert_that!(rehash_password(&pool, &user, &password).await?).is_true();
+        let rehashed = get_user_by_id(&pool, 1).await?.unwrap();
+        assert_that!(rehashed.password.as_str()).starts_with("$argon2id$");
+        assert_that!(verify_password(&rehashed.password, &password).await)
+            .is_equal_to(PasswordCheck::Valid);
+        assert_that!(get_token_version(&pool, 1).await?).is_equal_to(version);
+
+        // Already replaced since the user was read
+        assert_that!(rehash_password(&pool, &user, &password).await?).is_false();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_list_users_with_filters(pool: Pool) -> anyhow::Result<()> {
+        let all = list_users(&pool, None, None, None, 1, 20).await?;
+        assert_that!(all.content()).has_length(2);
+        let root = all.content().iter().find(|user| user.id == 1).unwrap();
+        assert_that!(root.subscriptions).is_equal_to(2);
+
+        let admins = list_users(&pool, Some(&UserRole::Admin), None, None, 1, 20).await?;
+        assert_that!(admins
+            .content()
+            .iter()
+            .map(|user| user.id)
+            .collect::<Vec<_>>())
+        .is_equal_to(vec![1]);
+
+        let found = list_users(&pool, None, Some(false), Some("DOE"), 1, 20).await?;
+        assert_that!(found
+            .content()
+            .iter()
+            .map(|user| user.id)
+            .collect::<Vec<_>>())
+        .is_equal_to(vec![2]);
+
+        // The wildcards are searched as is
+        let none = list_users(&pool, None, None, Some("%"), 1, 20).await?;
+        assert_that!(none.content()).is_empty();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_update_account(pool: Pool) -> anyhow::Result<()> {
+        create_session(&pool, 2, None, None).await?;
+        let version = get_token_version(&pool, 2).await?.unwrap();
+
+        // Nothing changes, nothing is revoked
+        let revoked =
+            update_account(&pool, 2, Some("john_doe"), Some(&UserRole::Basic), None).await?;
+        assert_that!(revoked).is_false();
+        assert_that!(get_token_version(&pool, 2).await?).is_equal_to(Some(version));
+
+        let revoked =
+            update_account(&pool, 2, Some("jane_doe"), Some(&UserRole::Admin), None).await?;
+        assert_that!(revoked).is_true();
+        let user = get_user_by_id(&pool, 2).await?.unwrap();
+        assert_that!(user.username.as_str()).is_equal_to("jane_doe");
+        assert_that!(user.role).is_equal_to(UserRole::Admin);
+        assert_that!(user.token_version).is_equal_to(version + 1);
+        assert_that!(list_sessions(&pool, 2).await?).has_length(1);
+
+        // A disabled user loses their sessions and tokens
+        let revoked = update_account(&pool, 2, None, None, Some(true)).await?;
+        assert_that!(revoked).is_true();
+        assert_that!(list_sessions(&pool, 2).await?).is_empty();
+        assert_that!(get_token_version(&pool, 2).await?).is_none();
+
+        let result = update_account(&pool, 2, Some("root"), None, None).await;
+        assert!(matches!(result, Err(UserError::UsernameTaken)));
+        let result = update_account(&pool, 2, Some(" "), None, None).await;
+        assert!(matches!(result, Err(UserError::EmptyUsername)));
+        let result = update_account(&pool, 999, None, None, Some(true)).await;
+        assert!(matches!(result, Err(UserError::NotFound(_, 999))));
+
+        Ok(())
+    }
+}
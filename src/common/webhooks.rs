@@ -0,0 +1,539 @@
+//! Outgoing webhooks, called with the new items of their user after each fetch.
+//! Payloads are signed with HMAC-SHA256, using the secret of the webhook as key.
+//! The webhooks cannot target the network of the server: their host is resolved before each delivery, and the request
+//! is sent to the checked address, so that a DNS answer changing in between does not get around the check.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::Result;
+use tracing::{error, instrument, warn};
+
+use crate::common::errors::WebhookError;
+use crate::common::model::{Webhook, WebhookDelivery};
+use crate::common::{DbError, Pool};
+
+/// Header holding the signature of the payload, as `sha256=<hex digest>`
+pub const SIGNATURE_HEADER: &str = "X-Harss-Signature";
+
+/// Header holding the type of event
+pub const EVENT_HEADER: &str = "X-Harss-Event";
+
+/// Number of delivery attempts before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Number of deliveries returned by the delivery log
+const DELIVERIES_LIMIT: i64 = 50;
+
+/// A new item, as sent to the webhooks
+#[derive(Debug, Serialize)]
+pub struct WebhookItem {
+    pub id: i32,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub content: Option<String>,
+    pub publish_timestamp: Option<DateTime<Utc>>,
+}
+
+/// A webhook to call for a channel, with the name the user gave to the channel
+struct WebhookTarget {
+    id: i32,
+    url: String,
+    secret: String,
+    keyword: Option<String>,
+    channel_name: String,
+}
+
+/// Register a new webhook for the user, returning its id
+#[instrument(skip(db, secret))]
+pub async fn create_webhook(
+    db: &Pool,
+    user_id: i32,
+    url: &str,
+    secret: &str,
+    channel_id: Option<i32>,
+    keyword: Option<&str>,
+) -> Result<i32> {
+    sqlx::query_scalar!(
+        r#"
+        INSERT INTO webhooks (user_id, url, secret, channel_id, keyword)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+        user_id,
+        url,
+        secret,
+        channel_id,
+        keyword
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// List the webhooks of the user
+#[instrument(skip(db))]
+pub async fn list_webhooks(db: &Pool, user_id: i32) -> Result<Vec<Webhook>> {
+    sqlx::query_as!(
+        Webhook,
+        r#"
+        SELECT id, url, channel_id, keyword, creation_timestamp
+        FROM webhooks
+        WHERE user_id = $1
+        ORDER BY id
+        "#,
+        user_id
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Delete a webhook of the user, along with its delivery log
+#[instrument(skip(db))]
+pub async fn delete_webhook(db: &Pool, user_id: i32, webhook_id: i32) -> Result<()> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM webhooks WHERE id = $1 AND user_id = $2
+        "#,
+        webhook_id,
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(DbError::RowNotFound);
+    }
+
+    Ok(())
+}
+
+/// Return the last delivery attempts of a webhook of the user, most recent first
+#[instrument(skip(db))]
+pub async fn list_deliveries(
+    db: &Pool,
+    user_id: i32,
+    webhook_id: i32,
+) -> Result<Vec<WebhookDelivery>> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT id FROM webhooks WHERE id = $1 AND user_id = $2
+        "#,
+        webhook_id,
+        user_id
+    )
+    .fetch_optional(db)
+    .await?
+    .ok_or(DbError::RowNotFound)?;
+
+    sqlx::query_as!(
+        WebhookDelivery,
+        r#"
+        SELECT id, attempt, delivery_timestamp, items_count, status_code, error
+        FROM webhooks_deliveries
+        WHERE webhook_id = $1
+        ORDER BY id DESC
+        LIMIT $2
+        "#,
+        webhook_id,
+        DELIVERIES_LIMIT
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Call the webhooks of the users subscribed to the channel with its new items.
+/// The deliveries are made in the background, this only looks for the webhooks to call.
+#[instrument(skip(db, items))]
+pub async fn notify_new_items(db: &Pool, channel_id: i32, items: &[WebhookItem]) -> Result<()> {
+    let targets = sqlx::query_as!(
+        WebhookTarget,
+        r#"
+        SELECT      webhooks.id,
+                    webhooks.url,
+                    webhooks.secret,
+                    webhooks.keyword,
+                    channel_users.name AS channel_name
+        FROM        webhooks
+                    JOIN channel_users ON channel_users.user_id = webhooks.user_id
+        WHERE       channel_users.channel_id = $1
+        AND         (webhooks.channel_id IS NULL OR webhooks.channel_id = $1)
+        "#,
+        channel_id
+    )
+    .fetch_all(db)
+    .await?;
+
+    for target in targets {
+        let items = items
+            .iter()
+            .filter(|item| matches_keyword(item, target.keyword.as_deref()))
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            continue;
+        }
+
+        let payload = json!({
+            "event": "new_items",
+            "channel": { "id": channel_id, "name": target.channel_name },
+            "items": items,
+        })
+        .to_string();
+        let items_count = items.len() as i32;
+
+        let db = db.clone();
+        tokio::task::spawn(async move {
+            deliver(
+                &db,
+                &target,
+                &payload,
+                items_count,
+                retry_delay(),
+                allow_private_addresses(),
+            )
+            .await;
+        });
+    }
+
+    Ok(())
+}
+
+/// Compute the hexadecimal HMAC-SHA256 signature of a payload
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(payload.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Resolve the host of a webhook url, refusing the urls which are not http or https, and the hosts resolving to a
+/// loopback, private, link-local or unspecified address, unless they are allowed.
+pub async fn resolve_target(
+    url: &str,
+    allow_private_addresses: bool,
+) -> std::result::Result<(Url, SocketAddr), WebhookError> {
+    let url = Url::parse(url).map_err(|_| WebhookError::InvalidUrl)?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(WebhookError::InvalidUrl);
+    }
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err(WebhookError::InvalidUrl);
+    };
+    // The brackets of the IPv6 addresses are not part of the host to resolve
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addresses = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| WebhookError::UnknownHost(host.to_owned()))?
+        .collect::<Vec<_>>();
+
+    if !allow_private_addresses {
+        if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+            return Err(WebhookError::PrivateAddress(address.ip()));
+        }
+    }
+
+    match addresses.into_iter().next() {
+        Some(address) => Ok((url, address)),
+        None => Err(WebhookError::UnknownHost(host.to_owned())),
+    }
+}
+
+/// Whether the webhooks may target the network of the server, from the configuration
+pub fn allow_private_addresses() -> bool {
+    std::env::var("WEBHOOK_ALLOW_PRIVATE_ADDRESSES")
+        .map(|x| x.parse().unwrap_or(false))
+        .unwrap_or(false)
+}
+
+/// Whether the address can be reached from outside of the network of the server
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            !(address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_multicast())
+        }
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => is_public(IpAddr::V4(address)),
+            None => {
+                let first_segment = address.segments()[0];
+                !(address.is_loopback()
+                    || address.is_unspecified()
+                    || address.is_multicast()
+                    // Unique local, fc00::/7
+                    || (first_segment & 0xfe00) == 0xfc00
+                    // Link-local, fe80::/10
+                    || (first_segment & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Send the payload to the address of the webhook. The redirections are not followed, as they could lead anywhere.
+async fn send(
+    target: &WebhookTarget,
+    payload: &str,
+    signature: &str,
+    allow_private_addresses: bool,
+) -> std::result::Result<reqwest::Response, WebhookError> {
+    let (url, address) = resolve_target(&target.url, allow_private_addresses).await?;
+
+    let mut client = Client::builder()
+        .user_agent("HaRSS webhooks (+https://github.com/fistons/rss-aggregator)")
+        .timeout(Duration::from_secs(10))
+        .redirect(Policy::none());
+    if let Some(domain) = url.domain() {
+        client = client.resolve(domain, address);
+    }
+
+    Ok(client
+        .build()?
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(EVENT_HEADER, "new_items")
+        .body(payload.to_owned())
+        .send()
+        .await?)
+}
+
+/// Send the payload to the webhook, retrying with an exponential backoff until it answers with a success status.
+/// Each attempt is saved in the delivery log.
+async fn deliver(
+    db: &Pool,
+    target: &WebhookTarget,
+    payload: &str,
+    items_count: i32,
+    retry_delay: Duration,
+    allow_private_addresses: bool,
+) {
+    let signature = format!("sha256={}", sign(&target.secret, payload));
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        let response = send(target, payload, &signature, allow_private_addresses).await;
+
+        let (status_code, error) = match &response {
+            Ok(response) => (Some(response.status().as_u16() as i32), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        if let Err(e) = sqlx::query!(
+            r#"
+            INSERT INTO webhooks_deliveries (webhook_id, attempt, items_count, status_code, error)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+            target.id,
+            attempt as i32,
+            items_count,
+            status_code,
+            error
+        )
+        .execute(db)
+        .await
+        {
+            error!("Could not log the delivery of webhook {}: {}", target.id, e);
+        }
+
+        match &response {
+            Ok(response) if response.status().is_success() => return,
+            Err(e @ (WebhookError::InvalidUrl | WebhookError::PrivateAddress(_))) => {
+                warn!("Refusing the delivery of webhook {}: {}", target.id, e);
+                return;
+            }
+            _ => {}
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(retry_delay * 2u32.pow(attempt - 1)).await;
+        }
+    }
+
+    warn!(
+        "Giving up the delivery of webhook {} after {} attempts",
+        target.id, MAX_ATTEMPTS
+    );
+}
+
+/// Check if the title or the content of the item contains the keyword, ignoring the case
+fn matches_keyword(item: &WebhookItem, keyword: Option<&str>) -> bool {
+    let Some(keyword) = keyword else {
+        return true;
+    };
+    let keyword = keyword.to_lowercase();
+
+    [&item.title, &item.content]
+        .into_iter()
+        .flatten()
+        .any(|text| text.to_lowercase().contains(&keyword))
+}
+
+/// Delay before the first retry of a delivery, in seconds. It doubles after each attempt.
+fn retry_delay() -> Duration {
+    let seconds = std::env::var("WEBHOOK_RETRY_DELAY")
+        .map(|x| x.parse::<u64>().unwrap_or(30))
+        .unwrap_or(30);
+    Duration::from_secs(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use speculoos::prelude::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+
+    fn item(title: &str) -> WebhookItem {
+        WebhookItem {
+            id: 1,
+            title: Some(title.to_owned()),
+            url: None,
+            content: None,
+            publish_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_sign() {
+        let signature = sign("key", "The quick brown fox jumps over the lazy dog");
+        assert_that!(signature).is_equal_to(
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8".to_owned(),
+        );
+    }
+
+    #[test]
+    fn test_matches_keyword() {
+        assert_that!(matches_keyword(&item("Rust 1.76 is out"), None)).is_true();
+        assert_that!(matches_keyword(&item("Rust 1.76 is out"), Some("rust"))).is_true();
+        assert_that!(matches_keyword(&item("Go 1.22 is out"), Some("rust"))).is_false();
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_webhooks(pool: Pool) -> Result<()> {
+        let id = create_webhook(&pool, 1, "https://example.com", "secret", Some(2), None).await?;
+
+        let webhooks = list_webhooks(&pool, 1).await?;
+        assert_that!(webhooks).has_length(1);
+        assert_that!(webhooks[0].channel_id).is_equal_to(Some(2));
+        assert_that!(list_webhooks(&pool, 2).await?).is_empty();
+
+        assert_that!(delete_webhook(&pool, 2, id).await).is_err();
+        assert_that!(list_deliveries(&pool, 2, id).await).is_err();
+        delete_webhook(&pool, 1, id).await?;
+        assert_that!(list_webhooks(&pool, 1).await?).is_empty();
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_deliver_with_retry(pool: Pool) -> Result<()> {
+        let mock = MockServer::start().await;
+        let payload = r#"{"event":"new_items"}"#;
+        let signature = format!("sha256={}", sign("secret", payload));
+
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&mock)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .and(header(SIGNATURE_HEADER, signature.as_str()))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock)
+            .await;
+
+        let url = format!("{}/hook", mock.uri());
+        let id = create_webhook(&pool, 1, &url, "secret", None, None).await?;
+        let target = WebhookTarget {
+            id,
+            url,
+            secret: "secret".to_owned(),
+            keyword: None,
+            channel_name: "Canard PC".to_owned(),
+        };
+        deliver(&pool, &target, payload, 3, Duration::ZERO, true).await;
+
+        let deliveries = list_deliveries(&pool, 1, id).await?;
+        assert_that!(deliveries).has_length(2);
+        assert_that!(deliveries[0].status_code).is_equal_to(Some(204));
+        assert_that!(deliveries[0].attempt).is_equal_to(2);
+        assert_that!(deliveries[1].status_code).is_equal_to(Some(503));
+
+        Ok(())
+    }
+
+    #[sqlx::test(fixtures("base_fixtures"), migrations = "./migrations")]
+    async fn test_deliver_to_private_address(pool: Pool) -> Result<()> {
+        let mock = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(0)
+            .mount(&mock)
+            .await;
+
+        let url = format!("{}/hook", mock.uri());
+        let id = create_webhook(&pool, 1, &url, "secret", None, None).await?;
+        let target = WebhookTarget {
+            id,
+            url,
+            secret: "secret".to_owned(),
+            keyword: None,
+            channel_name: "Canard PC".to_owned(),
+        };
+        deliver(&pool, &target, "{}", 1, Duration::ZERO, false).await;
+
+        // Refused once and for all, without retrying
+        let deliveries = list_deliveries(&pool, 1, id).await?;
+        assert_that!(deliveries).has_length(1);
+        assert_that!(deliveries[0].status_code).is_none();
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_target() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/hook",
+            "http://192.168.1.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+        ] {
+            assert!(
+                matches!(
+                    resolve_target(url, false).await,
+                    Err(WebhookError::PrivateAddress(_))
+                ),
+                "{} is not refused",
+                url
+            );
+        }
+        assert!(matches!(
+            resolve_target("ftp://example.com/hook", false).await,
+            Err(WebhookError::InvalidUrl)
+        ));
+
+        let (_, address) = resolve_target("https://93.184.216.34/hook", false)
+            .await
+            .unwrap();
+        assert_that!(address.to_string()).is_equal_to("93.184.216.34:443".to_owned());
+        assert_that!(resolve_target("http://127.0.0.1:8080/hook", true).await).is_ok();
+    }
+}
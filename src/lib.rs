@@ -7,8 +7,9 @@ pub mod services;
 pub mod startup;
 
 pub mod errors {
-    use actix_web::http::StatusCode;
+    use actix_web::http::{header, StatusCode};
     use actix_web::{HttpResponse, ResponseError};
+    use serde_json::json;
 
     #[derive(thiserror::Error, Debug)]
     pub enum AuthenticationError {
@@ -20,7 +21,9 @@ pub mod errors {
         InvalidJwt(#[from] jwt::Error),
         #[error("JWT Token is expired. Please renew it")]
         ExpiredToken,
-        #[error("Unsupported authentication scheme, Only Basic HTTP and JWT Bearer are supported")]
+        #[error("Too many failed logins, retry in {0} seconds")]
+        Locked(i64),
+        #[error("Unsupported authentication scheme, Only Basic HTTP, JWT Bearer and personal Token are supported")]
         UnknownAuthScheme,
         #[error(transparent)]
         Other(#[from] anyhow::Error),
@@ -33,6 +36,7 @@ pub mod errors {
                 | AuthenticationError::InvalidJwt(_)
                 | AuthenticationError::ExpiredToken => StatusCode::UNAUTHORIZED,
                 AuthenticationError::Forbidden(_) => StatusCode::FORBIDDEN,
+                AuthenticationError::Locked(_) => StatusCode::TOO_MANY_REQUESTS,
                 AuthenticationError::UnknownAuthScheme => StatusCode::BAD_REQUEST,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             }
@@ -40,7 +44,18 @@ pub mod errors {
 
         fn error_response(&self) -> HttpResponse {
             //TODO: Let's have a real response next time
-            HttpResponse::build(self.status_code()).finish()
+            match self {
+                AuthenticationError::Locked(retry_after) => HttpResponse::build(self.status_code())
+                    .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                    .finish(),
+                AuthenticationError::Forbidden(detail) => {
+                    HttpResponse::Forbidden().json(json!({"type": "/problem/forbidden",
+                        "title": "Forbidden",
+                        "status": 403,
+                        "detail": detail}))
+                }
+                _ => HttpResponse::build(self.status_code()).finish(),
+            }
         }
     }
 }
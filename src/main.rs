@@ -4,7 +4,7 @@ use std::net::TcpListener;
 use tokio_cron_scheduler::{Job, JobScheduler};
 use tracing::{error, info};
 
-use harss_api::common::{init_postgres_connection, init_redis_connection};
+use harss_api::common::{digests, init_postgres_connection, init_redis_connection};
 use harss_api::services;
 use harss_api::startup;
 
@@ -53,6 +53,26 @@ async fn main() -> std::io::Result<()> {
         )
         .await
         .expect("Could not schedule fetching task");
+
+    let postgres_connection_clone = postgres_connection.clone();
+    let digest_schedule = env::var("DIGEST_CRON").unwrap_or("0 0 7 * * *".to_owned());
+    sched
+        .add(
+            Job::new_async(&digest_schedule[..], move |_, _| {
+                let postgres_connection = postgres_connection_clone.clone();
+                Box::pin(async move {
+                    info!("Scheduled digests sending in progress");
+                    if let Err(e) = digests::send_due_digests(&postgres_connection).await {
+                        error!("Error during the scheduled digests sending: {:?}", e);
+                    } else {
+                        info!("Scheduled digests sending done");
+                    }
+                })
+            })
+            .expect("Could not add create digests task"),
+        )
+        .await
+        .expect("Could not schedule digests task");
     sched.start().await.expect("Could not start scheduler");
 
     startup::startup(postgres_connection, redis_pool, listener).await
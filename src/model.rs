@@ -1,6 +1,10 @@
 //! Http model
 
-pub use crate::common::model::UserRole;
+pub use crate::common::model::{
+    DigestContent, DigestFrequency, ItemsSort, OutputFeedSource, RuleAction, RuleField,
+    RuleMatching, TokenScope, UserRole,
+};
+use chrono::{DateTime, Utc};
 use secrecy::Secret;
 use serde::Deserialize;
 
@@ -12,6 +16,8 @@ pub struct NewUserRequest {
     pub confirm_password: Secret<String>,
     pub email: Option<Secret<String>>,
     pub role: UserRole,
+    /// Invitation code, to register when the account creation is not open
+    pub invitation: Option<Secret<String>>,
 }
 
 /// Request to register to a new channel
@@ -20,6 +26,22 @@ pub struct RegisterChannelRequest {
     pub url: String,
     pub name: Option<String>,
     pub notes: Option<String>,
+    pub folder_id: Option<i32>,
+}
+
+/// Request to set the Fever API password of the user, `None` disabling the Fever API
+#[derive(Debug, Deserialize)]
+pub struct FeverPasswordRequest {
+    pub password: Option<Secret<String>>,
+}
+
+/// Request to update the user's metadata and settings of a channel
+#[derive(Debug, Deserialize)]
+pub struct UpdateChannelRequest {
+    pub name: Option<String>,
+    pub notes: Option<String>,
+    pub hide_from_river: Option<bool>,
+    pub default_sort: Option<ItemsSort>,
 }
 
 /// Filter parameters on read/starred items' status
@@ -29,6 +51,29 @@ pub struct ReadStarredParameters {
     pub starred: Option<bool>,
 }
 
+/// Filter parameters on items
+#[derive(Debug, Deserialize)]
+pub struct ItemsFilterParameters {
+    pub tag: Option<i32>,
+    pub folder: Option<i32>,
+    pub sort: Option<ItemsSort>,
+}
+
+/// Filter parameters on channels
+#[derive(Debug, Deserialize)]
+pub struct ChannelsFilterParameters {
+    pub folder: Option<i32>,
+}
+
+/// Filter parameters on users
+#[derive(Debug, Deserialize)]
+pub struct UsersFilterParameters {
+    pub role: Option<UserRole>,
+    pub disabled: Option<bool>,
+    /// Part of the username
+    pub search: Option<String>,
+}
+
 /// Represent a list of IDs (could be item, channel, etc)
 #[derive(Debug, Deserialize)]
 pub struct IdListParameter {
@@ -76,6 +121,14 @@ pub struct UpdateUserRequest {
     pub email: Option<Secret<String>>,
 }
 
+/// Changes of an account by an admin
+#[derive(Debug, Deserialize)]
+pub struct UpdateAccountRequest {
+    pub username: Option<String>,
+    pub role: Option<UserRole>,
+    pub disabled: Option<bool>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ItemNotesRequest {
     pub notes: String,
@@ -93,3 +146,107 @@ pub struct ResetPasswordTokenRequest {
     pub new_password: Secret<String>,
     pub confirm_password: Secret<String>,
 }
+
+/// Request to create or rename a tag
+#[derive(Debug, Deserialize)]
+pub struct TagRequest {
+    pub name: String,
+}
+
+/// Request to create, rename or move a folder
+#[derive(Debug, Deserialize)]
+pub struct FolderRequest {
+    pub name: String,
+    pub parent_id: Option<i32>,
+}
+
+/// Request to move a channel in a folder
+#[derive(Debug, Deserialize)]
+pub struct ChannelFolderRequest {
+    pub folder_id: Option<i32>,
+}
+
+/// Request to register a webhook, called with the new items of the user.
+/// The webhook can be restricted to a channel, and to the items matching a keyword.
+#[derive(Debug, Deserialize)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub secret: Secret<String>,
+    pub channel_id: Option<i32>,
+    pub keyword: Option<String>,
+}
+
+/// Request to create an output feed, republishing the starred items, or the items of a tag, of a folder or of a saved search
+#[derive(Debug, Deserialize)]
+pub struct OutputFeedRequest {
+    pub name: String,
+    pub source: OutputFeedSource,
+    pub source_id: Option<i32>,
+}
+
+/// Request to create, replace or try a filter rule
+#[derive(Debug, Deserialize)]
+pub struct FilterRuleRequest {
+    pub channel_id: Option<i32>,
+    pub field: RuleField,
+    pub matching: RuleMatching,
+    pub pattern: String,
+    pub action: RuleAction,
+    pub tag_id: Option<i32>,
+}
+
+/// Request to create or replace a saved search
+#[derive(Debug, Deserialize)]
+pub struct SavedSearchRequest {
+    pub name: String,
+    pub terms: Option<String>,
+    #[serde(default)]
+    pub channel_ids: Vec<i32>,
+    #[serde(default)]
+    pub folder_ids: Vec<i32>,
+    #[serde(default)]
+    pub tag_ids: Vec<i32>,
+    pub read: Option<bool>,
+    pub starred: Option<bool>,
+    pub window_days: Option<i32>,
+}
+
+/// Request to enable, or change, the email digest of the user
+#[derive(Debug, Deserialize)]
+pub struct DigestRequest {
+    /// Verified email of the user, the digest is sent to
+    pub email: Secret<String>,
+    pub frequency: DigestFrequency,
+    pub content: DigestContent,
+    pub folder_id: Option<i32>,
+}
+
+/// Request to create a personal token
+#[derive(Debug, Deserialize)]
+pub struct PersonalTokenRequest {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub expiration_timestamp: Option<DateTime<Utc>>,
+}
+
+/// Request to create an invitation
+#[derive(Debug, Deserialize)]
+pub struct InvitationRequest {
+    pub role: Option<UserRole>,
+    #[serde(default)]
+    pub channels: Vec<String>,
+    pub max_uses: Option<i32>,
+    pub expiration_timestamp: Option<DateTime<Utc>>,
+}
+
+/// Request carrying a TOTP code, or a recovery code
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeRequest {
+    pub code: Secret<String>,
+}
+
+/// Request to change the two-factor authentication settings of the user
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorSettingsRequest {
+    pub allow_basic_auth: bool,
+}
@@ -3,6 +3,7 @@ use actix_governor::{
     GovernorConfig, GovernorConfigBuilder, KeyExtractor, SimpleKeyExtractionError,
 };
 use actix_web::dev::ServiceRequest;
+use http_auth_basic::Credentials;
 use std::env::var;
 
 #[derive(Clone)]
@@ -20,6 +21,10 @@ impl KeyExtractor for UserToken {
             .map(|token| token.trim().to_owned());
 
         if let Some(auth) = auth {
+            // Guessed passwords of a same user share their bucket
+            if let Ok(credentials) = Credentials::from_header(auth.clone()) {
+                return Ok(format!("basic.{}", credentials.user_id));
+            }
             return Ok(auth);
         }
 
@@ -1,13 +1,21 @@
-use actix_web::{post, web, HttpResponse};
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use anyhow::{anyhow, Context};
-use redis::AsyncCommands;
 use secrecy::{ExposeSecret, Secret};
 use serde::Deserialize;
 use serde_json::json;
-use tracing::{debug_span, Instrument};
-use uuid::Uuid;
 
-use crate::common::users::get_user_by_username;
+use crate::common::errors::OidcError;
+use crate::common::lockouts::{self, LockoutPolicy};
+use crate::common::model::User;
+use crate::common::oidc::{self, OidcConfig};
+use crate::common::users::{self, get_user_by_id};
+use crate::common::{hash_secret, public_url, sessions, two_factor, Pool};
+use crate::errors::AuthenticationError;
+
+use crate::auth::{check_and_get_user, client_ip, get_jwt};
 
 use crate::routes::errors::ApiError;
 use crate::startup::AppState;
@@ -23,56 +31,248 @@ pub struct RefreshRequest {
     token: Secret<String>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct TwoFactorLoginRequest {
+    challenge: String,
+    code: Secret<String>,
+}
+
 #[post("/auth/login")]
 pub async fn login(
     login: web::Json<LoginRequest>,
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let ip_address = client_ip(&req);
+    let user = check_and_get_user(
+        connection,
+        &app_state.redis,
+        &login.login,
+        &login.password,
+        ip_address.as_deref(),
+    )
+    .await?;
+
+    // The password is not enough, the client has to send a code with the challenge
+    if two_factor::is_enabled(connection, user.id).await? {
+        let challenge = two_factor::create_challenge(&app_state.redis, user.id).await?;
+        return Ok(HttpResponse::Ok().json(json!({
+            "two_factor_challenge": challenge,
+            "expires_in": two_factor::CHALLENGE_TTL
+        })));
+    }
+
+    let (access_token, refresh_token) = open_session(&user, &req, connection).await?;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"access_token": access_token, "refresh_token": refresh_token})))
+}
+
+#[post("/auth/login/2fa")]
+pub async fn login_second_factor(
+    request: web::Json<TwoFactorLoginRequest>,
+    req: HttpRequest,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
-    let redis_pool = &app_state.redis;
+    let redis = &app_state.redis;
 
-    let access_token =
-        crate::auth::get_jwt_from_login_request(&login.login, &login.password, connection).await?;
-    let refresh_token = format!("user.{}.{}", &login.login, Uuid::new_v4());
+    let Some(user_id) = two_factor::challenge_user(redis, &request.challenge).await? else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
 
-    redis_pool
-        .get()
+    let user = get_user_by_id(connection, user_id)
+        .await
+        .context("Could not get user")?
+        .ok_or_else(|| anyhow!("Unknown user"))?;
+
+    // The wrong codes are counted with the wrong passwords of the user, whatever the challenge they are sent to
+    let ip_address = client_ip(&req);
+    if let Some(retry_after) =
+        lockouts::locked_for(redis, &user.username, ip_address.as_deref()).await?
+    {
+        return Err(AuthenticationError::Locked(retry_after).into());
+    }
+
+    if two_factor::complete_challenge(connection, redis, &request.challenge, &request.code)
         .await?
-        .set_ex::<_, _, ()>(&refresh_token, 1, 60 * 60 * 24 * 5)
-        .instrument(debug_span!("set_refresh_token_in_redis"))
+        .is_none()
+    {
+        let delay = lockouts::record_failure(
+            redis,
+            &LockoutPolicy::from_env(),
+            &user.username,
+            ip_address.as_deref(),
+        )
         .await?;
+        tokio::time::sleep(delay).await;
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    lockouts::record_success(redis, &user.username).await?;
+
+    let (access_token, refresh_token) = open_session(&user, &req, connection).await?;
 
     Ok(HttpResponse::Ok()
         .json(json!({"access_token": access_token, "refresh_token": refresh_token})))
 }
 
+#[derive(Deserialize, Debug)]
+pub struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+/// Start a single sign-on login, redirecting to the identity provider
+#[get("/auth/oidc/login")]
+pub async fn oidc_login(app_state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let config = OidcConfig::from_env().ok_or(OidcError::NotConfigured)?;
+
+    let (url, state, pending_login) = oidc::start_login(&config).await?;
+    oidc::save_pending_login(&app_state.redis, &state, &pending_login).await?;
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .cookie(state_cookie(hash_secret(&state)))
+        .finish())
+}
+
+/// Complete a single sign-on login, when the identity provider redirects the user back.
+/// The token pair is returned, or given to the frontend in the fragment of its URL if `OIDC_POST_LOGIN_URL` is set.
+#[get("/auth/oidc/callback")]
+pub async fn oidc_callback(
+    callback: web::Query<OidcCallback>,
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let config = OidcConfig::from_env().ok_or(OidcError::NotConfigured)?;
+    let callback = callback.into_inner();
+
+    if let Some(error) = callback.error {
+        return Err(OidcError::Denied(callback.error_description.unwrap_or(error)).into());
+    }
+    let (Some(code), Some(state)) = (callback.code, callback.state) else {
+        return Err(OidcError::InvalidState.into());
+    };
+
+    // The login must come back to the browser which started it, not to the victim of a forged callback link
+    if req
+        .cookie(oidc::STATE_COOKIE)
+        .map_or(true, |cookie| cookie.value() != hash_secret(&state))
+    {
+        return Err(OidcError::InvalidState.into());
+    }
+
+    let pending_login = oidc::take_pending_login(&app_state.redis, &state)
+        .await?
+        .ok_or(OidcError::InvalidState)?;
+    let identity = oidc::exchange_code(&config, &code, &pending_login).await?;
+    let user = oidc::link_account(connection, &config, &identity).await?;
+    // The role may have changed along with the token version
+    users::forget_token_version(&app_state.redis, user.id).await?;
+
+    let (access_token, refresh_token) = open_session(&user, &req, connection).await?;
+
+    let mut expired_cookie = state_cookie(String::new());
+    expired_cookie.make_removal();
+
+    match std::env::var("OIDC_POST_LOGIN_URL") {
+        Ok(url) => {
+            let fragment: String = form_urlencoded::Serializer::new(String::new())
+                .append_pair("access_token", &access_token)
+                .append_pair("refresh_token", &refresh_token)
+                .finish();
+            Ok(HttpResponse::Found()
+                .insert_header((header::LOCATION, format!("{}#{}", url, fragment)))
+                .cookie(expired_cookie)
+                .finish())
+        }
+        Err(_) => Ok(HttpResponse::Ok()
+            .cookie(expired_cookie)
+            .json(json!({"access_token": access_token, "refresh_token": refresh_token}))),
+    }
+}
+
+/// Cookie holding the hash of the state of a single sign-on login, only sent back to the callback
+fn state_cookie(value: String) -> Cookie<'static> {
+    Cookie::build(oidc::STATE_COOKIE, value)
+        .path("/api/v1/auth/oidc")
+        .http_only(true)
+        .secure(public_url().starts_with("https://"))
+        .same_site(SameSite::Lax)
+        .max_age(CookieDuration::seconds(oidc::LOGIN_TTL as i64))
+        .finish()
+}
+
+/// Open a session for the authenticated user, returning its first token pair
+async fn open_session(
+    user: &User,
+    req: &HttpRequest,
+    connection: &Pool,
+) -> Result<(String, String), ApiError> {
+    let access_token = get_jwt(user).await?;
+
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let ip_address = client_ip(req);
+    let refresh_token =
+        sessions::create_session(connection, user.id, user_agent, ip_address.as_deref()).await?;
+
+    Ok((access_token, refresh_token))
+}
+
 #[post("/auth/refresh")]
 pub async fn refresh_auth(
     refresh_token: web::Json<RefreshRequest>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
-    let redis_pool = &app_state.redis;
-    let mut redis = redis_pool.get().await?;
     let token = refresh_token.token.expose_secret();
-    let token_exists = redis.exists::<_, bool>(token).await?;
-
-    if token_exists {
-        let user_login = crate::auth::extract_login_from_refresh_token(token);
-        let user = get_user_by_username(connection, user_login)
-            .await
-            .context("Could not get user")?
-            .ok_or_else(|| anyhow!("Unknown user"))?;
-        /* Create a new JWT */
-        let access_token = crate::auth::get_jwt(&user).await?;
-
-        Ok(HttpResponse::Ok().json(json!({ "access_token": access_token })))
-    } else {
-        Ok(HttpResponse::Unauthorized().finish())
+
+    let Some((user_id, refresh_token)) = sessions::rotate_refresh_token(connection, token).await?
+    else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+
+    let user = get_user_by_id(connection, user_id)
+        .await
+        .context("Could not get user")?
+        .ok_or_else(|| anyhow!("Unknown user"))?;
+    // The sessions of a disabled account are deleted, but one may have been rotated meanwhile
+    if user.disabled {
+        sessions::revoke_session_of_token(connection, &refresh_token).await?;
+        return Ok(HttpResponse::Unauthorized().finish());
     }
+    /* Create a new JWT */
+    let access_token = get_jwt(&user).await?;
+
+    Ok(HttpResponse::Ok()
+        .json(json!({"access_token": access_token, "refresh_token": refresh_token})))
+}
+
+#[post("/auth/logout")]
+pub async fn logout(
+    refresh_token: web::Json<RefreshRequest>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    sessions::revoke_session_of_token(connection, refresh_token.token.expose_secret()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
 }
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(login);
+    cfg.service(login_second_factor);
+    cfg.service(oidc_login);
+    cfg.service(oidc_callback);
     cfg.service(refresh_auth);
+    cfg.service(logout);
 }
@@ -1,14 +1,25 @@
+use actix_web::http::header::ContentDisposition;
 use actix_web::http::StatusCode;
-use actix_web::{delete, get, post, web, HttpResponse};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use serde::Deserialize;
 use serde_json::json;
 
 use crate::common::channels;
+use crate::common::counters::forget_counters;
+use crate::common::events::{self, Event};
+use crate::common::exports;
+use crate::common::folders;
 use crate::common::items;
 use crate::common::rss;
 
 use crate::auth::AuthenticatedUser;
-use crate::model::{PageParameters, RegisterChannelRequest};
+use crate::common::channels::SubscriptionUpdate;
+use crate::common::DbError::RowNotFound;
+use crate::errors::AuthenticationError;
+use crate::model::{
+    ChannelFolderRequest, ChannelsFilterParameters, ItemsFilterParameters, PageParameters,
+    RegisterChannelRequest, UpdateChannelRequest,
+};
 use crate::routes::errors::ApiError;
 use crate::startup::AppState;
 
@@ -27,6 +38,47 @@ pub async fn get_channel(
     }
 }
 
+#[patch("/channel/{id}")]
+pub async fn update_channel(
+    id: web::Path<i32>,
+    request: web::Json<UpdateChannelRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+    let request = request.into_inner();
+
+    if let Some(name) = &request.name {
+        if name.trim().is_empty() || name.chars().count() > 512 {
+            return Err(ApiError::InvalidRequest(
+                "The name must be between 1 and 512 characters long".to_owned(),
+            ));
+        }
+    }
+
+    if let Some(notes) = &request.notes {
+        if notes.chars().count() > 5000 {
+            return Err(ApiError::InvalidRequest(
+                "The notes must be at most 5000 characters long".to_owned(),
+            ));
+        }
+    }
+
+    let update = SubscriptionUpdate {
+        name: request.name,
+        notes: request.notes,
+        hide_from_river: request.hide_from_river,
+        default_sort: request.default_sort,
+    };
+
+    match channels::update_subscription(connection, id, user.id, &update).await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(RowNotFound) => Err(ApiError::NotFound(String::from("channel"), id)),
+        Err(e) => Err(ApiError::DatabaseError(e)),
+    }
+}
+
 #[delete("/channel/{id}")]
 pub async fn unsubscribe_channel(
     id: web::Path<i32>,
@@ -34,7 +86,16 @@ pub async fn unsubscribe_channel(
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
-    channels::unsubscribe_channel(connection, id.into_inner(), user.id).await?;
+    let channel_id = id.into_inner();
+    channels::unsubscribe_channel(connection, channel_id, user.id).await?;
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::SubscriptionRemoved { channel_id },
+    )
+    .await;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -63,8 +124,17 @@ pub async fn mark_channel_as_read(
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
+    let channel_id = id.into_inner();
 
-    channels::mark_channel_as_read(connection, id.into_inner(), user.id).await?;
+    channels::mark_channel_as_read(connection, channel_id, user.id).await?;
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::ChannelRead { channel_id },
+    )
+    .await;
 
     Ok(HttpResponse::NoContent().finish())
 }
@@ -73,13 +143,19 @@ pub async fn mark_channel_as_read(
 pub async fn get_channels(
     app_state: web::Data<AppState>,
     page: web::Query<PageParameters>,
+    filters: web::Query<ChannelsFilterParameters>,
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
 
-    let channels =
-        channels::select_page_by_user_id(connection, user.id, page.get_page(), page.get_size())
-            .await?;
+    let channels = channels::select_page_by_user_id(
+        connection,
+        user.id,
+        filters.folder,
+        page.get_page(),
+        page.get_size(),
+    )
+    .await?;
     Ok(HttpResponse::Ok().json(channels))
 }
 
@@ -93,28 +169,64 @@ async fn new_channel(
     let redis = &app_state.redis;
     let data = new_channel.into_inner();
 
+    // Checked first, not to subscribe the user when the folder is wrong
+    if let Some(folder_id) = data.folder_id {
+        folders::get_folder(connection, user.id, folder_id).await?;
+    }
+
     let channel_id = channels::create_or_link_channel(
         connection, redis, &data.url, data.name, data.notes, user.id,
     )
     .await?;
+    if data.folder_id.is_some() {
+        folders::set_channel_folder(connection, user.id, channel_id, data.folder_id).await?;
+    }
+    forget_counters(redis, &[user.id]).await;
+    events::notify(redis, &[user.id], Event::SubscriptionAdded { channel_id }).await;
 
     Ok(HttpResponse::Created().json(json!({"id": channel_id})))
 }
 
+#[put("/channel/{id}/folder")]
+async fn set_channel_folder(
+    id: web::Path<i32>,
+    request: web::Json<ChannelFolderRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    folders::set_channel_folder(connection, user.id, id.into_inner(), request.folder_id).await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[get("/channel/{chan_id}/items")]
 async fn get_items_of_channel(
     chan_id: web::Path<i32>,
     page: web::Query<PageParameters>,
+    filters: web::Query<ItemsFilterParameters>,
     app_state: web::Data<AppState>,
     auth: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
+    let chan_id = chan_id.into_inner();
+
+    let channel = match channels::select_by_id_and_user_id(connection, chan_id, auth.id).await? {
+        Some(channel) => channel,
+        None => return Err(ApiError::NotFound(String::from("channel"), chan_id)),
+    };
+
+    let filters = items::ItemFilters {
+        channel_id: Some(chan_id),
+        sort: filters.sort.unwrap_or(channel.default_sort),
+        ..Default::default()
+    };
 
     let items = items::get_items_of_user(
         connection,
-        Some(chan_id.into_inner()),
-        None,
-        None,
+        &filters,
         auth.id,
         page.get_page(),
         page.get_size(),
@@ -154,13 +266,50 @@ async fn search_channels(
     Ok(HttpResponse::Ok().json(found_channels))
 }
 
+#[get("/channels/export")]
+async fn export_channels(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let document = exports::export_user_channels(&app_state.db, user.id).await?;
+
+    Ok(opml_response(document, "harss-subscriptions.opml"))
+}
+
+#[get("/channels/export/all")]
+async fn export_all_channels(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::AuthenticationError(
+            AuthenticationError::Forbidden("You need to be an administrator".to_owned()),
+        ));
+    }
+
+    let document = exports::export_all_channels(&app_state.db).await?;
+
+    Ok(opml_response(document, "harss-channels.opml"))
+}
+
+fn opml_response(document: String, file_name: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/x-opml; charset=utf-8")
+        .insert_header(ContentDisposition::attachment(file_name))
+        .body(document)
+}
+
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(search_channels)
+        .service(export_channels)
+        .service(export_all_channels)
         .service(mark_channel_as_read)
         .service(get_channel)
+        .service(update_channel)
         .service(get_channels)
         .service(new_channel)
         .service(get_items_of_channel)
+        .service(set_channel_folder)
         .service(enable_channel)
         .service(get_errors_of_channel)
         .service(unsubscribe_channel);
@@ -0,0 +1,24 @@
+use actix_web::{get, web, HttpResponse};
+
+use crate::common::counters;
+
+use crate::auth::AuthenticatedUser;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[get("/counters")]
+pub async fn get_counters(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let redis = &app_state.redis;
+
+    let counters = counters::get_counters(connection, redis, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(counters))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_counters);
+}
@@ -0,0 +1,99 @@
+use actix_web::http::header::ContentType;
+use actix_web::{delete, get, post, put, web, HttpResponse};
+
+use crate::common::digests::{self, DigestDefinition};
+use crate::common::DbError::RowNotFound;
+
+use crate::auth::AuthenticatedUser;
+use crate::model::DigestRequest;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+/// Page of the unsubscribe link, the digest being only disabled by the form, so that link checkers cannot do it
+const UNSUBSCRIBE_PAGE: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Unsubscribe</title></head>\
+<body><form method=\"post\"><p>Stop receiving this digest?</p><button type=\"submit\">Unsubscribe</button></form>\
+</body></html>";
+
+/// Page shown once the digest is disabled
+const UNSUBSCRIBED_PAGE: &str =
+    "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Unsubscribed</title></head>\
+<body><p>You will not receive this digest anymore.</p></body></html>";
+
+#[get("/user/digest")]
+pub async fn get_digest(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    match digests::get_digest(connection, user.id).await? {
+        Some(digest) => Ok(HttpResponse::Ok().json(digest)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[put("/user/digest")]
+pub async fn enable_digest(
+    request: web::Json<DigestRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let request = request.into_inner();
+
+    let digest = DigestDefinition {
+        frequency: request.frequency,
+        content: request.content,
+        folder_id: request.folder_id,
+    };
+    digests::enable_digest(connection, user.id, &request.email, &digest).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/user/digest")]
+pub async fn disable_digest(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    match digests::disable_digest(connection, user.id).await {
+        Ok(()) | Err(RowNotFound) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Public page linked from the digests, asking to confirm the unsubscription
+#[get("/digest/unsubscribe/{token}")]
+pub async fn unsubscribe_page() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(UNSUBSCRIBE_PAGE)
+}
+
+/// Public endpoint authenticated by the unsubscribe token only, posted by the confirmation page or by the mail clients
+/// supporting the one-click unsubscription of the RFC 8058
+#[post("/digest/unsubscribe/{token}")]
+pub async fn unsubscribe(
+    token: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    match digests::unsubscribe(connection, &token).await {
+        Ok(()) => Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(UNSUBSCRIBED_PAGE)),
+        Err(RowNotFound) => Ok(HttpResponse::NotFound().finish()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_digest)
+        .service(enable_digest)
+        .service(disable_digest)
+        .service(unsubscribe_page)
+        .service(unsubscribe);
+}
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use futures_util::{future, stream, StreamExt};
+use tracing::debug;
+
+use crate::auth::{extract_authenticated_user, AuthenticatedUser};
+use crate::common::events::{self, UserEvent};
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+/// Interval between two keep-alive comments, so that proxies do not close idle streams
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[get("/events")]
+pub async fn get_events(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    // Subscribe before reading the history, so that no event is lost in between
+    let pubsub = events::subscribe(user.id).await?;
+    let missed = match last_event_id {
+        Some(last_event_id) => {
+            events::events_since(&mut app_state.redis.get().await?, user.id, last_event_id).await?
+        }
+        None => vec![],
+    };
+    let last_sent = missed
+        .last()
+        .map(|event| event.id)
+        .or(last_event_id)
+        .unwrap_or(0);
+
+    let live = pubsub
+        .into_on_message()
+        .filter_map(move |message| async move {
+            let payload: String = message.get_payload().ok()?;
+            events::parse(&payload).filter(|event| event.id > last_sent)
+        });
+    let events = stream::iter(missed)
+        .chain(live)
+        .map(|event| Some(to_sse(&event)));
+
+    // The credentials are checked again before each keep-alive: once the token is revoked or expired, or the account
+    // disabled, a None ends the stream
+    let keep_alive = stream::unfold(Some(req), |req| async move {
+        let req = req?;
+        tokio::time::sleep(KEEP_ALIVE_INTERVAL).await;
+        match extract_authenticated_user(&req).await {
+            Ok(_) => Some((Some(Bytes::from_static(b": keep-alive\n\n")), Some(req))),
+            Err(e) => {
+                debug!("Closing the events stream: {:?}", e);
+                Some((None, None))
+            }
+        }
+    });
+
+    let body = stream::select(events, keep_alive)
+        .take_while(|chunk| future::ready(chunk.is_some()))
+        .filter_map(|chunk| future::ready(chunk.map(Ok::<_, actix_web::Error>)));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .streaming(body))
+}
+
+/// Format an event as a Server-Sent Event
+fn to_sse(event: &UserEvent) -> Bytes {
+    let data = serde_json::to_string(&event.event).expect("Events are always serializable");
+    Bytes::from(format!("id: {}\ndata: {}\n\n", event.id, data))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_events);
+}
@@ -0,0 +1,214 @@
+use actix_web::{route, web, HttpResponse};
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::{json, Map};
+
+use crate::common::counters::forget_counters;
+use crate::common::events::{self, Event};
+use crate::common::fever::{self, ItemsSelection};
+use crate::common::items;
+
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+/// Version of the Fever API implemented
+const API_VERSION: u32 = 3;
+
+/// Fever does not know about favicons, so every feed uses this transparent one
+const DEFAULT_FAVICON: &str =
+    "image/gif;base64,R0lGODlhAQABAIAAAObm5gAAACH5BAEAAAAALAAAAAABAAEAAAICRAEAOw==";
+
+/// Fever parameters, which can either be sent in the query string or in the form body
+#[derive(Debug, Default, Deserialize)]
+pub struct FeverParameters {
+    api_key: Option<String>,
+    groups: Option<String>,
+    feeds: Option<String>,
+    favicons: Option<String>,
+    items: Option<String>,
+    since_id: Option<i32>,
+    max_id: Option<i32>,
+    with_ids: Option<String>,
+    unread_item_ids: Option<String>,
+    saved_item_ids: Option<String>,
+    mark: Option<String>,
+    #[serde(rename = "as")]
+    mark_as: Option<String>,
+    id: Option<i32>,
+    before: Option<i64>,
+}
+
+impl FeverParameters {
+    /// Complete the query parameters with the form ones
+    fn merge(self, form: FeverParameters) -> Self {
+        FeverParameters {
+            api_key: form.api_key.or(self.api_key),
+            groups: form.groups.or(self.groups),
+            feeds: form.feeds.or(self.feeds),
+            favicons: form.favicons.or(self.favicons),
+            items: form.items.or(self.items),
+            since_id: form.since_id.or(self.since_id),
+            max_id: form.max_id.or(self.max_id),
+            with_ids: form.with_ids.or(self.with_ids),
+            unread_item_ids: form.unread_item_ids.or(self.unread_item_ids),
+            saved_item_ids: form.saved_item_ids.or(self.saved_item_ids),
+            mark: form.mark.or(self.mark),
+            mark_as: form.mark_as.or(self.mark_as),
+            id: form.id.or(self.id),
+            before: form.before.or(self.before),
+        }
+    }
+
+    fn items_selection(&self) -> ItemsSelection {
+        if let Some(with_ids) = &self.with_ids {
+            ItemsSelection::WithIds(
+                with_ids
+                    .split(',')
+                    .filter_map(|id| id.trim().parse().ok())
+                    .take(50)
+                    .collect(),
+            )
+        } else if let Some(max_id) = self.max_id {
+            ItemsSelection::MaxId(max_id)
+        } else {
+            ItemsSelection::SinceId(self.since_id.unwrap_or(0))
+        }
+    }
+}
+
+#[route("/", method = "GET", method = "POST")]
+pub async fn fever_api(
+    query: web::Query<FeverParameters>,
+    form: Option<web::Form<FeverParameters>>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let parameters = query
+        .into_inner()
+        .merge(form.map(|form| form.into_inner()).unwrap_or_default());
+
+    let mut response = Map::new();
+    response.insert("api_version".to_owned(), json!(API_VERSION));
+
+    let user_id = match &parameters.api_key {
+        Some(api_key) => fever::get_user_id_by_api_key(connection, api_key).await?,
+        None => None,
+    };
+    let Some(user_id) = user_id else {
+        response.insert("auth".to_owned(), json!(0));
+        return Ok(HttpResponse::Ok().json(response));
+    };
+    response.insert("auth".to_owned(), json!(1));
+
+    if let Some(mark) = &parameters.mark {
+        mark_as(&app_state, user_id, mark, &parameters).await?;
+    }
+
+    let last_refreshed = fever::last_refreshed(connection, user_id).await?;
+    response.insert(
+        "last_refreshed_on_time".to_owned(),
+        json!(last_refreshed.map(|date| date.timestamp()).unwrap_or(0)),
+    );
+
+    if parameters.groups.is_some() {
+        let groups = fever::get_groups(connection, user_id).await?;
+        response.insert("groups".to_owned(), json!(groups));
+    }
+
+    if parameters.feeds.is_some() {
+        let feeds = fever::get_feeds(connection, user_id).await?;
+        response.insert("feeds".to_owned(), json!(feeds));
+    }
+
+    if parameters.groups.is_some() || parameters.feeds.is_some() {
+        let feeds_groups = fever::get_feeds_groups(connection, user_id).await?;
+        response.insert("feeds_groups".to_owned(), json!(feeds_groups));
+    }
+
+    if parameters.favicons.is_some() {
+        response.insert(
+            "favicons".to_owned(),
+            json!([{ "id": 1, "data": DEFAULT_FAVICON }]),
+        );
+    }
+
+    if parameters.items.is_some() {
+        let items = fever::get_items(connection, user_id, &parameters.items_selection()).await?;
+        let total_items = fever::count_items(connection, user_id).await?;
+        response.insert("items".to_owned(), json!(items));
+        response.insert("total_items".to_owned(), json!(total_items));
+    }
+
+    if parameters.unread_item_ids.is_some() {
+        let ids = fever::get_unread_item_ids(connection, user_id).await?;
+        response.insert("unread_item_ids".to_owned(), json!(join_ids(&ids)));
+    }
+
+    if parameters.saved_item_ids.is_some() {
+        let ids = fever::get_saved_item_ids(connection, user_id).await?;
+        response.insert("saved_item_ids".to_owned(), json!(join_ids(&ids)));
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Apply a `mark` action
+async fn mark_as(
+    app_state: &AppState,
+    user_id: i32,
+    mark: &str,
+    parameters: &FeverParameters,
+) -> Result<(), ApiError> {
+    let connection = &app_state.db;
+    let (Some(id), Some(mark_as)) = (parameters.id, parameters.mark_as.as_deref()) else {
+        return Ok(());
+    };
+    let before = parameters
+        .before
+        .and_then(|before| Utc.timestamp_opt(before, 0).single())
+        .unwrap_or_else(Utc::now);
+
+    let event = match (mark, mark_as) {
+        ("item", "read" | "unread") => {
+            let read = mark_as == "read";
+            items::set_item_read(connection, user_id, vec![id], read).await?;
+            Event::ItemsRead {
+                item_ids: vec![id],
+                read,
+            }
+        }
+        ("item", "saved" | "unsaved") => {
+            let starred = mark_as == "saved";
+            items::set_item_starred(connection, user_id, vec![id], starred).await?;
+            Event::ItemsStarred {
+                item_ids: vec![id],
+                starred,
+            }
+        }
+        ("feed", "read") => Event::ItemsRead {
+            item_ids: fever::mark_feed_as_read(connection, user_id, id, &before).await?,
+            read: true,
+        },
+        ("group", "read") => Event::ItemsRead {
+            item_ids: fever::mark_group_as_read(connection, user_id, id, &before).await?,
+            read: true,
+        },
+        _ => return Ok(()),
+    };
+
+    forget_counters(&app_state.redis, &[user_id]).await;
+    events::notify(&app_state.redis, &[user_id], event).await;
+
+    Ok(())
+}
+
+fn join_ids(ids: &[i32]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(fever_api);
+}
@@ -0,0 +1,80 @@
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use serde_json::json;
+
+use crate::common::counters::forget_counters;
+use crate::common::folders;
+
+use crate::auth::AuthenticatedUser;
+use crate::model::FolderRequest;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[get("/folders")]
+pub async fn list_folders(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let folders = folders::list_folders(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(folders))
+}
+
+#[post("/folders")]
+pub async fn new_folder(
+    request: web::Json<FolderRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let folder_id =
+        folders::create_folder(connection, user.id, &request.name, request.parent_id).await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::Created().json(json!({ "id": folder_id })))
+}
+
+#[patch("/folder/{id}")]
+pub async fn update_folder(
+    id: web::Path<i32>,
+    request: web::Json<FolderRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    folders::update_folder(
+        connection,
+        user.id,
+        id.into_inner(),
+        &request.name,
+        request.parent_id,
+    )
+    .await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/folder/{id}")]
+pub async fn delete_folder(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    folders::delete_folder(connection, user.id, id.into_inner()).await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_folders)
+        .service(new_folder)
+        .service(update_folder)
+        .service(delete_folder);
+}
@@ -0,0 +1,500 @@
+use actix_web::{get, post, route, web, HttpRequest, HttpResponse};
+use chrono::{TimeZone, Utc};
+use secrecy::Secret;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::common::channels::{self, SubscriptionUpdate};
+use crate::common::counters::forget_counters;
+use crate::common::events::{self, Event};
+use crate::common::greader::{self, Stream, StreamFilters, FEED_PREFIX, LABEL_PREFIX};
+use crate::common::items;
+use crate::common::{folders, DbError};
+
+use crate::auth::{client_ip, get_greader_token_from_login_request, AuthenticatedUser};
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+/// Maximum number of items returned by the stream endpoints
+const MAX_ITEMS: u64 = 1000;
+
+/// GReader parameters, sent either in the query string or in the form body.
+/// Some of them can be repeated, like `i` in `edit-tag`.
+struct Parameters(Vec<(String, String)>);
+
+impl Parameters {
+    fn from_request(req: &HttpRequest, body: &[u8]) -> Self {
+        Parameters(
+            form_urlencoded::parse(req.query_string().as_bytes())
+                .chain(form_urlencoded::parse(body))
+                .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                .collect(),
+        )
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn get_all(&self, key: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    fn stream_filters(&self) -> StreamFilters {
+        StreamFilters {
+            exclude: self.get("xt").and_then(Stream::parse),
+            include: self.get("it").and_then(Stream::parse),
+            newer_than: self.get("ot").and_then(|ot| ot.parse().ok()),
+            older_than: self.get("nt").and_then(|nt| nt.parse().ok()),
+            oldest_first: self.get("r") == Some("o"),
+        }
+    }
+
+    /// Number of items and page number requested. The continuation is the number of the next page.
+    fn page(&self) -> (u64, u64) {
+        let size = self
+            .get("n")
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(20)
+            .clamp(1, MAX_ITEMS);
+        let page = self
+            .get("c")
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(1)
+            .max(1);
+        (size, page)
+    }
+}
+
+fn ok() -> HttpResponse {
+    HttpResponse::Ok().content_type("text/plain").body("OK")
+}
+
+fn bad_request(reason: &str) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .content_type("text/plain")
+        .body(reason.to_owned())
+}
+
+#[post("/accounts/ClientLogin")]
+pub async fn client_login(
+    req: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let parameters = Parameters::from_request(&req, &body);
+    let (Some(email), Some(password)) = (parameters.get("Email"), parameters.get("Passwd")) else {
+        return Ok(HttpResponse::Unauthorized().body("Error=BadAuthentication"));
+    };
+
+    match get_greader_token_from_login_request(
+        email,
+        &Secret::new(password.to_owned()),
+        client_ip(&req).as_deref(),
+        &app_state.db,
+        &app_state.redis,
+    )
+    .await
+    {
+        Ok(auth) => Ok(HttpResponse::Ok()
+            .content_type("text/plain")
+            .body(format!("SID={auth}\nLSID={auth}\nAuth={auth}\n"))),
+        Err(_) => Ok(HttpResponse::Unauthorized().body("Error=BadAuthentication")),
+    }
+}
+
+#[get("/reader/api/0/token")]
+pub async fn token(_user: AuthenticatedUser) -> HttpResponse {
+    // Requests are authenticated by their header, so the action token does not need to be checked
+    HttpResponse::Ok()
+        .content_type("text/plain")
+        .body(Uuid::new_v4().simple().to_string())
+}
+
+#[get("/reader/api/0/user-info")]
+pub async fn user_info(user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "userId": user.id.to_string(),
+        "userName": user.login,
+        "userProfileId": user.id.to_string(),
+        "userEmail": "",
+    }))
+}
+
+#[get("/reader/api/0/subscription/list")]
+pub async fn subscription_list(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let subscriptions = greader::list_subscriptions(&app_state.db, user.id).await?;
+
+    let subscriptions = subscriptions
+        .into_iter()
+        .map(|subscription| {
+            json!({
+                "id": format!("{}{}", FEED_PREFIX, subscription.id),
+                "title": subscription.name,
+                "categories": subscription.folder.iter().map(|folder| json!({
+                    "id": format!("{}{}", LABEL_PREFIX, folder),
+                    "label": folder,
+                })).collect::<Vec<_>>(),
+                "url": subscription.url,
+                "htmlUrl": subscription.site_url.unwrap_or_default(),
+                "iconUrl": "",
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(json!({ "subscriptions": subscriptions })))
+}
+
+#[post("/reader/api/0/subscription/quickadd")]
+pub async fn subscription_quickadd(
+    req: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let parameters = Parameters::from_request(&req, &body);
+    let Some(url) = parameters.get("quickadd") else {
+        return Ok(bad_request("Missing quickadd parameter"));
+    };
+    let url = url.strip_prefix(FEED_PREFIX).unwrap_or(url);
+
+    let channel_id =
+        channels::create_or_link_channel(&app_state.db, &app_state.redis, url, None, None, user.id)
+            .await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::SubscriptionAdded { channel_id },
+    )
+    .await;
+    let channel = channels::select_by_id_and_user_id(&app_state.db, channel_id, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "numResults": 1,
+        "query": url,
+        "streamId": format!("{}{}", FEED_PREFIX, channel_id),
+        "streamName": channel.map(|channel| channel.name).unwrap_or_default(),
+    })))
+}
+
+#[post("/reader/api/0/subscription/edit")]
+pub async fn subscription_edit(
+    req: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let parameters = Parameters::from_request(&req, &body);
+    let (Some(action), Some(stream_id)) = (parameters.get("ac"), parameters.get("s")) else {
+        return Ok(bad_request("Missing ac or s parameter"));
+    };
+    let Some(target) = stream_id.strip_prefix(FEED_PREFIX) else {
+        return Ok(bad_request("Invalid stream id"));
+    };
+
+    // Feeds are identified by their id, except when subscribing where it is their URL
+    let channel_id = match action {
+        "subscribe" => Some(
+            channels::create_or_link_channel(
+                connection,
+                &app_state.redis,
+                target,
+                parameters.get("t").map(|title| title.to_owned()),
+                None,
+                user.id,
+            )
+            .await?,
+        ),
+        _ => match target.parse() {
+            Ok(id) => Some(id),
+            Err(_) => greader::get_channel_id_by_url(connection, target).await?,
+        },
+    };
+    let Some(channel_id) = channel_id else {
+        return Err(ApiError::NotFound(String::from("channel"), 0));
+    };
+
+    match action {
+        "unsubscribe" => channels::unsubscribe_channel(connection, channel_id, user.id).await?,
+        "edit" if parameters.get("t").is_some() => {
+            let update = SubscriptionUpdate {
+                name: parameters.get("t").map(|title| title.to_owned()),
+                ..Default::default()
+            };
+            match channels::update_subscription(connection, channel_id, user.id, &update).await {
+                Err(DbError::RowNotFound) => {
+                    return Err(ApiError::NotFound(String::from("channel"), channel_id))
+                }
+                result => result?,
+            }
+        }
+        _ => {}
+    }
+
+    if action != "unsubscribe" {
+        if let Some(label) = parameters
+            .get("a")
+            .and_then(|a| a.strip_prefix(LABEL_PREFIX))
+        {
+            let folder_id =
+                folders::find_or_create_folder(connection, user.id, label, None).await?;
+            folders::set_channel_folder(connection, user.id, channel_id, Some(folder_id)).await?;
+        } else if parameters.get("r").is_some() {
+            folders::set_channel_folder(connection, user.id, channel_id, None).await?;
+        }
+    }
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    match action {
+        "subscribe" => {
+            let event = Event::SubscriptionAdded { channel_id };
+            events::notify(&app_state.redis, &[user.id], event).await
+        }
+        "unsubscribe" => {
+            let event = Event::SubscriptionRemoved { channel_id };
+            events::notify(&app_state.redis, &[user.id], event).await
+        }
+        _ => {}
+    }
+
+    Ok(ok())
+}
+
+#[get("/reader/api/0/tag/list")]
+pub async fn tag_list(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let folders = folders::list_folders(&app_state.db, user.id).await?;
+
+    let mut tags = vec![json!({ "id": greader::STARRED })];
+    tags.extend(folders.into_iter().map(|folder| {
+        json!({
+            "id": format!("{}{}", LABEL_PREFIX, folder.name),
+            "type": "folder",
+        })
+    }));
+
+    Ok(HttpResponse::Ok().json(json!({ "tags": tags })))
+}
+
+#[route(
+    "/reader/api/0/stream/contents/{stream_id:.*}",
+    method = "GET",
+    method = "POST"
+)]
+pub async fn stream_contents(
+    req: HttpRequest,
+    stream_id: web::Path<String>,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let parameters = Parameters::from_request(&req, &body);
+    let stream_id = match stream_id.into_inner() {
+        stream_id if stream_id.is_empty() => greader::READING_LIST.to_owned(),
+        stream_id => stream_id,
+    };
+
+    stream_items(&app_state, user.id, &stream_id, &parameters, true).await
+}
+
+#[get("/reader/api/0/stream/items/ids")]
+pub async fn stream_items_ids(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let parameters = Parameters::from_request(&req, &[]);
+    let stream_id = parameters
+        .get("s")
+        .unwrap_or(greader::READING_LIST)
+        .to_owned();
+
+    stream_items(&app_state, user.id, &stream_id, &parameters, false).await
+}
+
+/// Return the items of a stream, either with their content or only their ids
+async fn stream_items(
+    app_state: &AppState,
+    user_id: i32,
+    stream_id: &str,
+    parameters: &Parameters,
+    with_content: bool,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let Some(stream) = Stream::parse(stream_id) else {
+        return Ok(bad_request("Unknown stream"));
+    };
+    let Some(filters) =
+        greader::to_item_filters(connection, user_id, &stream, &parameters.stream_filters())
+            .await?
+    else {
+        return Err(ApiError::NotFound(String::from("label"), 0));
+    };
+
+    let (size, page) = parameters.page();
+    let items = items::get_items_of_user(connection, &filters, user_id, page, size).await?;
+    let continuation = (page < *items.total_pages()).then(|| (page + 1).to_string());
+    let items = items.into_content();
+
+    let mut response = if with_content {
+        json!({
+            "id": stream_id,
+            "updated": Utc::now().timestamp(),
+            "items": items.into_iter().map(greader::GReaderItem::from).collect::<Vec<_>>(),
+        })
+    } else {
+        json!({
+            "itemRefs": items.iter().map(|item| json!({
+                "id": item.id.to_string(),
+                "directStreamIds": [],
+                "timestampUsec": item.publish_timestamp.unwrap_or(item.fetch_timestamp).timestamp_micros().to_string(),
+            })).collect::<Vec<_>>(),
+        })
+    };
+    if let Some(continuation) = continuation {
+        response["continuation"] = json!(continuation);
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[post("/reader/api/0/stream/items/contents")]
+pub async fn stream_items_contents(
+    req: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let parameters = Parameters::from_request(&req, &body);
+
+    let mut items = Vec::new();
+    for id in parameters
+        .get_all("i")
+        .into_iter()
+        .filter_map(greader::parse_item_id)
+    {
+        if let Some(item) = items::get_one_item(connection, id, user.id).await? {
+            items.push(greader::GReaderItem::from(item));
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "id": greader::READING_LIST,
+        "updated": Utc::now().timestamp(),
+        "items": items,
+    })))
+}
+
+#[post("/reader/api/0/edit-tag")]
+pub async fn edit_tag(
+    req: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let parameters = Parameters::from_request(&req, &body);
+    let ids: Vec<i32> = parameters
+        .get_all("i")
+        .into_iter()
+        .filter_map(greader::parse_item_id)
+        .collect();
+
+    let mut events = vec![];
+    for (tags, state) in [
+        (parameters.get_all("a"), true),
+        (parameters.get_all("r"), false),
+    ] {
+        for tag in tags.into_iter().filter_map(Stream::parse) {
+            match tag {
+                Stream::Read => {
+                    items::set_item_read(connection, user.id, ids.clone(), state).await?;
+                    events.push(Event::ItemsRead {
+                        item_ids: ids.clone(),
+                        read: state,
+                    });
+                }
+                Stream::Starred => {
+                    items::set_item_starred(connection, user.id, ids.clone(), state).await?;
+                    events.push(Event::ItemsStarred {
+                        item_ids: ids.clone(),
+                        starred: state,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    for event in events {
+        events::notify(&app_state.redis, &[user.id], event).await;
+    }
+
+    Ok(ok())
+}
+
+#[post("/reader/api/0/mark-all-as-read")]
+pub async fn mark_all_as_read(
+    req: HttpRequest,
+    body: web::Bytes,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let parameters = Parameters::from_request(&req, &body);
+    let Some(stream) = parameters.get("s").and_then(Stream::parse) else {
+        return Ok(bad_request("Unknown stream"));
+    };
+
+    let Some(mut filters) =
+        greader::to_item_filters(connection, user.id, &stream, &StreamFilters::default()).await?
+    else {
+        return Err(ApiError::NotFound(String::from("label"), 0));
+    };
+    // The timestamp is in microseconds
+    filters.published_before = parameters
+        .get("ts")
+        .and_then(|ts| ts.parse::<i64>().ok())
+        .and_then(|ts| Utc.timestamp_micros(ts).single());
+
+    let item_ids = items::mark_items_as_read(connection, &filters, user.id).await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+    let event = Event::ItemsRead {
+        item_ids,
+        read: true,
+    };
+    events::notify(&app_state.redis, &[user.id], event).await;
+
+    Ok(ok())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(client_login)
+        .service(token)
+        .service(user_info)
+        .service(subscription_list)
+        .service(subscription_quickadd)
+        .service(subscription_edit)
+        .service(tag_list)
+        .service(stream_contents)
+        .service(stream_items_ids)
+        .service(stream_items_contents)
+        .service(edit_tag)
+        .service(mark_all_as_read);
+}
@@ -0,0 +1,56 @@
+use actix_web::http::header;
+use actix_web::{get, post, web, HttpResponse};
+use serde_json::json;
+use tokio::task;
+use tracing::error;
+
+use crate::common::imports;
+
+use crate::auth::AuthenticatedUser;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[post("/channels/import")]
+pub async fn import_opml(
+    body: String,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let feeds = imports::parse_opml(&body)?;
+    let import_id = imports::create_import(connection, user.id, feeds.len() as i32).await?;
+
+    // Checking hundreds of feeds takes a while, so do it in the background
+    let db = connection.clone();
+    let redis = app_state.redis.clone();
+    let user_id = user.id;
+    task::spawn(async move {
+        if let Err(e) = imports::run_import(&db, &redis, import_id, user_id, feeds).await {
+            error!("Could not run import {}: {:?}", import_id, e);
+        }
+    });
+
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/api/v1/imports/{}", import_id)))
+        .json(json!({ "id": import_id })))
+}
+
+#[get("/imports/{id}")]
+pub async fn get_import(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    match imports::get_import(connection, user.id, id).await? {
+        Some(import) => Ok(HttpResponse::Ok().json(import)),
+        None => Err(ApiError::NotFound(String::from("import"), id)),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(import_opml).service(get_import);
+}
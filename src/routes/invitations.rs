@@ -0,0 +1,145 @@
+use std::env;
+
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{Duration, Utc};
+use reqwest::Url;
+use serde_json::json;
+
+use crate::common::invitations;
+use crate::common::model::UserRole;
+use crate::common::DbError::RowNotFound;
+
+use crate::auth::AuthenticatedUser;
+use crate::errors::AuthenticationError;
+use crate::model::InvitationRequest;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+/// Number of days an invitation is valid, unless told otherwise
+const DEFAULT_INVITATION_TTL_IN_DAYS: i64 = 7;
+
+/// Number of registrations the invitations of a user who is not an administrator can allow at once
+fn invitations_per_user() -> i64 {
+    env::var("RSS_AGGREGATOR_INVITATIONS_PER_USER")
+        .map(|x| x.parse().unwrap_or_default())
+        .unwrap_or_default()
+}
+
+#[get("/invitations")]
+pub async fn list_invitations(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    // The administrators see all the invitations
+    let owner = (!user.is_admin()).then_some(user.id);
+    let invitations = invitations::list_invitations(connection, owner).await?;
+
+    Ok(HttpResponse::Ok().json(invitations))
+}
+
+#[post("/invitations")]
+pub async fn new_invitation(
+    request: web::Json<InvitationRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let role = request.role.clone().unwrap_or(UserRole::Basic);
+
+    let max_uses = request.max_uses.unwrap_or(1);
+    if max_uses < 1 {
+        return Err(ApiError::InvalidRequest(String::from(
+            "An invitation must allow at least one use",
+        )));
+    }
+
+    let max_expiration_timestamp = Utc::now() + Duration::days(DEFAULT_INVITATION_TTL_IN_DAYS);
+    let expiration_timestamp = request
+        .expiration_timestamp
+        .unwrap_or(max_expiration_timestamp);
+    if expiration_timestamp <= Utc::now() {
+        return Err(ApiError::InvalidRequest(String::from(
+            "The expiration date of the invitation must be in the future",
+        )));
+    }
+
+    // The quota is on the registrations, so that a single invitation cannot open them to anyone for good
+    if !user.is_admin() {
+        if role == UserRole::Admin {
+            return Err(ApiError::AuthenticationError(
+                AuthenticationError::Forbidden("You need to be an administrator".to_owned()),
+            ));
+        }
+
+        if expiration_timestamp > max_expiration_timestamp {
+            return Err(ApiError::InvalidRequest(format!(
+                "Your invitations cannot be valid for more than {} days",
+                DEFAULT_INVITATION_TTL_IN_DAYS
+            )));
+        }
+
+        if invitations::count_remaining_uses(connection, user.id).await? + max_uses as i64
+            > invitations_per_user()
+        {
+            return Err(ApiError::AuthenticationError(
+                AuthenticationError::Forbidden("You cannot invite that many users".to_owned()),
+            ));
+        }
+    }
+
+    let mut channels = vec![];
+    for url in &request.channels {
+        let url = url.trim();
+        match Url::parse(url) {
+            Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {}
+            _ => {
+                return Err(ApiError::InvalidRequest(format!(
+                    "The channel url {} must be a valid http or https url",
+                    url
+                )))
+            }
+        }
+        channels.push(url.to_owned());
+    }
+    channels.dedup();
+
+    let (id, code) = invitations::create_invitation(
+        connection,
+        user.id,
+        &role,
+        &channels,
+        max_uses,
+        expiration_timestamp,
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(json!({ "id": id, "code": code })))
+}
+
+#[delete("/invitation/{id}")]
+pub async fn revoke_invitation(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    let owner = (!user.is_admin()).then_some(user.id);
+    invitations::revoke_invitation(connection, owner, id)
+        .await
+        .map_err(|e| match e {
+            RowNotFound => ApiError::NotFound(String::from("invitation"), id),
+            _ => ApiError::DatabaseError(e),
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_invitations)
+        .service(new_invitation)
+        .service(revoke_invitation);
+}
@@ -1,9 +1,13 @@
 use actix_web::{get, post, put, web, HttpResponse};
 
+use crate::common::counters::forget_counters;
+use crate::common::events::{self, Event};
 use crate::common::items::*;
 
 use crate::auth::AuthenticatedUser;
-use crate::model::{IdListParameter, ItemNotesRequest, PageParameters, ReadStarredParameters};
+use crate::model::{
+    IdListParameter, ItemNotesRequest, ItemsFilterParameters, PageParameters, ReadStarredParameters,
+};
 use crate::routes::errors::ApiError;
 use crate::startup::AppState;
 
@@ -11,16 +15,25 @@ use crate::startup::AppState;
 pub async fn get_all_items(
     page: web::Query<PageParameters>,
     read_starred: web::Query<ReadStarredParameters>,
+    filters: web::Query<ItemsFilterParameters>,
     app_state: web::Data<AppState>,
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
 
+    let filters = ItemFilters {
+        read: read_starred.read,
+        starred: read_starred.starred,
+        tag_id: filters.tag,
+        folder_id: filters.folder,
+        exclude_hidden: filters.tag.is_none() && filters.folder.is_none(),
+        sort: filters.sort.unwrap_or_default(),
+        ..Default::default()
+    };
+
     let items = get_items_of_user(
         connection,
-        None,
-        read_starred.starred,
-        read_starred.read,
+        &filters,
         user.id,
         page.get_page(),
         page.get_size(),
@@ -36,7 +49,19 @@ pub async fn star_items(
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
-    set_item_starred(connection, user.id, ids.into_inner().ids, true).await?;
+    let ids = ids.into_inner().ids;
+    set_item_starred(connection, user.id, ids.clone(), true).await?;
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::ItemsStarred {
+            item_ids: ids,
+            starred: true,
+        },
+    )
+    .await;
 
     Ok(HttpResponse::Accepted().finish())
 }
@@ -48,7 +73,19 @@ pub async fn unstar_items(
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
-    set_item_starred(connection, user.id, ids.into_inner().ids, false).await?;
+    let ids = ids.into_inner().ids;
+    set_item_starred(connection, user.id, ids.clone(), false).await?;
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::ItemsStarred {
+            item_ids: ids,
+            starred: false,
+        },
+    )
+    .await;
 
     Ok(HttpResponse::Accepted().finish())
 }
@@ -60,7 +97,19 @@ pub async fn read_item(
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
-    set_item_read(connection, user.id, ids.into_inner().ids, true).await?;
+    let ids = ids.into_inner().ids;
+    set_item_read(connection, user.id, ids.clone(), true).await?;
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::ItemsRead {
+            item_ids: ids,
+            read: true,
+        },
+    )
+    .await;
 
     Ok(HttpResponse::Accepted().finish())
 }
@@ -72,7 +121,19 @@ pub async fn unread_item(
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
-    set_item_read(connection, user.id, ids.into_inner().ids, false).await?;
+    let ids = ids.into_inner().ids;
+    set_item_read(connection, user.id, ids.clone(), false).await?;
+
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::ItemsRead {
+            item_ids: ids,
+            read: false,
+        },
+    )
+    .await;
 
     Ok(HttpResponse::Accepted().finish())
 }
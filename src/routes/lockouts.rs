@@ -0,0 +1,47 @@
+use actix_web::{delete, get, web, HttpResponse};
+
+use crate::common::lockouts;
+use crate::common::model::LockoutKind;
+
+use crate::auth::AuthenticatedUser;
+use crate::errors::AuthenticationError;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[get("/lockouts")]
+pub async fn list_lockouts(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::AuthenticationError(
+            AuthenticationError::Forbidden("You need to be an administrator".to_owned()),
+        ));
+    }
+
+    let lockouts = lockouts::list_lockouts(&app_state.redis).await?;
+
+    Ok(HttpResponse::Ok().json(lockouts))
+}
+
+#[delete("/lockouts/{kind}/{key}")]
+pub async fn clear_lockout(
+    path: web::Path<(LockoutKind, String)>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    if !user.is_admin() {
+        return Err(ApiError::AuthenticationError(
+            AuthenticationError::Forbidden("You need to be an administrator".to_owned()),
+        ));
+    }
+    let (kind, key) = path.into_inner();
+
+    lockouts::clear_lockout(&app_state.redis, kind, &key).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_lockouts).service(clear_lockout);
+}
@@ -3,14 +3,37 @@ use rand::Rng;
 
 pub mod auth;
 pub mod channels;
+pub mod counters;
+pub mod digests;
+pub mod events;
+pub mod fever;
+pub mod folders;
+pub mod greader;
+pub mod imports;
+pub mod invitations;
 pub mod items;
+pub mod lockouts;
+pub mod nextcloud;
+pub mod output_feeds;
+pub mod rules;
+pub mod sessions;
+pub mod streams;
+pub mod tags;
+pub mod tokens;
+pub mod two_factor;
 pub mod users;
+pub mod webhooks;
 
 mod errors {
     use actix_web::http::StatusCode;
     use actix_web::{HttpResponse, ResponseError};
     use serde_json::json;
+    use tracing::error;
 
+    use crate::common::errors::{
+        DigestError, EncryptionError, FolderError, ImportError, OidcError, PasswordPolicyError,
+        RuleError, SavedSearchError, ServiceError, TwoFactorError, UserError,
+    };
     use crate::common::DbError;
 
     use crate::errors::AuthenticationError;
@@ -29,6 +52,30 @@ mod errors {
         DatabaseError(#[from] DbError),
         #[error("Password mismatch")]
         PasswordMismatch,
+        #[error("Invalid request: {0}")]
+        InvalidRequest(String),
+        #[error("Conflict: {0}")]
+        Conflict(String),
+        #[error("Folder error: {0}")]
+        FolderError(#[from] FolderError),
+        #[error("Service error: {0}")]
+        ServiceError(#[from] ServiceError),
+        #[error("Import error: {0}")]
+        ImportError(#[from] ImportError),
+        #[error("Rule error: {0}")]
+        RuleError(#[from] RuleError),
+        #[error(transparent)]
+        SavedSearchError(#[from] SavedSearchError),
+        #[error(transparent)]
+        DigestError(#[from] DigestError),
+        #[error(transparent)]
+        TwoFactorError(#[from] TwoFactorError),
+        #[error(transparent)]
+        OidcError(#[from] OidcError),
+        #[error(transparent)]
+        UserError(#[from] UserError),
+        #[error(transparent)]
+        PasswordPolicyError(#[from] PasswordPolicyError),
         #[error(transparent)]
         Unexpected(#[from] anyhow::Error),
     }
@@ -49,10 +96,119 @@ mod errors {
                     "status": 500,
                     "detail": "Unexpected error with the database"})),
             ApiError::PasswordMismatch => HttpResponse::BadRequest().json(json!({"type":"/problem/password-mismatch", "title": "Passwords does not match", "status": 400, "title": "Passwords does not match"})),
+            ApiError::InvalidRequest(detail) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-request",
+                    "title": "Invalid request",
+                    "status": 400,
+                    "detail": detail})),
+            ApiError::Conflict(detail) => HttpResponse::Conflict()
+                .json(json!({"type":"/problem/conflict",
+                    "title": "Conflict",
+                    "status": 409,
+                    "detail": detail})),
+            ApiError::FolderError(FolderError::NotFound(object_type, id)) => ApiError::NotFound(object_type.to_string(), *id).error_response(),
+            ApiError::FolderError(FolderError::TooDeep) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/folder-too-deep",
+                    "title": "Folder too deep",
+                    "status": 400,
+                    "detail": "Folders can only be nested one level deep"})),
+            ApiError::FolderError(error @ FolderError::InvalidName) => ApiError::InvalidRequest(error.to_string()).error_response(),
+            ApiError::ServiceError(ServiceError::RssError(error)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-feed",
+                    "title": "Invalid feed",
+                    "status": 400,
+                    "detail": error.to_string()})),
+            ApiError::ImportError(error @ (ImportError::InvalidOpml(_) | ImportError::NoFeed)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-opml",
+                    "title": "Invalid OPML document",
+                    "status": 400,
+                    "detail": error.to_string()})),
+            ApiError::RuleError(RuleError::NotFound(object_type, id)) => ApiError::NotFound(object_type.to_string(), *id).error_response(),
+            ApiError::RuleError(error @ (RuleError::EmptyPattern | RuleError::InvalidRegex(_) | RuleError::MissingTag)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-rule",
+                    "title": "Invalid rule",
+                    "status": 400,
+                    "detail": error.to_string()})),
+            ApiError::SavedSearchError(SavedSearchError::NotFound(object_type, id)) => ApiError::NotFound(object_type.to_string(), *id).error_response(),
+            ApiError::SavedSearchError(error @ (SavedSearchError::EmptyName | SavedSearchError::InvalidWindow)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-saved-search",
+                    "title": "Invalid saved search",
+                    "status": 400,
+                    "detail": error.to_string()})),
+            ApiError::DigestError(DigestError::NotFound(object_type, id)) => ApiError::NotFound(object_type.to_string(), *id).error_response(),
+            ApiError::DigestError(error @ (DigestError::UnverifiedEmail | DigestError::MissingFolder)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-digest",
+                    "title": "Invalid digest",
+                    "status": 400,
+                    "detail": error.to_string()})),
+            ApiError::DigestError(DigestError::EncryptionError(EncryptionError::MissingKey | EncryptionError::InvalidKey)) => HttpResponse::ServiceUnavailable()
+                .json(json!({"type":"/problem/digests-unavailable",
+                    "title": "Digests are not available",
+                    "status": 503,
+                    "detail": "The server is not configured to keep the email addresses"})),
+            ApiError::TwoFactorError(error @ (TwoFactorError::NotEnrolled | TwoFactorError::AlreadyEnabled | TwoFactorError::InvalidCode)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-two-factor",
+                    "title": "Invalid two-factor authentication request",
+                    "status": 400,
+                    "detail": error.to_string()})),
+            ApiError::TwoFactorError(TwoFactorError::EncryptionError(EncryptionError::MissingKey | EncryptionError::InvalidKey)) => HttpResponse::ServiceUnavailable()
+                .json(json!({"type":"/problem/two-factor-unavailable",
+                    "title": "Two-factor authentication is not available",
+                    "status": 503,
+                    "detail": "The server is not configured to keep the TOTP secrets"})),
+            ApiError::OidcError(OidcError::NotConfigured) => HttpResponse::NotFound()
+                .json(json!({"type":"/problem/sso-disabled",
+                    "title": "Single sign-on is not configured",
+                    "status": 404,
+                    "detail": "The server is not configured to log in with an identity provider"})),
+            ApiError::OidcError(error @ (OidcError::ProviderError(_) | OidcError::HttpError(_))) => HttpResponse::BadGateway()
+                .json(json!({"type":"/problem/sso-provider",
+                    "title": "Error with the identity provider",
+                    "status": 502,
+                    "detail": error.to_string()})),
+            ApiError::OidcError(error @ (OidcError::InvalidState | OidcError::Denied(_) | OidcError::InvalidIdToken(_) | OidcError::MissingClaim(_) | OidcError::UnknownAccount | OidcError::AlreadyLinked | OidcError::NotLinkable | OidcError::DisabledAccount)) => HttpResponse::Unauthorized()
+                .json(json!({"type":"/problem/sso-failed",
+                    "title": "Single sign-on failed",
+                    "status": 401,
+                    "detail": error.to_string()})),
+            ApiError::UserError(UserError::NotFound(object_type, id)) => ApiError::NotFound(object_type.to_string(), *id).error_response(),
+            ApiError::UserError(error @ UserError::UsernameTaken) => ApiError::Conflict(error.to_string()).error_response(),
+            ApiError::UserError(error @ (UserError::EmptyUsername | UserError::OwnAccount)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/invalid-user",
+                    "title": "Invalid user",
+                    "status": 400,
+                    "detail": error.to_string()})),
+            ApiError::PasswordPolicyError(PasswordPolicyError::Violations(violations)) => HttpResponse::BadRequest()
+                .json(json!({"type":"/problem/weak-password",
+                    "title": "The password does not follow the policy",
+                    "status": 400,
+                    "detail": violations.iter().map(|violation| violation.detail.as_str()).collect::<Vec<_>>().join(". "),
+                    "violations": violations})),
+            ApiError::PasswordPolicyError(error @ PasswordPolicyError::BreachedListError(_)) => {
+                error!("{}", error);
+                HttpResponse::InternalServerError()
+                    .json(json!({"type":"/problem/password-policy",
+                        "title": "Could not check the password",
+                        "status": 500,
+                        "detail": "Unexpected error while checking the password against the policy"}))
+            }
+            ApiError::FolderError(FolderError::SqlError(_)) | ApiError::ServiceError(ServiceError::SqlError(_)) | ApiError::ImportError(ImportError::SqlError(_)) | ApiError::RuleError(RuleError::SqlError(_)) | ApiError::SavedSearchError(SavedSearchError::SqlError(_)) | ApiError::DigestError(DigestError::SqlError(_)) | ApiError::TwoFactorError(TwoFactorError::SqlError(_)) | ApiError::OidcError(OidcError::SqlError(_)) | ApiError::UserError(UserError::SqlError(_)) => HttpResponse::InternalServerError()
+                .json(json!({"type":"/problem/database",
+                    "title": "Error with the database",
+                    "status": 500,
+                    "detail": "Unexpected error with the database"})),
             _ => HttpResponse::build(StatusCode::INTERNAL_SERVER_ERROR).finish(),
         }
         }
     }
+
+    /// Map a missing row to a 404 about the requested object, and any other failure to a database error
+    pub fn not_found_or(error: DbError, object_type: &str, id: i32) -> ApiError {
+        match error {
+            DbError::RowNotFound => ApiError::NotFound(object_type.to_owned(), id),
+            _ => ApiError::DatabaseError(error),
+        }
+    }
 }
 
 #[get("/api/v1/ping")]
@@ -84,6 +240,21 @@ pub async fn ping() -> HttpResponse {
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.configure(auth::configure)
         .configure(channels::configure)
+        .configure(counters::configure)
+        .configure(digests::configure)
+        .configure(events::configure)
+        .configure(folders::configure)
+        .configure(imports::configure)
+        .configure(invitations::configure)
         .configure(items::configure)
-        .configure(users::configure);
+        .configure(lockouts::configure)
+        .configure(output_feeds::configure)
+        .configure(rules::configure)
+        .configure(sessions::configure)
+        .configure(streams::configure)
+        .configure(tags::configure)
+        .configure(tokens::configure)
+        .configure(two_factor::configure)
+        .configure(users::configure)
+        .configure(webhooks::configure);
 }
@@ -0,0 +1,471 @@
+use actix_web::{delete, get, post, route, web, HttpResponse};
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::auth::AuthenticatedUser;
+use crate::common::channels::{self, SubscriptionUpdate};
+use crate::common::counters::forget_counters;
+use crate::common::events::{self, Event};
+use crate::common::nextcloud::{self, ItemsQuery, Selection};
+use crate::common::{folders, items, DbError};
+
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+/// Nextcloud News version reported to the clients, some of them enabling features depending on it
+const NEWS_VERSION: &str = "25.0.0";
+
+/// Timestamps above this one are in microseconds rather than seconds
+const MAX_SECONDS_TIMESTAMP: i64 = 10_000_000_000;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemsParameters {
+    #[serde(default = "all_items")]
+    batch_size: i64,
+    #[serde(default)]
+    offset: i32,
+    #[serde(rename = "type", default = "all_type")]
+    item_type: u8,
+    #[serde(default)]
+    id: i32,
+    #[serde(default = "get_read")]
+    get_read: bool,
+    #[serde(default)]
+    oldest_first: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdatedItemsParameters {
+    last_modified: i64,
+    #[serde(rename = "type", default = "all_type")]
+    item_type: u8,
+    #[serde(default)]
+    id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FolderRequest {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedRequest {
+    url: String,
+    folder_id: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveFeedRequest {
+    folder_id: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameFeedRequest {
+    feed_title: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkAsReadRequest {
+    newest_item_id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultipleItemsRequest {
+    #[serde(alias = "items")]
+    item_ids: Vec<i32>,
+}
+
+fn all_items() -> i64 {
+    -1
+}
+
+fn all_type() -> u8 {
+    3
+}
+
+fn get_read() -> bool {
+    true
+}
+
+/// The root folder of Nextcloud News has the id `0`
+fn folder_id(folder_id: Option<i32>) -> Option<i32> {
+    folder_id.filter(|id| *id != 0)
+}
+
+#[get("/version")]
+pub async fn version(_user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "version": NEWS_VERSION }))
+}
+
+#[get("/folders")]
+pub async fn get_folders(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let folders = folders::list_folders(&app_state.db, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "folders": folders.iter().map(|folder| json!({
+            "id": folder.id,
+            "name": folder.name,
+        })).collect::<Vec<_>>()
+    })))
+}
+
+#[post("/folders")]
+pub async fn create_folder(
+    request: web::Json<FolderRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let name = request.name.trim();
+
+    let id = folders::create_folder(&app_state.db, user.id, name, None).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "folders": [{ "id": id, "name": name }] })))
+}
+
+#[route("/folders/{folder_id}", method = "PUT", method = "POST")]
+pub async fn rename_folder(
+    folder_id: web::Path<i32>,
+    request: web::Json<FolderRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let folder_id = folder_id.into_inner();
+    let name = request.name.trim();
+
+    let folder = folders::get_folder(connection, user.id, folder_id).await?;
+    folders::update_folder(connection, user.id, folder_id, name, folder.parent_id).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/folders/{folder_id}")]
+pub async fn delete_folder(
+    folder_id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    folders::delete_folder(&app_state.db, user.id, folder_id.into_inner()).await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[route("/folders/{folder_id}/read", method = "PUT", method = "POST")]
+pub async fn mark_folder_as_read(
+    folder_id: web::Path<i32>,
+    request: web::Json<MarkAsReadRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let selection = Selection::Folder(folder_id.into_inner());
+    mark_as_read(&app_state, user.id, &selection, request.newest_item_id).await
+}
+
+#[get("/feeds")]
+pub async fn get_feeds(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let feeds = nextcloud::get_feeds(connection, user.id).await?;
+    let starred_count = nextcloud::count_starred(connection, user.id).await?;
+    let newest_item_id = nextcloud::newest_item_id(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "feeds": feeds,
+        "starredCount": starred_count,
+        "newestItemId": newest_item_id,
+    })))
+}
+
+#[post("/feeds")]
+pub async fn create_feed(
+    request: web::Json<FeedRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let channel_id = channels::create_or_link_channel(
+        connection,
+        &app_state.redis,
+        &request.url,
+        None,
+        None,
+        user.id,
+    )
+    .await?;
+    if let Some(folder_id) = folder_id(request.folder_id) {
+        folders::set_channel_folder(connection, user.id, channel_id, Some(folder_id)).await?;
+    }
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::SubscriptionAdded { channel_id },
+    )
+    .await;
+
+    let feeds = nextcloud::get_feeds(connection, user.id).await?;
+    let newest_item_id = nextcloud::newest_item_id(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "feeds": feeds.into_iter().filter(|feed| feed.id == channel_id).collect::<Vec<_>>(),
+        "newestItemId": newest_item_id,
+    })))
+}
+
+#[delete("/feeds/{feed_id}")]
+pub async fn delete_feed(
+    feed_id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let feed_id = feed_id.into_inner();
+
+    match channels::unsubscribe_channel(&app_state.db, feed_id, user.id).await {
+        Err(DbError::RowNotFound) => return Err(ApiError::NotFound("feed".to_owned(), feed_id)),
+        result => result?,
+    }
+    forget_counters(&app_state.redis, &[user.id]).await;
+    events::notify(
+        &app_state.redis,
+        &[user.id],
+        Event::SubscriptionRemoved {
+            channel_id: feed_id,
+        },
+    )
+    .await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[route("/feeds/{feed_id}/move", method = "PUT", method = "POST")]
+pub async fn move_feed(
+    feed_id: web::Path<i32>,
+    request: web::Json<MoveFeedRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    folders::set_channel_folder(
+        &app_state.db,
+        user.id,
+        feed_id.into_inner(),
+        folder_id(request.folder_id),
+    )
+    .await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[route("/feeds/{feed_id}/rename", method = "PUT", method = "POST")]
+pub async fn rename_feed(
+    feed_id: web::Path<i32>,
+    request: web::Json<RenameFeedRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let feed_id = feed_id.into_inner();
+    let name = request.feed_title.trim();
+    if name.is_empty() || name.chars().count() > 512 {
+        return Err(ApiError::InvalidRequest(
+            "The feed title must be between 1 and 512 characters".to_owned(),
+        ));
+    }
+
+    let update = SubscriptionUpdate {
+        name: Some(name.to_owned()),
+        ..Default::default()
+    };
+    match channels::update_subscription(&app_state.db, feed_id, user.id, &update).await {
+        Err(DbError::RowNotFound) => return Err(ApiError::NotFound("feed".to_owned(), feed_id)),
+        result => result?,
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[route("/feeds/{feed_id}/read", method = "PUT", method = "POST")]
+pub async fn mark_feed_as_read(
+    feed_id: web::Path<i32>,
+    request: web::Json<MarkAsReadRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let selection = Selection::Feed(feed_id.into_inner());
+    mark_as_read(&app_state, user.id, &selection, request.newest_item_id).await
+}
+
+#[get("/items")]
+pub async fn get_items(
+    parameters: web::Query<ItemsParameters>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let Some(selection) = Selection::from_type(parameters.item_type, parameters.id) else {
+        return Err(ApiError::InvalidRequest("Unknown items type".to_owned()));
+    };
+
+    let query = ItemsQuery {
+        selection,
+        get_read: parameters.get_read,
+        batch_size: Some(parameters.batch_size).filter(|size| *size > 0),
+        offset: Some(parameters.offset).filter(|offset| *offset > 0),
+        oldest_first: parameters.oldest_first,
+        last_modified: None,
+    };
+    let items = nextcloud::get_items(&app_state.db, user.id, &query).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "items": items })))
+}
+
+#[get("/items/updated")]
+pub async fn get_updated_items(
+    parameters: web::Query<UpdatedItemsParameters>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let Some(selection) = Selection::from_type(parameters.item_type, parameters.id) else {
+        return Err(ApiError::InvalidRequest("Unknown items type".to_owned()));
+    };
+
+    // Recent versions of Nextcloud News send the last modification date in microseconds
+    let last_modified = if parameters.last_modified > MAX_SECONDS_TIMESTAMP {
+        Utc.timestamp_micros(parameters.last_modified)
+    } else {
+        Utc.timestamp_opt(parameters.last_modified, 0)
+    };
+
+    let query = ItemsQuery {
+        selection,
+        get_read: true,
+        batch_size: None,
+        offset: None,
+        oldest_first: false,
+        last_modified: last_modified.single(),
+    };
+    let items = nextcloud::get_items(&app_state.db, user.id, &query).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "items": items })))
+}
+
+#[route("/items/read", method = "PUT", method = "POST")]
+pub async fn mark_all_as_read(
+    request: web::Json<MarkAsReadRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    mark_as_read(&app_state, user.id, &Selection::All, request.newest_item_id).await
+}
+
+#[route("/items/{item_id:\\d+}/{action}", method = "PUT", method = "POST")]
+pub async fn update_item(
+    path: web::Path<(i32, String)>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let (item_id, action) = path.into_inner();
+    update_items(&app_state, user.id, vec![item_id], &action).await
+}
+
+#[route("/items/{action}/multiple", method = "PUT", method = "POST")]
+pub async fn update_multiple_items(
+    action: web::Path<String>,
+    request: web::Json<MultipleItemsRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let ids = request.into_inner().item_ids;
+    update_items(&app_state, user.id, ids, &action).await
+}
+
+/// Apply a `read`, `unread`, `star` or `unstar` action on items
+async fn update_items(
+    app_state: &AppState,
+    user_id: i32,
+    ids: Vec<i32>,
+    action: &str,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let event = match action {
+        "read" | "unread" => {
+            let read = action == "read";
+            items::set_item_read(connection, user_id, ids.clone(), read).await?;
+            Event::ItemsRead {
+                item_ids: ids,
+                read,
+            }
+        }
+        "star" | "unstar" => {
+            let starred = action == "star";
+            items::set_item_starred(connection, user_id, ids.clone(), starred).await?;
+            Event::ItemsStarred {
+                item_ids: ids,
+                starred,
+            }
+        }
+        _ => return Ok(HttpResponse::NotFound().finish()),
+    };
+    forget_counters(&app_state.redis, &[user_id]).await;
+    events::notify(&app_state.redis, &[user_id], event).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn mark_as_read(
+    app_state: &AppState,
+    user_id: i32,
+    selection: &Selection,
+    newest_item_id: i32,
+) -> Result<HttpResponse, ApiError> {
+    if let Selection::Folder(folder_id) = selection {
+        if *folder_id != 0 {
+            folders::get_folder(&app_state.db, user_id, *folder_id).await?;
+        }
+    }
+
+    let item_ids =
+        nextcloud::mark_as_read(&app_state.db, user_id, selection, newest_item_id).await?;
+    forget_counters(&app_state.redis, &[user_id]).await;
+    let event = Event::ItemsRead {
+        item_ids,
+        read: true,
+    };
+    events::notify(&app_state.redis, &[user_id], event).await;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(version)
+        .service(get_folders)
+        .service(create_folder)
+        .service(rename_folder)
+        .service(delete_folder)
+        .service(mark_folder_as_read)
+        .service(get_feeds)
+        .service(create_feed)
+        .service(delete_feed)
+        .service(move_feed)
+        .service(rename_feed)
+        .service(mark_feed_as_read)
+        .service(get_items)
+        .service(get_updated_items)
+        .service(mark_all_as_read)
+        .service(update_multiple_items)
+        .service(update_item);
+}
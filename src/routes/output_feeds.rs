@@ -0,0 +1,160 @@
+use actix_web::http::header::{
+    CacheControl, CacheDirective, ETag, EntityTag, Header, IfNoneMatch, IF_NONE_MATCH,
+};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use md5::{Digest, Md5};
+use serde_json::json;
+
+use crate::common::output_feeds::{self, OutputFormat};
+
+use crate::auth::AuthenticatedUser;
+use crate::model::{OutputFeedRequest, OutputFeedSource};
+use crate::routes::errors::{not_found_or, ApiError};
+use crate::startup::AppState;
+
+#[get("/output-feeds")]
+pub async fn list_output_feeds(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let feeds = output_feeds::list_output_feeds(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(feeds))
+}
+
+#[post("/output-feeds")]
+pub async fn new_output_feed(
+    request: web::Json<OutputFeedRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::InvalidRequest(String::from(
+            "The name of the feed cannot be empty",
+        )));
+    }
+
+    let source_id = match (request.source, request.source_id) {
+        (OutputFeedSource::Starred, _) => None,
+        (source, Some(source_id)) => {
+            if !output_feeds::source_exists(connection, user.id, source, source_id).await? {
+                return Err(ApiError::NotFound(source_name(source), source_id));
+            }
+            Some(source_id)
+        }
+        (source, None) => {
+            return Err(ApiError::InvalidRequest(format!(
+                "The id of the {} is missing",
+                source_name(source)
+            )))
+        }
+    };
+
+    let (id, token) =
+        output_feeds::create_output_feed(connection, user.id, name, request.source, source_id)
+            .await?;
+
+    Ok(HttpResponse::Created().json(json!({ "id": id, "token": token })))
+}
+
+#[delete("/output-feed/{id}")]
+pub async fn delete_output_feed(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    output_feeds::delete_output_feed(connection, user.id, id)
+        .await
+        .map_err(|e| not_found_or(e, "output feed", id))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/output-feed/{id}/token")]
+pub async fn regenerate_token(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    let token = output_feeds::regenerate_token(connection, user.id, id)
+        .await
+        .map_err(|e| not_found_or(e, "output feed", id))?;
+
+    Ok(HttpResponse::Ok().json(json!({ "token": token })))
+}
+
+/// Public endpoint serving an output feed, authenticated by its token only
+#[get("/{token}/{format}")]
+pub async fn get_output_feed(
+    path: web::Path<(String, OutputFormat)>,
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let (token, format) = path.into_inner();
+
+    let Some(feed) = output_feeds::get_by_token(connection, &token).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let items = output_feeds::get_published_items(connection, &feed).await?;
+
+    let info = req.connection_info();
+    let self_url = format!("{}://{}{}", info.scheme(), info.host(), req.path());
+    let body = output_feeds::render(&feed, &items, format, &self_url);
+
+    // No Last-Modified: starring or tagging an older item changes the feed without a newer date, only the ETag
+    // follows all the changes
+    let etag = EntityTag::new_strong(format!("{:x}", Md5::digest(&body)));
+    let not_modified = req.headers().contains_key(IF_NONE_MATCH)
+        && match IfNoneMatch::parse(&req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        };
+
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(ETag(etag))
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]));
+
+    if not_modified {
+        return Ok(response.finish());
+    }
+    Ok(response.content_type(format.content_type()).body(body))
+}
+
+fn source_name(source: OutputFeedSource) -> String {
+    match source {
+        OutputFeedSource::Starred => String::from("starred"),
+        OutputFeedSource::Tag => String::from("tag"),
+        OutputFeedSource::Folder => String::from("folder"),
+        OutputFeedSource::SavedSearch => String::from("stream"),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_output_feeds)
+        .service(new_output_feed)
+        .service(delete_output_feed)
+        .service(regenerate_token);
+}
+
+/// Configure the public endpoints, outside of the API scope
+pub fn configure_public(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_output_feed);
+}
@@ -0,0 +1,101 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use serde_json::json;
+
+use crate::common::rules::{self, RuleDefinition};
+
+use crate::auth::AuthenticatedUser;
+use crate::model::FilterRuleRequest;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[get("/rules")]
+pub async fn list_rules(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let rules = rules::list_rules(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[post("/rules")]
+pub async fn new_rule(
+    request: web::Json<FilterRuleRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let rule_id = rules::create_rule(connection, user.id, &request.into_inner().into()).await?;
+
+    Ok(HttpResponse::Created().json(json!({ "id": rule_id })))
+}
+
+#[post("/rules/dry-run")]
+pub async fn dry_run_rule(
+    request: web::Json<FilterRuleRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let items = rules::dry_run(connection, user.id, &request.into_inner().into()).await?;
+
+    Ok(HttpResponse::Ok().json(items))
+}
+
+#[put("/rule/{id}")]
+pub async fn update_rule(
+    id: web::Path<i32>,
+    request: web::Json<FilterRuleRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    rules::update_rule(
+        connection,
+        user.id,
+        id.into_inner(),
+        &request.into_inner().into(),
+    )
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/rule/{id}")]
+pub async fn delete_rule(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    rules::delete_rule(connection, user.id, id.into_inner()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+impl From<FilterRuleRequest> for RuleDefinition {
+    fn from(request: FilterRuleRequest) -> Self {
+        RuleDefinition {
+            channel_id: request.channel_id,
+            field: request.field,
+            matching: request.matching,
+            pattern: request.pattern,
+            action: request.action,
+            tag_id: request.tag_id,
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_rules)
+        .service(new_rule)
+        .service(dry_run_rule)
+        .service(update_rule)
+        .service(delete_rule);
+}
@@ -0,0 +1,59 @@
+use actix_web::{delete, get, web, HttpResponse};
+
+use crate::common::DbError::RowNotFound;
+use crate::common::{sessions, users};
+
+use crate::auth::AuthenticatedUser;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[get("/user/sessions")]
+pub async fn list_sessions(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let sessions = sessions::list_sessions(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+#[delete("/user/sessions")]
+pub async fn revoke_sessions(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    sessions::revoke_sessions(connection, user.id).await?;
+    // The access tokens already issued are revoked as well
+    users::bump_token_version(connection, &app_state.redis, user.id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/user/sessions/{id}")]
+pub async fn revoke_session(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    sessions::revoke_session(connection, user.id, id)
+        .await
+        .map_err(|e| match e {
+            RowNotFound => ApiError::NotFound(String::from("session"), id),
+            _ => ApiError::DatabaseError(e),
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_sessions)
+        .service(revoke_sessions)
+        .service(revoke_session);
+}
@@ -0,0 +1,134 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use serde_json::json;
+
+use crate::common::counters::forget_counters;
+use crate::common::saved_searches::{self, SavedSearchDefinition};
+
+use crate::auth::AuthenticatedUser;
+use crate::model::{PageParameters, SavedSearchRequest};
+use crate::routes::errors::{not_found_or, ApiError};
+use crate::startup::AppState;
+
+#[get("/streams")]
+pub async fn list_streams(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let streams = saved_searches::list_saved_searches(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(streams))
+}
+
+#[post("/streams")]
+pub async fn new_stream(
+    request: web::Json<SavedSearchRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let stream_id =
+        saved_searches::create_saved_search(connection, user.id, &request.into_inner().into())
+            .await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::Created().json(json!({ "id": stream_id })))
+}
+
+#[get("/streams/{id}")]
+pub async fn get_stream(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    let stream = saved_searches::get_saved_search(connection, user.id, id)
+        .await
+        .map_err(|e| not_found_or(e, "stream", id))?;
+
+    Ok(HttpResponse::Ok().json(stream))
+}
+
+#[put("/streams/{id}")]
+pub async fn update_stream(
+    id: web::Path<i32>,
+    request: web::Json<SavedSearchRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    saved_searches::update_saved_search(
+        connection,
+        user.id,
+        id.into_inner(),
+        &request.into_inner().into(),
+    )
+    .await?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/streams/{id}")]
+pub async fn delete_stream(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    saved_searches::delete_saved_search(connection, user.id, id)
+        .await
+        .map_err(|e| not_found_or(e, "stream", id))?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/streams/{id}/items")]
+pub async fn get_stream_items(
+    id: web::Path<i32>,
+    page: web::Query<PageParameters>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    let items =
+        saved_searches::get_stream_items(connection, user.id, id, page.get_page(), page.get_size())
+            .await
+            .map_err(|e| not_found_or(e, "stream", id))?;
+
+    Ok(HttpResponse::Ok().json(items))
+}
+
+impl From<SavedSearchRequest> for SavedSearchDefinition {
+    fn from(request: SavedSearchRequest) -> Self {
+        SavedSearchDefinition {
+            name: request.name,
+            terms: request.terms,
+            channel_ids: request.channel_ids,
+            folder_ids: request.folder_ids,
+            tag_ids: request.tag_ids,
+            read: request.read,
+            starred: request.starred,
+            window_days: request.window_days,
+        }
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_streams)
+        .service(new_stream)
+        .service(get_stream)
+        .service(update_stream)
+        .service(delete_stream)
+        .service(get_stream_items);
+}
@@ -0,0 +1,140 @@
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use serde_json::json;
+
+use crate::common::counters::forget_counters;
+use crate::common::tags;
+use crate::common::DbError;
+
+use crate::auth::AuthenticatedUser;
+use crate::model::{IdListParameter, TagRequest};
+use crate::routes::errors::{not_found_or, ApiError};
+use crate::startup::AppState;
+
+/// Maximum number of characters of a tag name
+const MAX_NAME_LENGTH: usize = 128;
+
+#[get("/tags")]
+pub async fn list_tags(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let tags = tags::list_tags(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(tags))
+}
+
+#[post("/tags")]
+pub async fn new_tag(
+    request: web::Json<TagRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let name = tag_name(&request)?;
+
+    let tag_id = tags::create_tag(connection, user.id, name).await?;
+
+    Ok(HttpResponse::Created().json(json!({ "id": tag_id })))
+}
+
+#[patch("/tag/{id}")]
+pub async fn rename_tag(
+    id: web::Path<i32>,
+    request: web::Json<TagRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+    let name = tag_name(&request)?;
+
+    tags::rename_tag(connection, user.id, id, name)
+        .await
+        .map_err(|e| match e {
+            DbError::Database(ref error) if error.is_unique_violation() => {
+                ApiError::Conflict(format!("A tag named {} already exists", name))
+            }
+            _ => not_found_or(e, "tag", id),
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/tag/{id}")]
+pub async fn delete_tag(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    tags::delete_tag(connection, user.id, id)
+        .await
+        .map_err(|e| not_found_or(e, "tag", id))?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/tag/{id}/attach")]
+pub async fn attach_tag(
+    id: web::Path<i32>,
+    ids: web::Json<IdListParameter>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    tags::attach_tag(connection, user.id, id, ids.into_inner().ids)
+        .await
+        .map_err(|e| not_found_or(e, "tag", id))?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[post("/tag/{id}/detach")]
+pub async fn detach_tag(
+    id: web::Path<i32>,
+    ids: web::Json<IdListParameter>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    tags::detach_tag(connection, user.id, id, ids.into_inner().ids)
+        .await
+        .map_err(|e| not_found_or(e, "tag", id))?;
+    forget_counters(&app_state.redis, &[user.id]).await;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Return the trimmed name of the requested tag, if it is neither empty nor too long
+fn tag_name(request: &TagRequest) -> Result<&str, ApiError> {
+    let name = request.name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(ApiError::InvalidRequest(format!(
+            "The name of a tag must have between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+
+    Ok(name)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_tags)
+        .service(new_tag)
+        .service(rename_tag)
+        .service(delete_tag)
+        .service(attach_tag)
+        .service(detach_tag);
+}
@@ -0,0 +1,92 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::Utc;
+use serde_json::json;
+
+use crate::common::personal_tokens;
+use crate::common::DbError::RowNotFound;
+
+use crate::auth::AuthenticatedUser;
+use crate::model::PersonalTokenRequest;
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[get("/tokens")]
+pub async fn list_tokens(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let tokens = personal_tokens::list_tokens(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+#[post("/tokens")]
+pub async fn new_token(
+    request: web::Json<PersonalTokenRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(ApiError::InvalidRequest(String::from(
+            "The name of the token cannot be empty",
+        )));
+    }
+
+    if request.scopes.is_empty() {
+        return Err(ApiError::InvalidRequest(String::from(
+            "The token needs at least one scope",
+        )));
+    }
+
+    if request
+        .expiration_timestamp
+        .is_some_and(|expiration| expiration <= Utc::now())
+    {
+        return Err(ApiError::InvalidRequest(String::from(
+            "The expiration date of the token must be in the future",
+        )));
+    }
+
+    let mut scopes = request.scopes.clone();
+    scopes.dedup();
+    let (id, token) = personal_tokens::create_token(
+        connection,
+        user.id,
+        name,
+        &scopes,
+        request.expiration_timestamp,
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(json!({ "id": id, "token": token })))
+}
+
+#[delete("/token/{id}")]
+pub async fn revoke_token(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    personal_tokens::revoke_token(connection, user.id, id)
+        .await
+        .map_err(|e| match e {
+            RowNotFound => ApiError::NotFound(String::from("token"), id),
+            _ => ApiError::DatabaseError(e),
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_tokens)
+        .service(new_token)
+        .service(revoke_token);
+}
@@ -0,0 +1,118 @@
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use serde_json::json;
+
+use crate::common::errors::TwoFactorError;
+use crate::common::DbError::RowNotFound;
+use crate::common::{two_factor, users};
+
+use crate::auth::AuthenticatedUser;
+use crate::errors::AuthenticationError;
+use crate::model::{TwoFactorCodeRequest, TwoFactorSettingsRequest};
+use crate::routes::errors::ApiError;
+use crate::startup::AppState;
+
+#[get("/user/2fa")]
+pub async fn get_two_factor(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let status = two_factor::get_status(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[post("/user/2fa")]
+pub async fn enroll(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let enrollment = two_factor::enroll(connection, user.id, &user.login).await?;
+
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+#[post("/user/2fa/confirm")]
+pub async fn confirm(
+    request: web::Json<TwoFactorCodeRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let recovery_codes = two_factor::confirm(connection, user.id, &request.code).await?;
+    // The credentials cached for HTTP Basic authentication are refused from now on
+    users::bump_token_version(connection, &app_state.redis, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "recovery_codes": recovery_codes })))
+}
+
+#[patch("/user/2fa")]
+pub async fn update_settings(
+    request: web::Json<TwoFactorSettingsRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    two_factor::set_allow_basic_auth(connection, user.id, request.allow_basic_auth).await?;
+    if !request.allow_basic_auth {
+        users::bump_token_version(connection, &app_state.redis, user.id).await?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/user/2fa")]
+pub async fn disable(
+    request: web::Json<TwoFactorCodeRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    if !two_factor::verify_code(connection, user.id, &request.code).await? {
+        return Err(TwoFactorError::InvalidCode.into());
+    }
+    two_factor::disable(connection, user.id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Reset the two-factor authentication of a user who lost both their device and their recovery codes
+#[delete("/user/{user_id}/2fa")]
+pub async fn reset(
+    user_id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let user_id = user_id.into_inner();
+
+    if !user.is_admin() {
+        return Err(ApiError::AuthenticationError(
+            AuthenticationError::Forbidden("You need to be an administrator".to_owned()),
+        ));
+    }
+
+    two_factor::disable(connection, user_id)
+        .await
+        .map_err(|e| match e {
+            RowNotFound => ApiError::NotFound(String::from("two-factor authentication"), user_id),
+            _ => ApiError::DatabaseError(e),
+        })?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_two_factor)
+        .service(enroll)
+        .service(confirm)
+        .service(update_settings)
+        .service(disable)
+        .service(reset);
+}
@@ -1,7 +1,11 @@
 use std::env;
 
+use crate::common::errors::UserError;
+use crate::common::fever;
+use crate::common::invitations;
 use crate::common::password::verify_password;
-use crate::common::DbError::RowNotFound;
+use crate::common::password_policy::check_password;
+use crate::common::DbError::{self, RowNotFound};
 use actix_web::{delete, get, patch, post, web, HttpResponse};
 use secrecy::{ExposeSecret, Secret};
 use serde_json::json;
@@ -13,8 +17,9 @@ use crate::common::users::{self, get_user_by_id};
 use crate::auth::AuthenticatedUser;
 use crate::errors::AuthenticationError;
 use crate::model::{
-    NewUserRequest, PageParameters, ResetPasswordRequest, ResetPasswordTokenRequest,
-    UpdateOtherPasswordRequest, UpdatePasswordRequest, UpdateUserRequest,
+    FeverPasswordRequest, NewUserRequest, PageParameters, ResetPasswordRequest,
+    ResetPasswordTokenRequest, UpdateAccountRequest, UpdateOtherPasswordRequest,
+    UpdatePasswordRequest, UpdateUserRequest, UsersFilterParameters,
 };
 use crate::routes::errors::ApiError;
 use crate::startup::AppState;
@@ -44,6 +49,7 @@ async fn new_user(
         if request.password.expose_secret() != request.confirm_password.expose_secret() {
             return Err(ApiError::PasswordMismatch);
         }
+        check_password(&request.password, &request.username).await?;
 
         let user = users::create_user(
             redis,
@@ -55,6 +61,31 @@ async fn new_user(
         )
         .await?;
 
+        Ok(HttpResponse::Created().json(json!({"id": user.id})))
+    } else if let Some(invitation) = &request.invitation {
+        debug!("Recording new invited user {:?}", request);
+
+        if request.password.expose_secret() != request.confirm_password.expose_secret() {
+            return Err(ApiError::PasswordMismatch);
+        }
+        check_password(&request.password, &request.username).await?;
+
+        // The role is the one of the invitation
+        let Some(user) = invitations::register(
+            connection,
+            redis,
+            invitation,
+            &request.username,
+            &request.password,
+            &request.email,
+        )
+        .await?
+        else {
+            return Err(ApiError::InvalidRequest(String::from(
+                "Invalid or expired invitation",
+            )));
+        };
+
         Ok(HttpResponse::Created().json(json!({"id": user.id})))
     } else {
         debug!("User creation attempt while it's disabled or creator is not admin");
@@ -66,16 +97,26 @@ async fn new_user(
 async fn list_users(
     app_state: web::Data<AppState>,
     page: web::Query<PageParameters>,
+    filters: web::Query<UsersFilterParameters>,
     user: AuthenticatedUser,
 ) -> Result<HttpResponse, ApiError> {
     let connection = &app_state.db;
 
     if user.is_admin() {
-        Ok(HttpResponse::Ok()
-            .json(users::list_users(connection, page.get_page(), page.get_size()).await?))
+        Ok(HttpResponse::Ok().json(
+            users::list_users(
+                connection,
+                filters.role.as_ref(),
+                filters.disabled,
+                filters.search.as_deref(),
+                page.get_page(),
+                page.get_size(),
+            )
+            .await?,
+        ))
     } else {
         Err(ApiError::AuthenticationError(
-            AuthenticationError::Forbidden("no".into()),
+            AuthenticationError::Forbidden("You need to be an administrator".to_owned()),
         ))
     }
 }
@@ -92,9 +133,13 @@ async fn update_password(
     if request.new_password.expose_secret() != request.confirm_password.expose_secret() {
         return Err(ApiError::PasswordMismatch);
     }
+    check_password(&request.new_password, &user.login).await?;
 
     if let Ok(Some(user)) = get_user_by_id(connection, user.id).await {
-        if !verify_password(&user.password, &request.current_password) {
+        if !verify_password(&user.password, &request.current_password)
+            .await
+            .is_valid()
+        {
             return Err(ApiError::PasswordMismatch);
         }
     } else {
@@ -134,6 +179,7 @@ async fn reset_password(
     if request.new_password.expose_secret() != request.confirm_password.expose_secret() {
         return Err(ApiError::PasswordMismatch);
     }
+    check_password(&request.new_password, &request.username).await?;
 
     users::reset_password(
         connection,
@@ -147,6 +193,28 @@ async fn reset_password(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[patch("/user/fever-password")]
+async fn update_fever_password(
+    app_state: web::Data<AppState>,
+    request: web::Json<FeverPasswordRequest>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    match fever::set_fever_password(connection, user.id, &user.login, request.password.as_ref())
+        .await
+    {
+        Err(DbError::Database(e)) if e.is_unique_violation() => {
+            return Err(ApiError::Conflict(String::from(
+                "This Fever password gives the API key of another user, choose another one",
+            )))
+        }
+        result => result?,
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[patch("/user/{user_id}/update-password")]
 async fn update_other_password(
     app_state: web::Data<AppState>,
@@ -168,6 +236,11 @@ async fn update_other_password(
         return Err(ApiError::PasswordMismatch);
     }
 
+    let other = get_user_by_id(connection, user_id)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(String::from("user"), user_id))?;
+    check_password(&request.new_password, &other.username).await?;
+
     if let Err(e) = users::update_user_password(connection, user_id, &request.new_password).await {
         return match e {
             RowNotFound => Err(ApiError::NotFound(String::from("user"), user_id)),
@@ -194,6 +267,48 @@ async fn update_user(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[patch("/user/{user_id}")]
+async fn update_account(
+    app_state: web::Data<AppState>,
+    user_id: web::Path<i32>,
+    request: web::Json<UpdateAccountRequest>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let user_id = user_id.into_inner();
+
+    if !user.is_admin() {
+        return Err(ApiError::AuthenticationError(
+            AuthenticationError::Forbidden("You need to be an administrator".to_owned()),
+        ));
+    }
+
+    // An admin must not lock themselves out
+    if user.id == user_id
+        && (request.disabled == Some(true)
+            || request
+                .role
+                .as_ref()
+                .is_some_and(|role| *role != UserRole::Admin))
+    {
+        return Err(UserError::OwnAccount.into());
+    }
+
+    let revoked = users::update_account(
+        connection,
+        user_id,
+        request.username.as_deref(),
+        request.role.as_ref(),
+        request.disabled,
+    )
+    .await?;
+    if revoked {
+        users::forget_token_version(&app_state.redis, user_id).await?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[get("/user/confirm-email/{token}")]
 async fn confirm_email(
     app_state: web::Data<AppState>,
@@ -230,10 +345,13 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(new_user)
         .service(list_users)
         .service(update_password)
+        .service(update_fever_password)
         .service(update_other_password)
         .service(reset_password_token)
         .service(update_user)
         .service(confirm_email)
         .service(delete_user)
-        .service(reset_password);
+        .service(reset_password)
+        // Last, as it would match the other routes under /user
+        .service(update_account);
 }
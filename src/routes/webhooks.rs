@@ -0,0 +1,109 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use secrecy::ExposeSecret;
+use serde_json::json;
+
+use crate::common::{channels, webhooks};
+
+use crate::auth::AuthenticatedUser;
+use crate::model::WebhookRequest;
+use crate::routes::errors::{not_found_or, ApiError};
+use crate::startup::AppState;
+
+/// Minimal length of the secret used to sign the payloads
+const MIN_SECRET_LENGTH: usize = 16;
+
+#[get("/webhooks")]
+pub async fn list_webhooks(
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    let webhooks = webhooks::list_webhooks(connection, user.id).await?;
+
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+#[post("/webhooks")]
+pub async fn new_webhook(
+    request: web::Json<WebhookRequest>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+
+    webhooks::resolve_target(&request.url, webhooks::allow_private_addresses())
+        .await
+        .map_err(|e| ApiError::InvalidRequest(e.to_string()))?;
+
+    let secret = request.secret.expose_secret();
+    if secret.chars().count() < MIN_SECRET_LENGTH {
+        return Err(ApiError::InvalidRequest(format!(
+            "The webhook secret must be at least {} characters long",
+            MIN_SECRET_LENGTH
+        )));
+    }
+
+    if let Some(channel_id) = request.channel_id {
+        channels::select_by_id_and_user_id(connection, channel_id, user.id)
+            .await?
+            .ok_or_else(|| ApiError::NotFound(String::from("channel"), channel_id))?;
+    }
+
+    let keyword = request
+        .keyword
+        .as_deref()
+        .map(str::trim)
+        .filter(|keyword| !keyword.is_empty());
+
+    let webhook_id = webhooks::create_webhook(
+        connection,
+        user.id,
+        &request.url,
+        secret,
+        request.channel_id,
+        keyword,
+    )
+    .await?;
+
+    Ok(HttpResponse::Created().json(json!({ "id": webhook_id })))
+}
+
+#[delete("/webhook/{id}")]
+pub async fn delete_webhook(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    webhooks::delete_webhook(connection, user.id, id)
+        .await
+        .map_err(|e| not_found_or(e, "webhook", id))?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/webhook/{id}/deliveries")]
+pub async fn list_deliveries(
+    id: web::Path<i32>,
+    app_state: web::Data<AppState>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, ApiError> {
+    let connection = &app_state.db;
+    let id = id.into_inner();
+
+    let deliveries = webhooks::list_deliveries(connection, user.id, id)
+        .await
+        .map_err(|e| not_found_or(e, "webhook", id))?;
+
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_webhooks)
+        .service(new_webhook)
+        .service(delete_webhook)
+        .service(list_deliveries);
+}
@@ -1,8 +1,13 @@
 use crate::common::channels::{
-    disable_channels, fail_channel, get_all_enabled_channels, get_last_update, update_last_fetched,
+    disable_channels, fail_channel, get_all_enabled_channels, get_last_update,
+    get_user_ids_of_channel, update_last_fetched, update_metadata,
 };
+use crate::common::counters::forget_counters;
+use crate::common::events::{self, Event};
 use crate::common::items::{insert_items, insert_items_delta_for_all_registered_users};
 use crate::common::model::{Channel, NewItem};
+use crate::common::rss;
+use crate::common::webhooks::{self, WebhookItem};
 use crate::common::DbError;
 use anyhow::Context;
 use chrono::{DateTime, Days, Utc};
@@ -15,7 +20,7 @@ use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_tracing::TracingMiddleware;
 use sqlx::PgPool;
 use std::error::Error;
-use tracing::{info, instrument, Instrument};
+use tracing::{error, info, instrument, Instrument};
 use uuid::Uuid;
 
 static CLIENT: Lazy<ClientWithMiddleware> = Lazy::new(|| {
@@ -78,13 +83,13 @@ pub async fn process(connection: &PgPool, redis: &RedisPool) -> Result<(), anyho
     Ok(())
 }
 
-#[tracing::instrument(skip(connection, redis))]
+#[tracing::instrument(skip(connection, redis_pool))]
 pub async fn update_channel(
     connection: &PgPool,
-    redis: &RedisPool,
+    redis_pool: &RedisPool,
     channel: &Channel,
 ) -> Result<(), FetchError> {
-    let mut redis = redis.get().await?;
+    let mut redis = redis_pool.get().await?;
 
     let (key, value, response) = acquire_lock(&mut redis, channel.id).await;
     if response?.is_none() {
@@ -100,11 +105,26 @@ pub async fn update_channel(
     let feed = match get_and_parse_feed(&channel.url).await {
         Ok(feed) => feed,
         Err(error) => {
-            fail_channel(connection, channel.id, &error.to_string()).await?;
+            let reason = error.to_string();
+            fail_channel(connection, channel.id, &reason).await?;
+
+            let event = Event::ChannelError {
+                channel_id: channel.id,
+                reason,
+            };
+            let user_ids = get_user_ids_of_channel(connection, channel.id).await?;
+            events::notify(redis_pool, &user_ids, event).await;
+
             return Err(error);
         }
     };
 
+    let site_url = rss::site_url(&feed);
+    let description = rss::description(&feed);
+    if site_url != channel.site_url || description != channel.description {
+        update_metadata(connection, channel.id, site_url, description).await?;
+    }
+
     let now = Utc::now();
     let last_update = get_last_update(connection, &channel.id)
         .await?
@@ -118,10 +138,39 @@ pub async fn update_channel(
         .filter(|item| item.publish_timestamp.unwrap_or(last_update) >= last_update)
         .collect::<Vec<NewItem>>();
 
-    insert_items(connection, &new_items).await?;
+    let item_ids = insert_items(connection, &new_items).await?;
     insert_items_delta_for_all_registered_users(connection, channel.id, &now).await?;
     update_last_fetched(connection, channel.id, &now).await?;
 
+    let user_ids = get_user_ids_of_channel(connection, channel.id).await?;
+    forget_counters(redis_pool, &user_ids).await;
+    if !item_ids.is_empty() {
+        let webhook_items = item_ids
+            .iter()
+            .zip(new_items)
+            .map(|(id, item)| WebhookItem {
+                id: *id,
+                title: item.title,
+                url: item.url,
+                content: item.content,
+                publish_timestamp: item.publish_timestamp,
+            })
+            .collect::<Vec<_>>();
+        // The items are saved anyway, the webhooks must not fail the fetch
+        if let Err(e) = webhooks::notify_new_items(connection, channel.id, &webhook_items).await {
+            error!(
+                "Could not notify the webhooks of channel {}: {}",
+                channel.id, e
+            );
+        }
+
+        let event = Event::NewItems {
+            channel_id: channel.id,
+            item_ids,
+        };
+        events::notify(redis_pool, &user_ids, event).await;
+    }
+
     release_lock(&mut redis, &key, &value).await?;
 
     Ok(())
@@ -174,6 +223,7 @@ fn item_from_rss_entry(entry: Entry, channel_id: i32, timestamp: &DateTime<Utc>)
     let guid = Some(entry.id);
     let url = entry.links.first().map(|x| String::from(&x.href[..]));
     let content = entry.summary.map(|x| x.content);
+    let author = entry.authors.into_iter().next().map(|x| x.name);
     let publish_timestamp = entry.published.or(Some(*timestamp));
 
     NewItem {
@@ -181,6 +231,7 @@ fn item_from_rss_entry(entry: Entry, channel_id: i32, timestamp: &DateTime<Utc>)
         title,
         url,
         content,
+        author,
         fetch_timestamp: *timestamp,
         publish_timestamp,
         channel_id,
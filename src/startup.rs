@@ -34,6 +34,26 @@ pub async fn startup(database: DbPool, redis: Pool, listener: TcpListener) -> st
                     .wrap(Governor::new(&governor_conf))
                     .configure(routes::configure),
             )
+            .service(
+                web::scope("/fever")
+                    .wrap(Governor::new(&governor_conf))
+                    .configure(routes::fever::configure),
+            )
+            .service(
+                web::scope("/greader")
+                    .wrap(Governor::new(&governor_conf))
+                    .configure(routes::greader::configure),
+            )
+            .service(
+                web::scope("/index.php/apps/news/api/v1-3")
+                    .wrap(Governor::new(&governor_conf))
+                    .configure(routes::nextcloud::configure),
+            )
+            .service(
+                web::scope("/feeds")
+                    .wrap(Governor::new(&governor_conf))
+                    .configure(routes::output_feeds::configure_public),
+            )
             .service(actix_files::Files::new("/", "./static/").index_file("index.html"))
     })
     .listen(listener)?